@@ -0,0 +1,301 @@
+#![allow(clippy::redundant_field_names, clippy::len_zero)]
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use std::io;
+use std::sync::Arc;
+
+use futures_io::{AsyncRead, AsyncWrite};
+
+use connection::*;
+use http::*;
+use parser::*;
+use router::*;
+use alloc::vec::Vec;
+
+/// Serve HTTP on an async stream until either side closes it. Works with
+/// any runtime whose streams implement the `futures-io` traits; see
+/// `TokioIo` for tokio. Timeouts are left to the runtime.
+pub fn serve_connection_async<S>(stream: S, router: Arc<HttpRouter>, config: HttpConnectionConfig) -> ServeConnection<S>
+	where S: AsyncRead + AsyncWrite + Unpin
+{
+	ServeConnection {
+		stream: stream,
+		router: router,
+		conn: HttpConnection::with_config(config),
+		buf: vec![0; 1024],
+		out: vec![0; 1024],
+		out_pos: 0,
+		out_len: 0,
+		closing: false
+	}
+}
+
+/// Future returned by `serve_connection_async`.
+pub struct ServeConnection<S> {
+	stream: S,
+	router: Arc<HttpRouter>,
+	conn: HttpConnection,
+	buf: Vec<u8>,
+	out: Vec<u8>,
+	out_pos: usize,
+	out_len: usize,
+	closing: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Future for ServeConnection<S> {
+	type Output = io::Result<()>;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+
+		loop {
+			// what was taken from the connection is written before its
+			// events, so routes hear of responses that went out
+			if this.out_pos < this.out_len {
+				match Pin::new(&mut this.stream).poll_write(cx, &this.out[this.out_pos..this.out_len]) {
+					Poll::Ready(Ok(0)) => { return Poll::Ready(Err(io::ErrorKind::WriteZero.into())); }
+					Poll::Ready(Ok(n)) => this.out_pos += n,
+					Poll::Ready(Err(e)) => { return Poll::Ready(Err(e)); }
+					Poll::Pending => { return Poll::Pending; }
+				}
+				continue;
+			}
+
+			while let Some(event) = this.conn.poll_routed(&this.router) {
+				if let HttpConnectionEvent::Close = event {
+					this.closing = true;
+				}
+			}
+
+			if this.conn.wants_transmit() {
+				this.out_len = this.conn.poll_transmit(&mut this.out);
+				this.out_pos = 0;
+				continue;
+			}
+
+			if this.closing {
+				// flushes whatever is still buffered
+				return Pin::new(&mut this.stream).poll_close(cx);
+			}
+
+			match Pin::new(&mut this.stream).poll_flush(cx) {
+				Poll::Ready(Ok(())) => (),
+				Poll::Ready(Err(e)) => { return Poll::Ready(Err(e)); }
+				Poll::Pending => { return Poll::Pending; }
+			}
+
+			match Pin::new(&mut this.stream).poll_read(cx, &mut this.buf) {
+				Poll::Ready(Ok(0)) => this.conn.peer_closed(),
+				Poll::Ready(Ok(n)) => this.conn.received(&this.buf[..n]),
+				Poll::Ready(Err(e)) => { return Poll::Ready(Err(e)); }
+				Poll::Pending => { return Poll::Pending; }
+			}
+		}
+	}
+}
+
+/// Send `req` over an async stream and read back the response, skipping
+/// interim 1xx responses. The stream stays with the caller, so it can be
+/// reused if the response allows it. Only this one response is read: the
+/// server isn't expected to send anything after it, and whatever it does
+/// send in the same read is dropped.
+pub fn send_request_async<'a, S>(stream: &'a mut S, req: &HttpRequestMessage) -> SendRequest<'a, S>
+	where S: AsyncRead + AsyncWrite + Unpin
+{
+	SendRequest {
+		stream: stream,
+		out: req.to_bytes(),
+		out_pos: 0,
+		method: req.method,
+		parser: Some(HttpParser::new_response_to(req.method)),
+		buf: vec![0; 1024]
+	}
+}
+
+/// Future returned by `send_request_async`.
+pub struct SendRequest<'a, S: 'a> {
+	stream: &'a mut S,
+	out: Vec<u8>,
+	out_pos: usize,
+	method: HttpMethod,
+	parser: Option<HttpParser>,
+	buf: Vec<u8>,
+}
+
+impl<'a, S: AsyncRead + AsyncWrite + Unpin> Future for SendRequest<'a, S> {
+	type Output = io::Result<HttpResponseMessage>;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<HttpResponseMessage>> {
+		let this = self.get_mut();
+
+		while this.out_pos < this.out.len() {
+			match Pin::new(&mut *this.stream).poll_write(cx, &this.out[this.out_pos..]) {
+				Poll::Ready(Ok(0)) => { return Poll::Ready(Err(io::ErrorKind::WriteZero.into())); }
+				Poll::Ready(Ok(n)) => this.out_pos += n,
+				Poll::Ready(Err(e)) => { return Poll::Ready(Err(e)); }
+				Poll::Pending => { return Poll::Pending; }
+			}
+		}
+
+		match Pin::new(&mut *this.stream).poll_flush(cx) {
+			Poll::Ready(Ok(())) => (),
+			Poll::Ready(Err(e)) => { return Poll::Ready(Err(e)); }
+			Poll::Pending => { return Poll::Pending; }
+		}
+
+		loop {
+			let n = match Pin::new(&mut *this.stream).poll_read(cx, &mut this.buf) {
+				Poll::Ready(Ok(n)) => n,
+				Poll::Ready(Err(e)) => { return Poll::Ready(Err(e)); }
+				Poll::Pending => { return Poll::Pending; }
+			};
+
+			if n == 0 {
+				let parser = this.parser.take().unwrap();
+				if !parser.are_headers_parsed() {
+					return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+				}
+				// without a length, the body runs until the connection closes
+				return Poll::Ready(Ok(parser.into_response().unwrap()));
+			}
+
+			let mut data = &this.buf[..n];
+			while data.len() > 0 {
+				let used = match this.parser.as_mut().unwrap().parse_partial(data) {
+					Ok((used, _)) => used,
+					Err(e) => { return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))); }
+				};
+				data = &data[used..];
+				if !this.parser.as_ref().unwrap().is_complete() {
+					break;
+				}
+
+				let resp = this.parser.take().unwrap().into_response().unwrap();
+				if resp.response_code >= 100 && resp.response_code < 200 && resp.response_code != 101 {
+					this.parser = Some(HttpParser::new_response_to(this.method));
+					continue;
+				}
+				return Poll::Ready(Ok(resp));
+			}
+		}
+	}
+}
+
+#[cfg(feature = "async-tokio")]
+pub use self::tokio_adapter::*;
+
+#[cfg(feature = "async-tokio")]
+mod tokio_adapter {
+	use core::pin::Pin;
+	use core::task::{Context, Poll};
+	use std::io;
+
+	use futures_io;
+	use tokio;
+
+	/// Wraps a tokio stream, like `tokio::net::TcpStream`, so it can be used
+	/// with `serve_connection_async` and `send_request_async`.
+	pub struct TokioIo<T>(pub T);
+
+	impl<T: tokio::io::AsyncRead + Unpin> futures_io::AsyncRead for TokioIo<T> {
+		fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+			let mut read_buf = tokio::io::ReadBuf::new(buf);
+			match Pin::new(&mut self.get_mut().0).poll_read(cx, &mut read_buf) {
+				Poll::Ready(Ok(())) => Poll::Ready(Ok(read_buf.filled().len())),
+				Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+				Poll::Pending => Poll::Pending
+			}
+		}
+	}
+
+	impl<T: tokio::io::AsyncWrite + Unpin> futures_io::AsyncWrite for TokioIo<T> {
+		fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+			Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+			Pin::new(&mut self.get_mut().0).poll_flush(cx)
+		}
+
+		fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+			Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+		}
+	}
+}
+
+#[cfg(all(test, feature = "async-tokio"))]
+mod tests {
+	use super::*;
+
+	use alloc::string::ToString;
+	use std::sync::Arc;
+	use tokio;
+
+	fn router() -> Arc<HttpRouter> {
+		let mut router = HttpRouter::new();
+		router.add_route(HttpRouteDynamicUrl::new(DynamicUrl::parse_str("/test/:id").unwrap(), HttpMethod::Get, |_, vars| {
+			HttpResponseMessage::text_utf8(vars.get("id").unwrap())
+		}));
+		Arc::new(router)
+	}
+
+	#[test]
+	pub fn test_async_keep_alive_connection() {
+		let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+		let (client, server) = tokio::io::duplex(64);
+
+		let config = HttpConnectionConfig { keep_alive: true, .. HttpConnectionConfig::default() };
+		let served = rt.spawn(serve_connection_async(TokioIo(server), router(), config));
+
+		let mut client = TokioIo(client);
+		for id in &["1", "22", "333"] {
+			let req = HttpRequestMessage::new_get(&format!("/test/{}", id), "localhost");
+			let resp = rt.block_on(send_request_async(&mut client, &req)).unwrap();
+			assert_eq!(resp.response_code, 200);
+			assert_eq!(resp.body, id.as_bytes());
+		}
+
+		let mut req = HttpRequestMessage::new_get("/test/last", "localhost");
+		req.headers.insert("Connection".to_string(), "close".to_string());
+		let resp = rt.block_on(send_request_async(&mut client, &req)).unwrap();
+		assert_eq!(resp.body, b"last");
+		assert!(!resp.keep_alive());
+
+		rt.block_on(served).unwrap().unwrap();
+	}
+
+	#[test]
+	pub fn test_async_head_response() {
+		let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+		let (client, mut server) = tokio::io::duplex(256);
+
+		// the length of the body a GET would have, but no body
+		rt.block_on(tokio::io::AsyncWriteExt::write_all(&mut server, b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n")).unwrap();
+
+		let mut client = TokioIo(client);
+		let mut req = HttpRequestMessage::new_get("/test/1", "localhost");
+		req.method = HttpMethod::Head;
+		let resp = rt.block_on(send_request_async(&mut client, &req)).unwrap();
+		assert_eq!(resp.response_code, 200);
+		assert_eq!(resp.body.len(), 0);
+		drop(server);
+	}
+
+	#[test]
+	pub fn test_async_interim_responses() {
+		let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+		let (client, mut server) = tokio::io::duplex(256);
+
+		rt.block_on(tokio::io::AsyncWriteExt::write_all(&mut server, b"HTTP/1.1 100 Continue\r\n\r\n\
+HTTP/1.1 103 Early Hints\r\nLink: </style.css>\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi")).unwrap();
+
+		let mut client = TokioIo(client);
+		let req = HttpRequestMessage::new_get("/test/1", "localhost");
+		let resp = rt.block_on(send_request_async(&mut client, &req)).unwrap();
+		assert_eq!(resp.response_code, 200);
+		assert_eq!(resp.body, b"hi");
+		drop(server);
+	}
+}
@@ -0,0 +1,647 @@
+#![allow(deprecated, bare_trait_objects, clippy::redundant_field_names, clippy::new_without_default, clippy::len_zero, clippy::match_like_matches_macro)]
+
+use std::net::{TcpStream, ToSocketAddrs};
+use std::io;
+use std::io::{Read, Write};
+use std::sync::Mutex;
+#[cfg(feature = "tls")]
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use http::*;
+use parser::*;
+use url::*;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use alloc::boxed::Box;
+
+#[cfg(feature = "tls")]
+use core::convert::TryFrom;
+#[cfg(feature = "tls")]
+use rustls;
+#[cfg(feature = "tls")]
+use tls::*;
+
+#[derive(Debug)]
+pub enum HttpClientError {
+	Io(io::Error),
+	/// Only `http` URLs, and `https` with the `tls` feature, are supported.
+	UnsupportedScheme(String),
+	/// The host name didn't resolve to any address.
+	UnknownHost(String),
+	InvalidResponse(HttpParserError),
+	/// The server closed the connection before the response was complete.
+	UnexpectedEof,
+	ResponseTooLarge,
+	/// A redirect without a usable `Location` header.
+	InvalidRedirect(String),
+	TooManyRedirects,
+}
+
+impl From<io::Error> for HttpClientError {
+	fn from(e: io::Error) -> HttpClientError {
+		HttpClientError::Io(e)
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpClientConfig {
+	pub connect_timeout: Option<Duration>,
+	/// Longest wait for each read of the response.
+	pub read_timeout: Option<Duration>,
+	pub write_timeout: Option<Duration>,
+	/// Sent unless the request already has a `User-Agent`.
+	pub user_agent: String,
+	/// Largest accepted status line and headers, in bytes.
+	pub max_head_size: usize,
+	/// Largest accepted response body, in bytes.
+	pub max_response_size: usize,
+	/// Redirects followed before giving up. With 0, redirect responses are
+	/// returned as they are.
+	pub max_redirects: usize,
+	/// Idle keep-alive connections kept per host and port. With 0, every
+	/// request uses a new connection.
+	pub max_idle_per_host: usize,
+	/// TLS setup for `https` URLs. By default, servers are verified against
+	/// the Mozilla root certificates.
+	#[cfg(feature = "tls")]
+	pub tls: Option<Arc<rustls::ClientConfig>>,
+}
+
+impl Default for HttpClientConfig {
+	fn default() -> HttpClientConfig {
+		HttpClientConfig {
+			connect_timeout: Some(Duration::from_secs(10)),
+			read_timeout: Some(Duration::from_secs(30)),
+			write_timeout: Some(Duration::from_secs(30)),
+			user_agent: concat!("core_mini_http/", env!("CARGO_PKG_VERSION")).to_string(),
+			max_head_size: 8 * 1024,
+			max_response_size: 1024 * 1024,
+			max_redirects: 5,
+			max_idle_per_host: 2,
+			#[cfg(feature = "tls")]
+			tls: None
+		}
+	}
+}
+
+/// Blocking HTTP/1.1 client. Connections are kept alive and reused for
+/// later requests to the same host and port.
+pub struct HttpClient {
+	config: HttpClientConfig,
+	/// Idle connections by "scheme://host:port".
+	pool: Mutex<BTreeMap<String, Vec<Box<ClientStream>>>>,
+	/// Set up with the first `https` request; loading the root
+	/// certificates isn't free.
+	#[cfg(feature = "tls")]
+	tls: OnceLock<Arc<rustls::ClientConfig>>,
+}
+
+/// What a request is sent over: plain TCP, or TLS on top of it.
+trait ClientStream: Read + Write + Send {}
+
+impl<S: Read + Write + Send> ClientStream for S {}
+
+impl HttpClient {
+	pub fn new() -> HttpClient {
+		HttpClient::with_config(HttpClientConfig::default())
+	}
+
+	pub fn with_config(config: HttpClientConfig) -> HttpClient {
+		HttpClient {
+			#[cfg(feature = "tls")]
+			tls: OnceLock::new(),
+			config: config,
+			pool: Mutex::new(BTreeMap::new())
+		}
+	}
+
+	pub fn config(&self) -> &HttpClientConfig {
+		&self.config
+	}
+
+	pub fn get(&self, url: &Url) -> Result<HttpResponseMessage, HttpClientError> {
+		self.send(url, self.request(HttpMethod::Get, url))
+	}
+
+	pub fn post(&self, url: &Url, content_type: &str, body: &[u8]) -> Result<HttpResponseMessage, HttpClientError> {
+		let mut req = self.request(HttpMethod::Post, url);
+		req.headers.insert("Content-Type".to_string(), content_type.to_string());
+		req.body.extend_from_slice(body);
+		self.send(url, req)
+	}
+
+	/// Request for `url`, with the `Host` and `User-Agent` headers set.
+	pub fn request(&self, method: HttpMethod, url: &Url) -> HttpRequestMessage {
+		let mut req = HttpRequestMessage::empty();
+		req.method = method;
+		req.url = url.request_target();
+		req.headers.insert("Host".to_string(), host_header(url));
+		req.headers.insert("User-Agent".to_string(), self.config.user_agent.clone());
+		req
+	}
+
+	/// Send `req` to the server `url` points to, following redirects.
+	/// `Host`, `User-Agent` and `Content-Length` are filled in if missing.
+	pub fn send(&self, url: &Url, req: HttpRequestMessage) -> Result<HttpResponseMessage, HttpClientError> {
+		let mut url = url.clone();
+		let mut req = req;
+		let mut redirects = 0;
+
+		loop {
+			let resp = try!(self.send_once(&url, &mut req));
+
+			if self.config.max_redirects == 0 || !is_redirect(resp.response_code) {
+				return Ok(resp);
+			}
+			if redirects == self.config.max_redirects {
+				return Err(HttpClientError::TooManyRedirects);
+			}
+
+			let next = match resp.get_raw_header("Location") {
+				Some(location) => try!(url.join(location).map_err(|_| HttpClientError::InvalidRedirect(location.clone()))),
+				None => { return Err(HttpClientError::InvalidRedirect(String::new())); }
+			};
+			redirect_request(&mut req, resp.response_code, &url, &next);
+			url = next;
+			redirects += 1;
+		}
+	}
+
+	#[cfg(feature = "tls")]
+	fn tls_config(&self) -> Arc<rustls::ClientConfig> {
+		self.tls.get_or_init(|| self.config.tls.clone().unwrap_or_else(tls_client_config)).clone()
+	}
+
+	/// Number of idle connections waiting to be reused.
+	pub fn idle_connections(&self) -> usize {
+		self.pool.lock().unwrap().values().map(|c| c.len()).sum()
+	}
+
+	fn send_once(&self, url: &Url, req: &mut HttpRequestMessage) -> Result<HttpResponseMessage, HttpClientError> {
+		if req.get_raw_header("Host").is_none() {
+			req.headers.insert("Host".to_string(), host_header(url));
+		}
+		if req.get_raw_header("User-Agent").is_none() {
+			req.headers.insert("User-Agent".to_string(), self.config.user_agent.clone());
+		}
+		if req.get_raw_header("Content-Length").is_none() && (req.body.len() > 0 || req.method == HttpMethod::Post || req.method == HttpMethod::Put) {
+			req.headers.insert("Content-Length".to_string(), req.body.len().to_string());
+		}
+		if self.config.max_idle_per_host == 0 {
+			req.headers.insert("Connection".to_string(), "close".to_string());
+		}
+
+		let key = format!("{}://{}:{}", url.scheme, host_header(url), url.port_or_known_default().unwrap_or(80));
+		let bytes = req.to_bytes();
+
+		// a pooled connection may have been closed by the server in the
+		// meantime; if it fails before any response arrives, use a new one.
+		// Only requests that are safe to repeat are sent twice, unless the
+		// first one couldn't even be written.
+		if let Some(mut stream) = self.take_idle(&key) {
+			if stream.write_all(&bytes).is_ok() {
+				match try!(read_response(&mut stream, req.method, &self.config)) {
+					Some((resp, reusable)) => {
+						if reusable && req.keep_alive() {
+							self.put_idle(key, stream);
+						}
+						return Ok(resp);
+					}
+					None if !is_idempotent(req.method) => { return Err(HttpClientError::UnexpectedEof); }
+					None => ()
+				}
+			}
+		}
+
+		let mut stream = try!(self.connect(url));
+		try!(stream.write_all(&bytes));
+		match try!(read_response(&mut stream, req.method, &self.config)) {
+			Some((resp, reusable)) => {
+				if reusable && req.keep_alive() {
+					self.put_idle(key, stream);
+				}
+				Ok(resp)
+			}
+			None => Err(HttpClientError::UnexpectedEof)
+		}
+	}
+
+	fn take_idle(&self, key: &str) -> Option<Box<ClientStream>> {
+		self.pool.lock().unwrap().get_mut(key).and_then(|c| c.pop())
+	}
+
+	fn put_idle(&self, key: String, stream: Box<ClientStream>) {
+		let mut pool = self.pool.lock().unwrap();
+		let idle = pool.entry(key).or_default();
+		if idle.len() < self.config.max_idle_per_host {
+			idle.push(stream);
+		}
+	}
+
+	fn connect(&self, url: &Url) -> Result<Box<ClientStream>, HttpClientError> {
+		match url.scheme.as_str() {
+			"http" => Ok(Box::new(try!(self.connect_tcp(url)))),
+			#[cfg(feature = "tls")]
+			"https" => {
+				// IP addresses are checked against the certificate without brackets
+				let host = host_name(url);
+				let name = try!(rustls::ServerName::try_from(host.trim_matches(|c| c == '[' || c == ']')).map_err(|_| HttpClientError::UnknownHost(host.clone())));
+				let conn = try!(rustls::ClientConnection::new(self.tls_config(), name).map_err(io::Error::other));
+				Ok(Box::new(rustls::StreamOwned::new(conn, try!(self.connect_tcp(url)))))
+			}
+			_ => Err(HttpClientError::UnsupportedScheme(url.scheme.clone()))
+		}
+	}
+
+	fn connect_tcp(&self, url: &Url) -> Result<TcpStream, HttpClientError> {
+		let port = url.port_or_known_default().unwrap_or(80);
+		let addrs = try!(format!("{}:{}", host_name(url), port).to_socket_addrs());
+
+		let mut last_err = None;
+		for addr in addrs {
+			let stream = match self.config.connect_timeout {
+				Some(t) => TcpStream::connect_timeout(&addr, t),
+				None => TcpStream::connect(addr)
+			};
+			match stream {
+				Ok(stream) => {
+					try!(stream.set_read_timeout(self.config.read_timeout));
+					try!(stream.set_write_timeout(self.config.write_timeout));
+					return Ok(stream);
+				}
+				Err(e) => last_err = Some(e)
+			}
+		}
+
+		match last_err {
+			Some(e) => Err(HttpClientError::Io(e)),
+			None => Err(HttpClientError::UnknownHost(host_name(url)))
+		}
+	}
+}
+
+/// The host, IPv6 addresses in brackets. Empty if there's none.
+fn host_name(url: &Url) -> String {
+	url.host.as_ref().map(|host| host.to_string()).unwrap_or_default()
+}
+
+/// `Host` header value, with the port left out when it's the default.
+fn host_header(url: &Url) -> String {
+	match (url.scheme.as_str(), url.port) {
+		(_, None) | ("http", Some(80)) | ("https", Some(443)) => host_name(url),
+		(_, Some(port)) => format!("{}:{}", host_name(url), port)
+	}
+}
+
+fn is_redirect(response_code: u16) -> bool {
+	match response_code {
+		301 | 302 | 303 | 307 | 308 => true,
+		_ => false
+	}
+}
+
+/// Can the request be sent again when it's unknown whether the server got
+/// it? (RFC 7231, section 4.2.2)
+fn is_idempotent(method: HttpMethod) -> bool {
+	match method {
+		HttpMethod::Get | HttpMethod::Head | HttpMethod::Put | HttpMethod::Delete | HttpMethod::Options => true,
+		_ => false
+	}
+}
+
+/// Turn `req` into the request for the redirect from `from` to `to`.
+fn redirect_request(req: &mut HttpRequestMessage, response_code: u16, from: &Url, to: &Url) {
+	// 303 always means GET; for 301 and 302 every browser does the same with
+	// POST. 307 and 308 keep the method and body.
+	let to_get = match response_code {
+		303 => req.method != HttpMethod::Head,
+		301 | 302 => req.method == HttpMethod::Post,
+		_ => false
+	};
+	if to_get {
+		req.method = HttpMethod::Get;
+		req.body.clear();
+		remove_header(req, "Content-Length");
+		remove_header(req, "Content-Type");
+	}
+
+	req.url = to.request_target();
+	req.headers.insert("Host".to_string(), host_header(to));
+
+	// credentials are for the original host only
+	if from.host != to.host || from.port_or_known_default() != to.port_or_known_default() || from.scheme != to.scheme {
+		remove_header(req, "Authorization");
+		remove_header(req, "Cookie");
+		remove_header(req, "Proxy-Authorization");
+	}
+}
+
+/// Remove every `name` header, whatever its case.
+fn remove_header(req: &mut HttpRequestMessage, name: &str) {
+	req.headers.retain(|k, _| !k.eq_ignore_ascii_case(name));
+	req.repeated_headers.retain(|h| !h.0.eq_ignore_ascii_case(name));
+}
+
+/// Read one response to a `method` request, and whether the connection can
+/// carry another. Interim 1xx responses are skipped. `None` if the
+/// connection closed before any of the response arrived.
+fn read_response<R: Read>(stream: &mut R, method: HttpMethod, config: &HttpClientConfig) -> Result<Option<(HttpResponseMessage, bool)>, HttpClientError> {
+	let mut parser = HttpParser::new_response_to(method);
+	let mut buf = [0; 4096];
+	let mut received = false;
+	let mut head_bytes = 0;
+
+	loop {
+		let n = match stream.read(&mut buf) {
+			Ok(n) => n,
+			// a TLS peer that went away without saying so gives UnexpectedEof
+			Err(ref e) if !received && (e.kind() == io::ErrorKind::ConnectionReset || e.kind() == io::ErrorKind::ConnectionAborted || e.kind() == io::ErrorKind::UnexpectedEof) => 0,
+			Err(e) => { return Err(HttpClientError::Io(e)); }
+		};
+		if n == 0 {
+			if !received {
+				return Ok(None);
+			}
+			if parser.is_close_delimited() {
+				return Ok(Some((parser.into_response().unwrap(), false)));
+			}
+			return Err(HttpClientError::UnexpectedEof);
+		}
+		received = true;
+
+		let mut data = &buf[..n];
+		while data.len() > 0 {
+			let head_done = parser.are_headers_parsed();
+			let (used, _) = try!(parser.parse_partial(data).map_err(HttpClientError::InvalidResponse));
+			data = &data[used..];
+
+			if !head_done {
+				head_bytes += used;
+				if parser.are_headers_parsed() {
+					head_bytes -= parser.get_message().get_body().len();
+				}
+				if head_bytes > config.max_head_size {
+					return Err(HttpClientError::ResponseTooLarge);
+				}
+			}
+			if parser.get_message().get_body().len() > config.max_response_size {
+				return Err(HttpClientError::ResponseTooLarge);
+			}
+			if !parser.is_complete() {
+				break;
+			}
+
+			let resp = parser.into_response().unwrap();
+			if resp.response_code >= 100 && resp.response_code < 200 && resp.response_code != 101 {
+				parser = HttpParser::new_response_to(method);
+				head_bytes = 0;
+				continue;
+			}
+			// anything sent after the response means we're out of step
+			let reusable = resp.keep_alive() && data.len() == 0;
+			return Ok(Some((resp, reusable)));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use router::*;
+	use server::*;
+
+	use std::io::{Read, Write};
+	use std::net::{TcpListener, SocketAddr};
+	use std::thread;
+	use std::thread::JoinHandle;
+	use std::time::Duration;
+	use alloc::vec::Vec;
+	use alloc::string::String;
+
+	/// Answer one connection with `response`, returning what was received.
+	fn serve_once(response: &'static [u8]) -> (SocketAddr, JoinHandle<Vec<u8>>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let t = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let mut req = Vec::new();
+			let mut buf = [0; 1024];
+			while !String::from_utf8_lossy(&req).contains("\r\n\r\n") {
+				let n = stream.read(&mut buf).unwrap();
+				if n == 0 { break; }
+				req.extend_from_slice(&buf[..n]);
+			}
+			stream.write_all(response).unwrap();
+			req
+		});
+		(addr, t)
+	}
+
+	fn url(addr: SocketAddr, path: &str) -> Url {
+		Url::parse(&format!("http://{}{}", addr, path)).unwrap()
+	}
+
+	#[test]
+	pub fn test_client_content_length() {
+		let (addr, t) = serve_once(b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+		let resp = HttpClient::new().post(&url(addr, "/upload"), "text/plain", b"data").unwrap();
+		assert_eq!(resp.response_code, 200);
+		assert_eq!(resp.body, b"hello");
+
+		let req = String::from_utf8(t.join().unwrap()).unwrap();
+		assert!(req.starts_with("POST /upload HTTP/1.1\r\n"));
+		assert!(req.contains(&format!("Host: 127.0.0.1:{}\r\n", addr.port())));
+		assert!(req.contains("Content-Length: 4\r\n"));
+		assert!(req.contains("User-Agent: core_mini_http/"));
+		assert!(req.ends_with("\r\n\r\ndata"));
+	}
+
+	#[test]
+	pub fn test_client_chunked_and_close_delimited() {
+		let (addr, t) = serve_once(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nX-Trailer: 1\r\n\r\n");
+		let resp = HttpClient::new().get(&url(addr, "")).unwrap();
+		assert_eq!(resp.body, b"hello, world");
+		assert!(t.join().unwrap().starts_with(b"GET / HTTP/1.1\r\n"));
+
+		let (addr, _) = serve_once(b"HTTP/1.0 200 OK\r\n\r\nuntil the end");
+		let resp = HttpClient::new().get(&url(addr, "/")).unwrap();
+		assert_eq!(resp.body, b"until the end");
+
+		let (addr, _) = serve_once(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort");
+		match HttpClient::new().get(&url(addr, "/")) {
+			Err(HttpClientError::UnexpectedEof) => (),
+			r => panic!("unexpected result {:?}", r)
+		}
+	}
+
+	#[test]
+	pub fn test_client_response_too_large() {
+		let config = HttpClientConfig { max_head_size: 64, max_response_size: 8, .. HttpClientConfig::default() };
+
+		let (addr, _) = serve_once(b"HTTP/1.1 200 OK\r\nX-Padding: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\nContent-Length: 0\r\n\r\n");
+		match HttpClient::with_config(config.clone()).get(&url(addr, "/")) {
+			Err(HttpClientError::ResponseTooLarge) => (),
+			r => panic!("unexpected result {:?}", r)
+		}
+
+		let (addr, _) = serve_once(b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n123456789");
+		match HttpClient::with_config(config.clone()).get(&url(addr, "/")) {
+			Err(HttpClientError::ResponseTooLarge) => (),
+			r => panic!("unexpected result {:?}", r)
+		}
+
+		let (addr, _) = serve_once(b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\n12345678");
+		assert_eq!(HttpClient::with_config(config).get(&url(addr, "/")).unwrap().body, b"12345678");
+	}
+
+	#[test]
+	pub fn test_client_timeout() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+
+		let config = HttpClientConfig { read_timeout: Some(Duration::from_millis(100)), .. HttpClientConfig::default() };
+		match HttpClient::with_config(config).get(&url(addr, "/")) {
+			Err(HttpClientError::Io(_)) => (),
+			r => panic!("unexpected result {:?}", r)
+		}
+		drop(listener);
+
+		match HttpClient::new().get(&Url::parse("ftp://example.com/").unwrap()) {
+			Err(HttpClientError::UnsupportedScheme(_)) => (),
+			r => panic!("unexpected result {:?}", r)
+		}
+	}
+
+	fn redirect(response_code: u16, location: &str) -> HttpResponseMessage {
+		let mut resp = HttpResponseMessage::new_status(response_code, "Redirect");
+		resp.headers.insert("Location".to_string(), location.to_string());
+		resp
+	}
+
+	fn echo(req: &HttpRequestMessage) -> HttpResponseMessage {
+		HttpResponseMessage::text_utf8(&format!("{} {} {}", req.method.as_str(), req.url, String::from_utf8_lossy(&req.body)))
+	}
+
+	#[test]
+	pub fn test_client_redirects() {
+		let dynamic = |url: &str, method: HttpMethod, action: fn(&HttpRequestMessage) -> HttpResponseMessage| {
+			HttpRouteDynamicUrl::new(DynamicUrl::parse_str(url).unwrap(), method, move |req, _| action(req))
+		};
+		let server = HttpServerBuilder::new()
+			.bind("127.0.0.1:0")
+			.route(HttpRouteStaticUrl::new_get("/a/old", |_| redirect(301, "new")))
+			.route(dynamic("/a/new", HttpMethod::Get, echo))
+			.route(dynamic("/a/new", HttpMethod::Post, echo))
+			.route(dynamic("/form", HttpMethod::Post, |_| redirect(303, "/a/new")))
+			.route(dynamic("/temp", HttpMethod::Post, |_| redirect(307, "/a/new")))
+			.route(HttpRouteStaticUrl::new_get("/loop", |_| redirect(302, "/loop")))
+			.build()
+			.unwrap();
+		let addr = server.local_addr();
+		let handle = server.handle();
+		let t = thread::spawn(move || server.run().unwrap());
+
+		let client = HttpClient::new();
+		let resp = client.get(&url(addr, "/a/old")).unwrap();
+		assert_eq!(resp.body, b"GET /a/new ");
+
+		// 303 turns into a GET, 307 keeps the method and body
+		let resp = client.post(&url(addr, "/form"), "text/plain", b"data").unwrap();
+		assert_eq!(resp.body, b"GET /a/new ");
+		let resp = client.post(&url(addr, "/temp"), "text/plain", b"data").unwrap();
+		assert_eq!(resp.body, b"POST /a/new data");
+
+		match client.get(&url(addr, "/loop")) {
+			Err(HttpClientError::TooManyRedirects) => (),
+			r => panic!("unexpected result {:?}", r)
+		}
+
+		let config = HttpClientConfig { max_redirects: 0, .. HttpClientConfig::default() };
+		let resp = HttpClient::with_config(config).get(&url(addr, "/a/old")).unwrap();
+		assert_eq!(resp.response_code, 301);
+
+		drop(client);
+		handle.shutdown();
+		t.join().unwrap();
+	}
+
+	#[test]
+	pub fn test_redirect_request() {
+		let from = Url::parse("http://example.com/a").unwrap();
+		let mut req = HttpRequestMessage::empty();
+		req.method = HttpMethod::Post;
+		req.body.extend_from_slice(b"data");
+		req.headers.insert("content-length".to_string(), "4".to_string());
+		req.headers.insert("authorization".to_string(), "Basic YTpi".to_string());
+		req.headers.insert("COOKIE".to_string(), "a=1".to_string());
+		req.headers.insert("Proxy-Authorization".to_string(), "Basic YTpi".to_string());
+		req.repeated_headers.push(("cookie".to_string(), "b=2".to_string()));
+
+		// the same origin keeps the credentials
+		redirect_request(&mut req, 307, &from, &Url::parse("http://example.com:80/b").unwrap());
+		assert_eq!(req.url, "/b");
+		assert_eq!(req.get_raw_header("Authorization"), Some(&"Basic YTpi".to_string()));
+		assert_eq!(req.repeated_headers.len(), 1);
+
+		redirect_request(&mut req, 302, &from, &Url::parse("https://example.com/c").unwrap());
+		assert_eq!(req.method, HttpMethod::Get);
+		assert_eq!(req.get_raw_header("Host"), Some(&"example.com".to_string()));
+		for name in &["Content-Length", "Authorization", "Cookie", "Proxy-Authorization"] {
+			assert!(req.get_raw_header(name).is_none(), "{} kept", name);
+		}
+		assert_eq!(req.repeated_headers.len(), 0);
+	}
+
+	#[test]
+	pub fn test_client_connection_reuse() {
+		// answers every request on the first connection, and never accepts another
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let t = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let mut buf = [0; 1024];
+			for _ in 0..3 {
+				let n = stream.read(&mut buf).unwrap();
+				assert!(buf[..n].ends_with(b"\r\n\r\nping"));
+				stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\npong").unwrap();
+			}
+			// idle connections timed out on the server side
+			for close in &["", "Connection: close\r\n"] {
+				drop(stream);
+				stream = listener.accept().unwrap().0;
+				let n = stream.read(&mut buf).unwrap();
+				assert!(buf[..n].ends_with(b"\r\n\r\nping"));
+				stream.write_all(format!("HTTP/1.1 200 OK\r\n{}Content-Length: 4\r\n\r\npong", close).as_bytes()).unwrap();
+			}
+		});
+
+		let config = HttpClientConfig { read_timeout: Some(Duration::from_secs(2)), .. HttpClientConfig::default() };
+		let client = HttpClient::with_config(config);
+		for _ in 0..3 {
+			let resp = client.post(&url(addr, "/telemetry"), "text/plain", b"ping").unwrap();
+			assert_eq!(resp.body, b"pong");
+			assert_eq!(client.idle_connections(), 1);
+		}
+
+		// a PUT is sent again over a new connection
+		thread::sleep(Duration::from_millis(100));
+		let mut req = client.request(HttpMethod::Put, &url(addr, "/telemetry"));
+		req.body.extend_from_slice(b"ping");
+		assert_eq!(client.send(&url(addr, "/telemetry"), req).unwrap().body, b"pong");
+		assert_eq!(client.idle_connections(), 1);
+
+		// but a POST might have been acted on already
+		thread::sleep(Duration::from_millis(100));
+		match client.post(&url(addr, "/telemetry"), "text/plain", b"ping") {
+			Err(HttpClientError::UnexpectedEof) => (),
+			r => panic!("unexpected result {:?}", r)
+		}
+		assert_eq!(client.idle_connections(), 0);
+		let resp = client.post(&url(addr, "/telemetry"), "text/plain", b"ping").unwrap();
+		assert_eq!(resp.body, b"pong");
+		t.join().unwrap();
+	}
+}
@@ -0,0 +1,596 @@
+#![allow(clippy::redundant_field_names, clippy::len_zero, clippy::new_without_default)]
+
+use http::*;
+use parser::*;
+use router::*;
+use writer::*;
+use alloc::vec::*;
+use alloc::collections::VecDeque;
+use alloc::string::ToString;
+
+/// Limits and policy of an `HttpConnection`.
+#[derive(Debug, Clone)]
+pub struct HttpConnectionConfig {
+	/// Largest accepted request line and headers, in bytes.
+	pub max_head_size: usize,
+	/// Largest accepted request body, in bytes.
+	pub max_body_size: usize,
+	/// Serve more than one request per connection, if the client agrees.
+	pub keep_alive: bool,
+	/// Close a kept-alive connection after this many requests.
+	pub max_requests: Option<usize>,
+}
+
+impl Default for HttpConnectionConfig {
+	fn default() -> HttpConnectionConfig {
+		HttpConnectionConfig {
+			max_head_size: 8 * 1024,
+			max_body_size: 64 * 1024,
+			keep_alive: false,
+			max_requests: None
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum HttpConnectionEvent {
+	/// A complete request arrived. Answer it with `HttpConnection::respond`.
+	RequestReady(HttpRequestMessage),
+	/// The last byte of a response was handed out by `poll_transmit`.
+	ResponseDone,
+	/// Nothing more will be sent; close the transport.
+	Close,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HttpConnectionError {
+	/// There's no request waiting for a response.
+	NoPendingRequest,
+	Closed,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ConnectionState {
+	ReadingRequest,
+	AwaitingResponse,
+	SendingResponse,
+	Closed,
+}
+
+/// Transport-agnostic server side of an HTTP connection. Feed it whatever
+/// the transport received, answer the requests it produces, and send what
+/// `poll_transmit` hands out. Doesn't do any I/O itself, so it can sit on
+/// top of lwIP, smoltcp, a modem's AT command socket or `std::net`.
+pub struct HttpConnection {
+	config: HttpConnectionConfig,
+	state: ConnectionState,
+	parser: HttpParser,
+	head_bytes: usize,
+	inbox: Vec<u8>,
+	/// The peer sent more than `receive_capacity` allowed; what follows is
+	/// dropped.
+	input_refused: bool,
+	response: Option<HttpResponseMessage>,
+	response_sent: usize,
+	/// The request `poll_routed` answered, and then the response once
+	/// it's sent, for `HttpRouter::responded`.
+	routed: Option<(HttpRequestMessage, Option<HttpResponseMessage>)>,
+	close_after_response: bool,
+	requests_served: usize,
+	request_version: HttpVersion,
+	/// The response is sent without its body, which is only measured.
+	head_request: bool,
+	/// Unsent part of a `100 Continue` interim response.
+	interim: Vec<u8>,
+	continue_sent: bool,
+	events: VecDeque<HttpConnectionEvent>,
+}
+
+impl HttpConnection {
+	pub fn new() -> HttpConnection {
+		HttpConnection::with_config(HttpConnectionConfig::default())
+	}
+
+	pub fn with_config(config: HttpConnectionConfig) -> HttpConnection {
+		HttpConnection {
+			config: config,
+			state: ConnectionState::ReadingRequest,
+			parser: HttpParser::new_request(),
+			head_bytes: 0,
+			inbox: Vec::new(),
+			input_refused: false,
+			response: None,
+			response_sent: 0,
+			routed: None,
+			close_after_response: true,
+			requests_served: 0,
+			request_version: HttpVersion::Http11,
+			head_request: false,
+			interim: Vec::new(),
+			continue_sent: false,
+			events: VecDeque::new()
+		}
+	}
+
+	/// Bytes received from the peer. Takes at most `receive_capacity`
+	/// bytes; past that, pipelined requests are dropped and the connection
+	/// closes once the current one is answered.
+	pub fn received(&mut self, data: &[u8]) {
+		if self.state == ConnectionState::Closed || self.input_refused { return; }
+
+		self.inbox.extend_from_slice(data);
+		self.process_inbox();
+
+		if self.inbox.len() > self.inbox_limit() {
+			self.inbox.clear();
+			self.input_refused = true;
+			self.close_after_response = true;
+		}
+	}
+
+	/// How many more bytes `received` takes right now. Transports that can
+	/// leave data in their receive buffer should stop reading at 0, so the
+	/// peer is slowed down instead of cut off.
+	pub fn receive_capacity(&self) -> usize {
+		if self.state == ConnectionState::Closed || self.input_refused {
+			return 0;
+		}
+		self.inbox_limit().saturating_sub(self.inbox.len())
+	}
+
+	pub fn can_receive(&self) -> bool {
+		self.receive_capacity() > 0
+	}
+
+	/// The peer closed its side of the connection.
+	pub fn peer_closed(&mut self) {
+		match self.state {
+			ConnectionState::ReadingRequest => self.close(),
+			// still answer what was asked, then close
+			_ => self.close_after_response = true
+		}
+	}
+
+	/// The transport gave up waiting for data. A half-received request is
+	/// answered with 408, otherwise the connection just closes.
+	pub fn timed_out(&mut self) {
+		match self.state {
+			ConnectionState::ReadingRequest if !self.is_idle() => self.reject(408, "Request Timeout"),
+			ConnectionState::ReadingRequest => self.close(),
+			_ => ()
+		}
+	}
+
+	/// Waiting for a new request, with nothing of it received yet.
+	pub fn is_idle(&self) -> bool {
+		self.state == ConnectionState::ReadingRequest && self.inbox.len() == 0 && self.head_bytes == 0
+	}
+
+	/// Next thing the caller has to act upon.
+	pub fn poll_event(&mut self) -> Option<HttpConnectionEvent> {
+		self.events.pop_front()
+	}
+
+	/// Answer the request last returned by `RequestReady`.
+	pub fn respond(&mut self, resp: HttpResponseMessage) -> Result<(), HttpConnectionError> {
+		match self.state {
+			ConnectionState::AwaitingResponse => (),
+			ConnectionState::Closed => { return Err(HttpConnectionError::Closed); }
+			_ => { return Err(HttpConnectionError::NoPendingRequest); }
+		}
+
+		self.queue_response(resp);
+		Ok(())
+	}
+
+	/// Route the pending requests through `router` and queue the responses,
+	/// passing on the other events. Once a response is sent, the route
+	/// hears about it.
+	pub fn poll_routed(&mut self, router: &HttpRouter) -> Option<HttpConnectionEvent> {
+		loop {
+			match self.poll_event() {
+				Some(HttpConnectionEvent::RequestReady(req)) => {
+					let resp = router.respond(&req);
+					if self.respond(resp).is_ok() {
+						self.routed = Some((req, None));
+					}
+				}
+				Some(HttpConnectionEvent::ResponseDone) => {
+					if let Some((req, Some(resp))) = self.routed.take() {
+						router.responded(&req, &resp);
+					}
+					return Some(HttpConnectionEvent::ResponseDone);
+				}
+				e => { return e; }
+			}
+		}
+	}
+
+	/// Is there a response waiting to be sent?
+	pub fn wants_transmit(&self) -> bool {
+		self.state == ConnectionState::SendingResponse || self.interim.len() > 0
+	}
+
+	/// Fill `buf` with the next bytes to send. Returns how many were written.
+	pub fn poll_transmit(&mut self, buf: &mut [u8]) -> usize {
+		if self.interim.len() > 0 {
+			let n = ::core::cmp::min(buf.len(), self.interim.len());
+			buf[..n].copy_from_slice(&self.interim[..n]);
+			self.interim.drain(..n);
+			return n;
+		}
+
+		if self.state != ConnectionState::SendingResponse { return 0; }
+
+		let (n, complete) = {
+			let resp = self.response.as_ref().unwrap();
+			let mut writer = HttpMessageWriter::resume(resp, self.response_sent);
+			let n = writer.write_to(buf);
+			(n, writer.is_complete())
+		};
+		self.response_sent += n;
+
+		if complete {
+			let resp = self.response.take();
+			if let Some((_, ref mut sent)) = self.routed {
+				*sent = resp;
+			}
+			self.response_sent = 0;
+			self.head_request = false;
+			self.events.push_back(HttpConnectionEvent::ResponseDone);
+
+			if self.close_after_response {
+				self.close();
+			} else {
+				self.state = ConnectionState::ReadingRequest;
+				self.process_inbox();
+			}
+		}
+
+		n
+	}
+
+	pub fn is_closed(&self) -> bool {
+		self.state == ConnectionState::Closed
+	}
+
+	fn close(&mut self) {
+		if self.state != ConnectionState::Closed {
+			self.state = ConnectionState::Closed;
+			self.events.push_back(HttpConnectionEvent::Close);
+		}
+	}
+
+	/// Unparsed input kept around, mostly pipelined requests waiting for
+	/// the current one to be answered.
+	fn inbox_limit(&self) -> usize {
+		self.config.max_head_size + self.config.max_body_size
+	}
+
+	fn process_inbox(&mut self) {
+		if self.state != ConnectionState::ReadingRequest || self.inbox.len() == 0 { return; }
+
+		let head_done = self.parser.are_headers_parsed();
+		let used = match self.parser.parse_partial(&self.inbox) {
+			Ok((used, _)) => used,
+			Err(_) => {
+				self.reject(400, "Bad Request");
+				return;
+			}
+		};
+
+		if !head_done {
+			let mut head = used;
+			if self.parser.are_headers_parsed() {
+				head -= self.parser.get_message().get_body().len();
+			}
+			self.head_bytes += head;
+			if self.head_bytes > self.config.max_head_size {
+				self.reject(431, "Request Header Fields Too Large");
+				return;
+			}
+		}
+
+		self.inbox.drain(..used);
+
+		if self.parser.are_headers_parsed() {
+			let (send_continue, body_received) = {
+				let msg = self.parser.get_message();
+				let expected = msg.content_length().unwrap_or(0) as usize;
+				if msg.get_body().len() > self.config.max_body_size || expected > self.config.max_body_size {
+					self.reject(413, "Payload Too Large");
+					return;
+				}
+
+				let send_continue = match msg.get_raw_header("Expect") {
+					Some(e) if e.eq_ignore_ascii_case("100-continue") => true,
+					Some(_) => {
+						self.reject(417, "Expectation Failed");
+						return;
+					}
+					None => false
+				};
+				// HTTP/1.0 clients don't know about interim responses
+				let version = match *msg {
+					HttpMessage::Request(ref r) => r.http_version,
+					_ => HttpVersion::Http10
+				};
+				(send_continue && version >= HttpVersion::Http11, msg.get_body().len() > 0)
+			};
+
+			// the client waits for this before sending a large body
+			if send_continue && !self.continue_sent && !body_received && !self.parser.is_complete() {
+				self.continue_sent = true;
+				self.interim.extend_from_slice(b"HTTP/1.1 100 Continue\r\n\r\n");
+			}
+		}
+
+		if !self.parser.is_complete() { return; }
+
+		let parser = ::core::mem::replace(&mut self.parser, HttpParser::new_request());
+		self.head_bytes = 0;
+		self.continue_sent = false;
+		let req = parser.into_request().unwrap();
+		self.request_version = req.http_version;
+		self.head_request = req.method == HttpMethod::Head;
+		self.requests_served += 1;
+
+		if req.http_version == HttpVersion::Http20 {
+			self.reject(505, "HTTP Version Not Supported");
+		} else if !req.is_host_valid() {
+			self.reject(400, "Bad Request");
+		} else {
+			let limit_reached = match self.config.max_requests {
+				Some(max) => self.requests_served >= max,
+				None => false
+			};
+			self.close_after_response = !(self.config.keep_alive && req.keep_alive()) || limit_reached;
+			self.state = ConnectionState::AwaitingResponse;
+			self.events.push_back(HttpConnectionEvent::RequestReady(req));
+		}
+	}
+
+	/// Answer with an error and close, without bothering the caller.
+	fn reject(&mut self, response_code: u16, response_status: &str) {
+		self.inbox.clear();
+		self.close_after_response = true;
+		self.queue_response(HttpResponseMessage::new_status(response_code, response_status));
+	}
+
+	fn queue_response(&mut self, resp: HttpResponseMessage) {
+		let mut resp = resp;
+		if !resp.keep_alive() {
+			self.close_after_response = true;
+		}
+
+		let len = resp.body.len().to_string();
+		resp.headers.insert("Content-Length".to_string(), len);
+		if self.head_request {
+			resp.body.clear();
+		}
+		if self.close_after_response {
+			resp.headers.insert("Connection".to_string(), "close".to_string());
+		} else if !self.request_version.keep_alive_by_default() {
+			// HTTP/1.0 clients assume a close unless told otherwise
+			resp.headers.insert("Connection".to_string(), "keep-alive".to_string());
+		}
+
+		self.response = Some(resp);
+		self.response_sent = 0;
+		self.state = ConnectionState::SendingResponse;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use alloc::vec::Vec;
+	use alloc::string::String;
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	fn transmit_all(conn: &mut HttpConnection) -> Vec<u8> {
+		let mut out = Vec::new();
+		let mut buf = [0; 16];
+		loop {
+			let n = conn.poll_transmit(&mut buf);
+			if n == 0 { break; }
+			out.extend_from_slice(&buf[..n]);
+		}
+		out
+	}
+
+	#[test]
+	pub fn test_connection_request_response() {
+		let mut conn = HttpConnection::new();
+		conn.received(b"GET /index.html HTTP/1.1\r\nHo");
+		assert!(conn.poll_event().is_none());
+		conn.received(b"st: localhost\r\n\r\n");
+
+		match conn.poll_event() {
+			Some(HttpConnectionEvent::RequestReady(req)) => assert_eq!(req.url, "/index.html"),
+			e => panic!("unexpected event {:?}", e)
+		}
+		assert!(!conn.wants_transmit());
+
+		conn.respond(HttpResponseMessage::text_utf8("hi")).unwrap();
+		assert!(conn.wants_transmit());
+		let out = transmit_all(&mut conn);
+		assert!(out.starts_with(b"HTTP/1.1 200 OK\r\n"));
+		assert!(out.ends_with(b"\r\n\r\nhi"));
+
+		match conn.poll_event() { Some(HttpConnectionEvent::ResponseDone) => (), e => panic!("unexpected event {:?}", e) }
+		match conn.poll_event() { Some(HttpConnectionEvent::Close) => (), e => panic!("unexpected event {:?}", e) }
+		assert!(conn.is_closed());
+		assert_eq!(conn.respond(HttpResponseMessage::text_utf8("hi")), Err(HttpConnectionError::Closed));
+	}
+
+	#[test]
+	pub fn test_connection_rejects_bad_requests() {
+		let mut conn = HttpConnection::new();
+		conn.received(b"GET HTTP/1.1\r\n\r\n");
+		assert!(transmit_all(&mut conn).starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+		match conn.poll_event() { Some(HttpConnectionEvent::ResponseDone) => (), e => panic!("unexpected event {:?}", e) }
+		match conn.poll_event() { Some(HttpConnectionEvent::Close) => (), e => panic!("unexpected event {:?}", e) }
+
+		let mut conn = HttpConnection::new();
+		conn.received(b"GET / HTTP/1.1\r\nHost");
+		assert!(!conn.is_idle());
+		conn.timed_out();
+		assert!(transmit_all(&mut conn).starts_with(b"HTTP/1.1 408 Request Timeout\r\n"));
+		assert!(conn.is_closed());
+
+		let mut conn = HttpConnection::with_config(HttpConnectionConfig { max_body_size: 3, .. HttpConnectionConfig::default() });
+		conn.received(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\n\r\n");
+		assert!(transmit_all(&mut conn).starts_with(b"HTTP/1.1 413 "));
+	}
+
+	#[test]
+	pub fn test_connection_rejects_bad_length() {
+		let config = HttpConnectionConfig { keep_alive: true, .. HttpConnectionConfig::default() };
+		let mut conn = HttpConnection::with_config(config);
+		let mut router = HttpRouter::new();
+		router.add_route(CountingRoute { responded: Arc::new(AtomicUsize::new(0)) });
+
+		// what follows the head can't be told apart from the body
+		conn.received(b"POST /a HTTP/1.1\r\nHost: a\r\nContent-Length: abc\r\n\r\nGET /smuggled HTTP/1.1\r\nHost: a\r\n\r\n");
+		assert!(conn.poll_routed(&router).is_none());
+		let out = String::from_utf8(transmit_all(&mut conn)).unwrap();
+		assert!(out.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+		assert_eq!(out.matches("HTTP/1.1 ").count(), 1);
+		assert!(conn.is_closed());
+	}
+
+	#[test]
+	pub fn test_connection_routing() {
+		let mut router = HttpRouter::new();
+		router.add_route(HttpRouteStaticUrl::new_get("/", |_| HttpResponseMessage::text_utf8("index")));
+
+		let mut conn = HttpConnection::new();
+		conn.received(b"GET /nope HTTP/1.1\r\nHost: a\r\n\r\n");
+		assert!(conn.poll_routed(&router).is_none());
+		assert!(transmit_all(&mut conn).starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+	}
+
+	struct CountingRoute {
+		responded: Arc<AtomicUsize>,
+	}
+
+	impl HttpRoute for CountingRoute {
+		fn try(&self, _msg: &HttpRequestMessage) -> Result<bool, HttpRouteError> {
+			Ok(true)
+		}
+
+		fn execute(&self, _msg: &HttpRequestMessage) -> Result<HttpResponseMessage, HttpRouteError> {
+			Ok(HttpResponseMessage::text_utf8("hi"))
+		}
+
+		fn responded(&self, msg: &HttpRequestMessage, resp: &HttpResponseMessage) {
+			assert_eq!((msg.url.as_str(), resp.body.as_slice()), ("/", &b"hi"[..]));
+			self.responded.fetch_add(1, Ordering::SeqCst);
+		}
+	}
+
+	#[test]
+	pub fn test_connection_responded() {
+		let responded = Arc::new(AtomicUsize::new(0));
+		let mut router = HttpRouter::new();
+		router.add_route(CountingRoute { responded: responded.clone() });
+		let mut conn = HttpConnection::new();
+
+		// the route hears of the response once it's all out
+		conn.received(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+		assert!(conn.poll_routed(&router).is_none());
+		let mut buf = [0; 16];
+		conn.poll_transmit(&mut buf);
+		assert!(conn.poll_routed(&router).is_none());
+		assert_eq!(responded.load(Ordering::SeqCst), 0);
+		transmit_all(&mut conn);
+		match conn.poll_routed(&router) { Some(HttpConnectionEvent::ResponseDone) => (), e => panic!("unexpected event {:?}", e) }
+		assert_eq!(responded.load(Ordering::SeqCst), 1);
+	}
+
+	#[test]
+	pub fn test_connection_keep_alive() {
+		let config = HttpConnectionConfig { keep_alive: true, max_requests: Some(2), .. HttpConnectionConfig::default() };
+		let mut conn = HttpConnection::with_config(config);
+		let mut router = HttpRouter::new();
+		router.add_route(HttpRouteStaticUrl::new_get("/", |_| HttpResponseMessage::text_utf8("index")));
+
+		conn.received(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+		assert!(conn.poll_routed(&router).is_none());
+		let out = transmit_all(&mut conn);
+		assert!(String::from_utf8_lossy(&out).contains("Connection: keep-alive\r\n"));
+		match conn.poll_routed(&router) { Some(HttpConnectionEvent::ResponseDone) => (), e => panic!("unexpected event {:?}", e) }
+		assert!(conn.is_idle());
+
+		// the second request is the last one allowed
+		conn.received(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+		assert!(conn.poll_routed(&router).is_none());
+		assert!(String::from_utf8_lossy(&transmit_all(&mut conn)).contains("Connection: close\r\n"));
+		assert!(conn.is_closed());
+	}
+
+	#[test]
+	pub fn test_connection_head() {
+		let config = HttpConnectionConfig { keep_alive: true, .. HttpConnectionConfig::default() };
+		let mut conn = HttpConnection::with_config(config);
+
+		// the length of the body a GET would get, but not the body
+		conn.received(b"HEAD / HTTP/1.1\r\nHost: a\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n");
+		for body in &["", "index"] {
+			match conn.poll_event() { Some(HttpConnectionEvent::RequestReady(_)) => (), e => panic!("unexpected event {:?}", e) }
+			conn.respond(HttpResponseMessage::text_utf8("index")).unwrap();
+			let out = String::from_utf8(transmit_all(&mut conn)).unwrap();
+			assert!(out.contains("Content-Length: 5\r\n"));
+			assert!(out.ends_with(&format!("\r\n\r\n{}", body)));
+			match conn.poll_event() { Some(HttpConnectionEvent::ResponseDone) => (), e => panic!("unexpected event {:?}", e) }
+		}
+	}
+
+	#[test]
+	pub fn test_connection_receive_limit() {
+		let config = HttpConnectionConfig { keep_alive: true, max_head_size: 64, max_body_size: 64, .. HttpConnectionConfig::default() };
+		let mut conn = HttpConnection::with_config(config);
+		assert_eq!(conn.receive_capacity(), 128);
+
+		// pipelined requests pile up while the first one waits for an answer
+		let req = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+		conn.received(req);
+		assert!(conn.poll_event().is_some());
+		for _ in 0..4 {
+			conn.received(req);
+		}
+		assert_eq!(conn.receive_capacity(), 128 - 4 * req.len());
+		assert!(conn.can_receive());
+
+		// data beyond the capacity isn't buffered; the current request is
+		// still answered, then the connection closes
+		conn.received(req);
+		assert!(!conn.can_receive());
+		conn.respond(HttpResponseMessage::text_utf8("hi")).unwrap();
+		assert!(String::from_utf8_lossy(&transmit_all(&mut conn)).contains("Connection: close\r\n"));
+		match conn.poll_event() { Some(HttpConnectionEvent::ResponseDone) => (), e => panic!("unexpected event {:?}", e) }
+		match conn.poll_event() { Some(HttpConnectionEvent::Close) => (), e => panic!("unexpected event {:?}", e) }
+	}
+
+	#[test]
+	pub fn test_connection_expect_continue() {
+		let mut conn = HttpConnection::new();
+		conn.received(b"POST / HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nContent-Length: 2\r\n\r\n");
+		assert!(conn.poll_event().is_none());
+		assert!(conn.wants_transmit());
+		assert_eq!(transmit_all(&mut conn), b"HTTP/1.1 100 Continue\r\n\r\n".to_vec());
+
+		conn.received(b"hi");
+		match conn.poll_event() {
+			Some(HttpConnectionEvent::RequestReady(req)) => assert_eq!(req.body, b"hi"),
+			e => panic!("unexpected event {:?}", e)
+		}
+
+		// the body came along anyway, no need to ask for it
+		let mut conn = HttpConnection::new();
+		conn.received(b"POST / HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nContent-Length: 4\r\n\r\nhi");
+		assert!(!conn.wants_transmit());
+	}
+}
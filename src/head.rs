@@ -0,0 +1,380 @@
+#![allow(deprecated, ellipsis_inclusive_range_patterns, clippy::redundant_field_names, clippy::len_zero, clippy::match_like_matches_macro)]
+
+use core::str::from_utf8;
+use http::*;
+
+/// Errors of the borrowed request head parser. Doesn't allocate, so it can
+/// be used in no-alloc builds; the streaming parser maps these into
+/// `HttpParserError`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HttpHeadError {
+	/// The head isn't terminated by an empty line yet.
+	Incomplete,
+	InvalidString,
+	/// The request line isn't `method SP request-target SP HTTP-version`.
+	InvalidRequestLine,
+	/// The status line isn't `HTTP-version SP status-code SP reason-phrase`.
+	InvalidStatusLine,
+	/// The method isn't a token.
+	InvalidMethod,
+	/// A well-formed method this crate doesn't know about.
+	UnknownMethod,
+	/// The request target has no valid form for the method.
+	InvalidRequestTarget,
+	/// The version isn't `HTTP/` DIGIT `.` DIGIT, or is one we don't know.
+	InvalidVersion,
+	InvalidHeader,
+	/// Empty header name, or one that isn't an RFC 7230 token.
+	InvalidHeaderName,
+	/// Whitespace between the header name and the colon, which RFC 7230
+	/// requires to be rejected.
+	WhitespaceBeforeColon,
+	/// A header line continued with obs-fold, where folding isn't accepted.
+	ObsoleteLineFolding,
+	/// More headers than the caller-provided array can hold.
+	TooManyHeaders,
+}
+
+/// A header borrowed from the input buffer.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct HttpHeaderRef<'a> {
+	pub name: &'a str,
+	pub value: &'a str
+}
+
+pub const EMPTY_HEADER: HttpHeaderRef<'static> = HttpHeaderRef { name: "", value: "" };
+
+/// A parsed request head, made of slices of the input buffer.
+#[derive(Debug)]
+pub struct HttpRequestHead<'a, 'h> {
+	pub method: HttpMethod,
+	pub url: &'a str,
+	pub target_form: RequestTargetForm,
+	pub http_version: HttpVersion,
+	pub headers: &'h [HttpHeaderRef<'a>],
+	/// Length of the head, including the terminating empty line. The body,
+	/// if any, starts at this offset.
+	pub head_len: usize
+}
+
+impl<'a, 'h> HttpRequestHead<'a, 'h> {
+	/// Case-insensitive header lookup.
+	pub fn get_header(&self, name: &str) -> Option<&'a str> {
+		for h in self.headers {
+			if h.name.eq_ignore_ascii_case(name) {
+				return Some(h.value);
+			}
+		}
+
+		None
+	}
+}
+
+/// Parse a complete request head from `data` without copying. Headers are
+/// stored in `headers`, which bounds how many of them are accepted.
+///
+/// Folded header values can't be joined without copying, so obs-fold is
+/// always rejected with `ObsoleteLineFolding`.
+pub fn parse_request_head<'a, 'h>(data: &'a [u8], headers: &'h mut [HttpHeaderRef<'a>]) -> Result<HttpRequestHead<'a, 'h>, HttpHeadError> {
+	let mut pos = 0;
+	let mut line_num = 0;
+	let mut header_count = 0;
+
+	let mut request_line = None;
+
+	loop {
+		let line_end = match find_crlf(&data[pos..]) {
+			Some(i) => pos + i,
+			None => { return Err(HttpHeadError::Incomplete); }
+		};
+		let line = &data[pos..line_end];
+		pos = line_end + 2;
+
+		if line.len() == 0 {
+			if line_num == 0 { return Err(HttpHeadError::InvalidRequestLine); }
+			break;
+		}
+
+		let line = match from_utf8(line) {
+			Ok(l) => l,
+			Err(_) => { return Err(HttpHeadError::InvalidString); }
+		};
+
+		if line_num == 0 {
+			request_line = Some(try!(parse_request_line(line)));
+		} else if is_obs_fold(line) {
+			return Err(HttpHeadError::ObsoleteLineFolding);
+		} else {
+			if header_count == headers.len() {
+				return Err(HttpHeadError::TooManyHeaders);
+			}
+			let (name, value) = try!(split_header_line(line));
+			headers[header_count] = HttpHeaderRef { name: name, value: value };
+			header_count += 1;
+		}
+
+		line_num += 1;
+	}
+
+	let request_line = request_line.unwrap();
+	Ok(HttpRequestHead {
+		method: request_line.method,
+		url: request_line.target,
+		target_form: request_line.target_form,
+		http_version: request_line.http_version,
+		headers: &headers[..header_count],
+		head_len: pos
+	})
+}
+
+fn find_crlf(data: &[u8]) -> Option<usize> {
+	data.windows(2).position(|w| w == b"\r\n")
+}
+
+/// The four forms of request target defined by RFC 7230, section 5.3.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RequestTargetForm {
+	/// An absolute path with an optional query, `/index.html?x=1`.
+	Origin,
+	/// A complete URI, as sent to proxies, `http://example.com/`.
+	Absolute,
+	/// `host:port`, only used by CONNECT.
+	Authority,
+	/// `*`, used by OPTIONS and SSDP's M-SEARCH and NOTIFY.
+	Asterisk,
+}
+
+/// The components of a request line.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RequestLine<'a> {
+	pub method: HttpMethod,
+	pub target: &'a str,
+	pub target_form: RequestTargetForm,
+	pub http_version: HttpVersion
+}
+
+/// Parse `method SP request-target SP HTTP-version`.
+pub fn parse_request_line(line: &str) -> Result<RequestLine<'_>, HttpHeadError> {
+	let mut parts = line.split(' ');
+	let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+		(Some(m), Some(t), Some(v), None) => (m, t, v),
+		_ => { return Err(HttpHeadError::InvalidRequestLine); }
+	};
+
+	if method.len() == 0 || !method.bytes().all(is_token_char) {
+		return Err(HttpHeadError::InvalidMethod);
+	}
+	let method = match HttpMethod::from_str(method) {
+		Some(m) => m,
+		None => { return Err(HttpHeadError::UnknownMethod); }
+	};
+
+	let version = match HttpVersion::from_str(version) {
+		Some(v) => v,
+		None => { return Err(HttpHeadError::InvalidVersion); }
+	};
+
+	let target_form = try!(classify_request_target(method, target));
+
+	Ok(RequestLine {
+		method: method,
+		target: target,
+		target_form: target_form,
+		http_version: version
+	})
+}
+
+/// Work out which form `target` takes, and whether it's allowed for `method`.
+pub fn classify_request_target(method: HttpMethod, target: &str) -> Result<RequestTargetForm, HttpHeadError> {
+	if target.len() == 0 || !target.bytes().all(|c| c > 0x20 && c < 0x7f) {
+		return Err(HttpHeadError::InvalidRequestTarget);
+	}
+
+	let form = if target == "*" {
+		RequestTargetForm::Asterisk
+	} else if target.starts_with("/") {
+		RequestTargetForm::Origin
+	} else if is_absolute_uri(target) {
+		RequestTargetForm::Absolute
+	} else if method == HttpMethod::Connect && is_authority(target) {
+		RequestTargetForm::Authority
+	} else {
+		return Err(HttpHeadError::InvalidRequestTarget);
+	};
+
+	let allowed = match (method, form) {
+		(HttpMethod::Connect, RequestTargetForm::Authority) => true,
+		(HttpMethod::Connect, _) => false,
+		(HttpMethod::Options, RequestTargetForm::Asterisk) |
+		(HttpMethod::MSearch, RequestTargetForm::Asterisk) |
+		(HttpMethod::Notify, RequestTargetForm::Asterisk) => true,
+		(_, RequestTargetForm::Asterisk) => false,
+		_ => true
+	};
+
+	if allowed {
+		Ok(form)
+	} else {
+		Err(HttpHeadError::InvalidRequestTarget)
+	}
+}
+
+fn is_absolute_uri(target: &str) -> bool {
+	match target.find("://") {
+		Some(sep) if sep > 0 => {
+			let scheme = &target.as_bytes()[..sep];
+			(scheme[0] as char).is_ascii_alphabetic() &&
+				scheme.iter().all(|&c| (c as char).is_ascii_alphanumeric() || c == b'+' || c == b'-' || c == b'.')
+		}
+		_ => false
+	}
+}
+
+fn is_authority(target: &str) -> bool {
+	match target.rfind(':') {
+		Some(sep) => {
+			let port = &target[(sep + 1)..];
+			sep > 0 && !target.contains('/') && !target.contains('@') &&
+				port.len() > 0 && port.bytes().all(|c| c.is_ascii_digit())
+		}
+		None => false
+	}
+}
+
+/// Split a header line into its name and value, as per RFC 7230. The name
+/// must be a token directly followed by the colon; optional whitespace
+/// around the value is trimmed.
+pub fn split_header_line(line: &str) -> Result<(&str, &str), HttpHeadError> {
+	let sep = line.find(':');
+	if sep.is_none() {
+		return Err(HttpHeadError::InvalidHeader);
+	}
+	let sep = sep.unwrap();
+
+	let name = &line[0..sep];
+	if name.ends_with(&[' ', '\t'][..]) {
+		return Err(HttpHeadError::WhitespaceBeforeColon);
+	}
+	if name.len() == 0 || !name.bytes().all(is_token_char) {
+		return Err(HttpHeadError::InvalidHeaderName);
+	}
+
+	let value = trim_ows(&line[sep + 1..]);
+	if !value.bytes().all(is_field_value_char) {
+		return Err(HttpHeadError::InvalidHeader);
+	}
+
+	Ok((name, value))
+}
+
+/// Does this header line continue the previous one (obs-fold)?
+#[inline]
+pub fn is_obs_fold(line: &str) -> bool {
+	line.starts_with(&[' ', '\t'][..])
+}
+
+/// Strip optional whitespace (spaces and horizontal tabs) from both ends.
+#[inline]
+pub fn trim_ows(s: &str) -> &str {
+	s.trim_matches(|c| c == ' ' || c == '\t')
+}
+
+/// tchar, as defined by RFC 7230.
+#[inline]
+pub fn is_token_char(c: u8) -> bool {
+	match c {
+		b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' |
+		b'^' | b'_' | b'`' | b'|' | b'~' => true,
+		b'0' ... b'9' | b'a' ... b'z' | b'A' ... b'Z' => true,
+		_ => false
+	}
+}
+
+#[inline]
+fn is_field_value_char(c: u8) -> bool {
+	c == b'\t' || (c >= 0x20 && c != 0x7f)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	pub fn test_borrowed_request_head() {
+		let msg = b"POST /form HTTP/1.1\r\nHost: www.example.com\r\nContent-Length: 4\r\n\r\nbody";
+
+		let mut headers = [EMPTY_HEADER; 4];
+		let head = parse_request_head(msg, &mut headers).unwrap();
+		assert_eq!(head.method, HttpMethod::Post);
+		assert_eq!(head.url, "/form");
+		assert_eq!(head.target_form, RequestTargetForm::Origin);
+		assert_eq!(head.http_version, HttpVersion::Http11);
+		assert_eq!(head.headers.len(), 2);
+		assert_eq!(head.get_header("content-length"), Some("4"));
+		assert_eq!(&msg[head.head_len..], b"body");
+	}
+
+	#[test]
+	pub fn test_borrowed_request_head_errors() {
+		let mut headers = [EMPTY_HEADER; 1];
+		assert_eq!(parse_request_head(b"GET / HTTP/1.1\r\nHost: a\r\n", &mut headers).err(), Some(HttpHeadError::Incomplete));
+		assert_eq!(parse_request_head(b"GET / HTTP/1.1\r\nHost: a\r\nX: b\r\n\r\n", &mut headers).err(), Some(HttpHeadError::TooManyHeaders));
+		assert_eq!(parse_request_head(b"GET HTTP/1.1\r\n\r\n", &mut headers).err(), Some(HttpHeadError::InvalidRequestLine));
+		assert_eq!(parse_request_head(b"\r\n", &mut headers).err(), Some(HttpHeadError::InvalidRequestLine));
+		assert_eq!(parse_request_head(b"GET / HTTP/1.1\r\nHost\r\n\r\n", &mut headers).err(), Some(HttpHeadError::InvalidHeader));
+		assert_eq!(parse_request_head(b"GET / HTTP/1.1\r\nX: a\r\n b\r\n\r\n", &mut headers).err(), Some(HttpHeadError::ObsoleteLineFolding));
+	}
+
+	#[test]
+	pub fn test_request_line() {
+		let l = parse_request_line("GET /index.html?x=1 HTTP/1.1").unwrap();
+		assert_eq!((l.method, l.target, l.target_form, l.http_version), (HttpMethod::Get, "/index.html?x=1", RequestTargetForm::Origin, HttpVersion::Http11));
+
+		let l = parse_request_line("GET http://example.com:8080/x HTTP/1.1").unwrap();
+		assert_eq!(l.target_form, RequestTargetForm::Absolute);
+
+		let l = parse_request_line("CONNECT example.com:443 HTTP/1.1").unwrap();
+		assert_eq!((l.method, l.target_form), (HttpMethod::Connect, RequestTargetForm::Authority));
+
+		let l = parse_request_line("M-SEARCH * HTTP/1.1").unwrap();
+		assert_eq!((l.method, l.target_form), (HttpMethod::MSearch, RequestTargetForm::Asterisk));
+
+		let l = parse_request_line("SUBSCRIBE /upnp/event HTTP/1.1").unwrap();
+		assert_eq!((l.method, l.target_form), (HttpMethod::Subscribe, RequestTargetForm::Origin));
+
+		let l = parse_request_line("OPTIONS * HTTP/1.0").unwrap();
+		assert_eq!((l.target_form, l.http_version), (RequestTargetForm::Asterisk, HttpVersion::Http10));
+	}
+
+	#[test]
+	pub fn test_request_line_errors() {
+		assert_eq!(parse_request_line("GET HTTP/1.1"), Err(HttpHeadError::InvalidRequestLine));
+		assert_eq!(parse_request_line("GET  / HTTP/1.1"), Err(HttpHeadError::InvalidRequestLine));
+		assert_eq!(parse_request_line("GET / HTTP/1.1 x"), Err(HttpHeadError::InvalidRequestLine));
+		assert_eq!(parse_request_line(""), Err(HttpHeadError::InvalidRequestLine));
+		assert_eq!(parse_request_line("G(T / HTTP/1.1"), Err(HttpHeadError::InvalidMethod));
+		assert_eq!(parse_request_line("BREW / HTTP/1.1"), Err(HttpHeadError::UnknownMethod));
+		assert_eq!(parse_request_line("GET / HTTP/1"), Err(HttpHeadError::InvalidVersion));
+		assert_eq!(parse_request_line("GET / HTTP/1.10"), Err(HttpHeadError::InvalidVersion));
+		assert_eq!(parse_request_line("GET / FTP/1.1"), Err(HttpHeadError::InvalidVersion));
+		assert_eq!(parse_request_line("GET / HTTP/3.0"), Err(HttpHeadError::InvalidVersion));
+		assert_eq!(parse_request_line("GET * HTTP/1.1"), Err(HttpHeadError::InvalidRequestTarget));
+		assert_eq!(parse_request_line("GET index.html HTTP/1.1"), Err(HttpHeadError::InvalidRequestTarget));
+		assert_eq!(parse_request_line("CONNECT /x HTTP/1.1"), Err(HttpHeadError::InvalidRequestTarget));
+		assert_eq!(parse_request_line("CONNECT example.com HTTP/1.1"), Err(HttpHeadError::InvalidRequestTarget));
+	}
+
+	#[test]
+	pub fn test_header_line_splitting() {
+		assert_eq!(split_header_line("Host:example.com"), Ok(("Host", "example.com")));
+		assert_eq!(split_header_line("Key:  value \t"), Ok(("Key", "value")));
+		assert_eq!(split_header_line("Empty:"), Ok(("Empty", "")));
+		assert_eq!(split_header_line("X-Time: 12:30"), Ok(("X-Time", "12:30")));
+		assert_eq!(split_header_line("Host : example.com"), Err(HttpHeadError::WhitespaceBeforeColon));
+		assert_eq!(split_header_line("Host\t: example.com"), Err(HttpHeadError::WhitespaceBeforeColon));
+		assert_eq!(split_header_line("Bad Name: x"), Err(HttpHeadError::InvalidHeaderName));
+		assert_eq!(split_header_line("(x): y"), Err(HttpHeadError::InvalidHeaderName));
+		assert_eq!(split_header_line(": y"), Err(HttpHeadError::InvalidHeaderName));
+		assert_eq!(split_header_line("X: a\x00b"), Err(HttpHeadError::InvalidHeader));
+	}
+}
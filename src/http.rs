@@ -1,8 +1,12 @@
+#![allow(ellipsis_inclusive_range_patterns, clippy::redundant_field_names, clippy::unnecessary_unwrap, clippy::should_implement_trait)]
+
 use core::str::from_utf8;
-use collections::vec::*;
-use collections::String;
-use collections::string::ToString;
-use collections::BTreeMap;
+use alloc::vec::*;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::collections::BTreeMap;
+use writer::*;
+use head::*;
 
 pub enum HttpMessage {
 	Request(HttpRequestMessage),
@@ -44,10 +48,13 @@ pub enum HttpMethod {
 	Options,
 	Notify,
 	MSearch,
+	Connect,
+	Subscribe,
+	Unsubscribe,
 }
 
 impl HttpMethod {
-	pub fn to_string(&self) -> String {
+	pub fn as_str(&self) -> &'static str {
 		match *self {
 			HttpMethod::Get => "GET",
 			HttpMethod::Post => "POST",
@@ -56,8 +63,82 @@ impl HttpMethod {
 			HttpMethod::Delete => "DELETE",
 			HttpMethod::Options => "OPTIONS",
 			HttpMethod::Notify => "NOTIFY",
-			HttpMethod::MSearch => "M-SEARCH"
-		}.to_string()
+			HttpMethod::MSearch => "M-SEARCH",
+			HttpMethod::Connect => "CONNECT",
+			HttpMethod::Subscribe => "SUBSCRIBE",
+			HttpMethod::Unsubscribe => "UNSUBSCRIBE"
+		}
+	}
+
+	#[allow(clippy::inherent_to_string)]
+	pub fn to_string(&self) -> String {
+		self.as_str().to_string()
+	}
+
+	pub fn from_str(s: &str) -> Option<HttpMethod> {
+		let m = match s {
+			"GET" => HttpMethod::Get,
+			"POST" => HttpMethod::Post,
+			"HEAD" => HttpMethod::Head,
+			"PUT" => HttpMethod::Put,
+			"DELETE" => HttpMethod::Delete,
+			"OPTIONS" => HttpMethod::Options,
+			"NOTIFY" => HttpMethod::Notify,
+			"M-SEARCH" => HttpMethod::MSearch,
+			"CONNECT" => HttpMethod::Connect,
+			"SUBSCRIBE" => HttpMethod::Subscribe,
+			"UNSUBSCRIBE" => HttpMethod::Unsubscribe,
+			_ => { return None; }
+		};
+
+		Some(m)
+	}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum HttpVersion {
+	Http09,
+	Http10,
+	Http11,
+	/// Recognized, but not spoken by this crate.
+	Http20,
+}
+
+impl HttpVersion {
+	pub fn as_str(&self) -> &'static str {
+		match *self {
+			HttpVersion::Http09 => "HTTP/0.9",
+			HttpVersion::Http10 => "HTTP/1.0",
+			HttpVersion::Http11 => "HTTP/1.1",
+			HttpVersion::Http20 => "HTTP/2.0"
+		}
+	}
+
+	/// Parse `HTTP/x.y`. Later HTTP/1 minor versions are understood as 1.1,
+	/// as per RFC 7230, section 2.6.
+	pub fn from_str(s: &str) -> Option<HttpVersion> {
+		let v = s.as_bytes();
+		if v.len() != 8 || !v.starts_with(b"HTTP/") || v[6] != b'.' {
+			return None;
+		}
+
+		match (v[5], v[7]) {
+			(b'0', b'9') => Some(HttpVersion::Http09),
+			(b'1', b'0') => Some(HttpVersion::Http10),
+			(b'1', b'1' ... b'9') => Some(HttpVersion::Http11),
+			(b'2', b'0') => Some(HttpVersion::Http20),
+			_ => None
+		}
+	}
+
+	/// Are connections persistent unless a `Connection` header says otherwise?
+	pub fn keep_alive_by_default(&self) -> bool {
+		*self >= HttpVersion::Http11
+	}
+
+	/// Must requests carry a `Host` header?
+	pub fn requires_host(&self) -> bool {
+		*self >= HttpVersion::Http11
 	}
 }
 
@@ -70,9 +151,12 @@ pub enum HttpContentType {
 #[derive(Debug, Eq, PartialEq)]
 pub struct HttpRequestMessage {
     pub method: HttpMethod,
-    pub http_version: String,
+    pub http_version: HttpVersion,
     pub url: String,
     pub headers: BTreeMap<String, String>,
+    /// Later occurrences of fields that can't be combined into a
+    /// comma-separated list: `Set-Cookie`, and a repeated `Host`.
+    pub repeated_headers: Vec<(String, String)>,
     pub body: Vec<u8>,
 }
 
@@ -80,9 +164,10 @@ impl HttpRequestMessage {
     pub fn empty() -> HttpRequestMessage {
         HttpRequestMessage {
             method: HttpMethod::Get,
-            http_version: String::new(),
+            http_version: HttpVersion::Http11,
             url: String::new(),
             headers: BTreeMap::new(),
+            repeated_headers: Vec::new(),
             body: Vec::new()
         }
     }
@@ -93,38 +178,41 @@ impl HttpRequestMessage {
 
 		HttpRequestMessage {
 			method: HttpMethod::Get,
-			http_version: "1.1".to_string(),
+			http_version: HttpVersion::Http11,
 			url: url.to_string(),
 			headers: headers,
+			repeated_headers: Vec::new(),
 			body: Vec::new()
 		}
 	}
 
-	pub fn to_bytes(&self) -> Vec<u8> {
-		let mut ret = Vec::new();
-		
-		fn output_line(r: &mut Vec<u8>, s: &str) {
-			let nl = b"\r\n";
-			for b in s.bytes() {
-				r.push(b);
-			}
-			for b in nl {
-				r.push(*b);
-			}
-		}
-
-		output_line(&mut ret, format!("{} {} HTTP/{}", self.method.to_string(), self.url, self.http_version).as_str());		
+	/// Should the connection stay open after this request?
+	pub fn keep_alive(&self) -> bool {
+		connection_keep_alive(self, self.http_version)
+	}
 
-		for (key, val) in &self.headers {
-			output_line(&mut ret, format!("{}: {}", key, val).as_str());
+	/// HTTP/1.1 requests must carry exactly one Host header (RFC 7230,
+	/// section 5.4). The parser keeps a second one in `repeated_headers`.
+	pub fn is_host_valid(&self) -> bool {
+		if !self.http_version.requires_host() {
+			return true;
 		}
 
-		output_line(&mut ret, "");
+		self.get_raw_header("Host").is_some() &&
+			!self.repeated_headers.iter().any(|h| h.0.eq_ignore_ascii_case("Host"))
+	}
 
-		ret.extend_from_slice(&self.body);
+	/// Which form the request target (`url`) takes. `None` if it isn't a
+	/// valid target for this method.
+	pub fn target_form(&self) -> Option<RequestTargetForm> {
+		classify_request_target(self.method, &self.url).ok()
+	}
 
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut ret = Vec::new();
+		let _ = serialize_to(self, &mut ret);
 		ret
-	}	
+	}
 }
 
 impl HttpHeaders for HttpRequestMessage {
@@ -137,9 +225,31 @@ pub trait HttpHeaders {
     fn get_raw_headers(&self) -> &BTreeMap<String, String>;
 	//fn get_mut_raw_headers(&mut self) -> &mut BTreeMap<String, String>;
 
+    /// Header names are case-insensitive; an exact match is tried first.
     fn get_raw_header(&self, key: &str) -> Option<&String> {
         let h = self.get_raw_headers();
-        h.get(key)
+        let v = h.get(key);
+        if v.is_some() {
+            return v;
+        }
+
+        for (k, v) in h {
+            if k.eq_ignore_ascii_case(key) {
+                return Some(v);
+            }
+        }
+
+        None
+    }
+
+    /// Does the comma-separated `Connection` header list this option?
+    fn has_connection_option(&self, option: &str) -> bool {
+        let c = self.get_raw_header("Connection");
+        if c.is_some() {
+            return c.unwrap().split(',').any(|o| o.trim().eq_ignore_ascii_case(option));
+        }
+
+        false
     }
 
     fn content_length(&self) -> Option<u32> {
@@ -154,18 +264,37 @@ pub trait HttpHeaders {
         None
     }
 
+    /// Is the body sent with the chunked transfer coding? It has to be the
+    /// last coding applied.
+    fn is_chunked(&self) -> bool {
+        let te = self.get_raw_header("Transfer-Encoding");
+        if te.is_some() {
+            return te.unwrap().rsplit(',').next().is_some_and(|c| c.trim().eq_ignore_ascii_case("chunked"));
+        }
+
+        false
+    }
+
     fn content_type(&self) -> HttpContentType {
         let c = self.get_raw_header("Content-Type");
-        if c.is_some() {
-            if c.unwrap().starts_with("application/x-www-form-urlencoded") {
-                return HttpContentType::UrlEncodedForm;
-            }
+        if c.is_some() && c.unwrap().starts_with("application/x-www-form-urlencoded") {
+            return HttpContentType::UrlEncodedForm;
         }
 
         HttpContentType::Unknown
     }
 }
 
+fn connection_keep_alive<H: HttpHeaders>(msg: &H, version: HttpVersion) -> bool {
+    if msg.has_connection_option("close") {
+        false
+    } else if msg.has_connection_option("keep-alive") {
+        true
+    } else {
+        version.keep_alive_by_default()
+    }
+}
+
 pub struct BodyFormParser;
 impl BodyFormParser {
     pub fn parse(req: &HttpRequestMessage) -> BTreeMap<String, String> {
@@ -184,7 +313,7 @@ pub fn parse_urlencoded_form(body: &str) -> BTreeMap<String, String> {
     for f in body.split("&") {
         let kv: Vec<&str> = f.split("=").collect();
         if kv.len() == 2 {
-            let k = kv.get(0).unwrap();
+            let k = kv.first().unwrap();
             let v = kv.get(1).unwrap();
 
             let k = percent_decode_str(k);
@@ -258,15 +387,15 @@ pub fn lossy_utf8_percent_decode(input: &[u8]) -> String {
 #[test]
 fn test_form_parser() {
     let f = "ssid=test&submit=Connect";
-    let p = parse_urlencoded_form(&f);
+    let p = parse_urlencoded_form(f);
     println!("p: {:?}", p);
 
     let f = "ssid=rock+%26+roll&submit=Connect";
-    let p = parse_urlencoded_form(&f);
+    let p = parse_urlencoded_form(f);
     println!("p: {:?}", p);
 
     let f = "ssid=%26%23269%3B%9E%26%23263%3B%26%23273%3B%9A%26%23269%3B&submit=Connect";
-    let p = parse_urlencoded_form(&f);
+    let p = parse_urlencoded_form(f);
     println!("p: {:?}", p);	
 
 }
@@ -275,8 +404,11 @@ fn test_form_parser() {
 pub struct HttpResponseMessage {
     pub response_code: u16,
     pub response_status: String,
-    pub http_version: String,
+    pub http_version: HttpVersion,
     pub headers: BTreeMap<String, String>,
+    /// Later occurrences of fields that can't be combined into a
+    /// comma-separated list: `Set-Cookie`, and a repeated `Host`.
+    pub repeated_headers: Vec<(String, String)>,
     pub body: Vec<u8>
 }
 
@@ -287,41 +419,35 @@ impl HttpHeaders for HttpResponseMessage {
 }
 
 impl HttpResponseMessage {
+	/// Should the connection stay open after this response?
+	pub fn keep_alive(&self) -> bool {
+		connection_keep_alive(self, self.http_version)
+	}
+
 	pub fn empty() -> HttpResponseMessage {
 		HttpResponseMessage {
 			response_code: 0,
 			response_status: "".to_string(),
-			http_version: "".to_string(),
+			http_version: HttpVersion::Http11,
 			headers: BTreeMap::new(),
+			repeated_headers: Vec::new(),
 			body: Vec::new()
 		}
 	}
 
 	pub fn to_bytes(&self) -> Vec<u8> {
 		let mut ret = Vec::new();
-        
-        fn output_line(r: &mut Vec<u8>, s: &str) {
-            let nl = b"\r\n";
-            for b in s.bytes() {
-                r.push(b);
-            }
-            for b in nl {
-                r.push(*b);
-            }
-        }
-
-        output_line(&mut ret, &format!("HTTP/{} {} {}", self.http_version, self.response_code, self.response_status));
-
-        for (key, val) in &self.headers {
-            output_line(&mut ret, &format!("{}: {}", key, val));
-        }
-
-        output_line(&mut ret, "");
-
-        ret.extend_from_slice(&self.body);
+		let _ = serialize_to(self, &mut ret);
+		ret
+	}
 
-        ret
-    }
+	/// Plain-text response with the status line as its body, for errors.
+	pub fn new_status(response_code: u16, response_status: &str) -> HttpResponseMessage {
+		let mut resp = HttpResponseMessage::text_utf8(response_status);
+		resp.response_code = response_code;
+		resp.response_status = response_status.to_string();
+		resp
+	}
 
     pub fn html_utf8(body: &str) -> HttpResponseMessage {
         let mut headers = BTreeMap::new();
@@ -330,8 +456,9 @@ impl HttpResponseMessage {
         HttpResponseMessage {
             response_code: 200,
             response_status: String::from("OK"),
-            http_version: String::from("1.1"),
+            http_version: HttpVersion::Http11,
             headers: headers,
+            repeated_headers: Vec::new(),
             body: body.bytes().collect()
         }
     }
@@ -343,8 +470,9 @@ impl HttpResponseMessage {
 		HttpResponseMessage {
 			response_code: 200,
 			response_status: "OK".to_string(),
-			http_version: "1.1".to_string(),
+			http_version: HttpVersion::Http11,
 			headers: headers,
+			repeated_headers: Vec::new(),
 			body: body.bytes().collect()
 		}
 	}
@@ -356,8 +484,9 @@ impl HttpResponseMessage {
 		HttpResponseMessage {
 			response_code: 200,
 			response_status: "OK".to_string(),
-			http_version: "1.1".to_string(),
+			http_version: HttpVersion::Http11,
 			headers: headers,
+			repeated_headers: Vec::new(),
 			body: body.bytes().collect()
 		}
 	}	
@@ -1,29 +1,70 @@
 #![no_std]
 
-#![feature(alloc)]
-#![feature(no_std)]
-#![feature(collections)]
-#![feature(convert)]
-#![feature(vec_push_all)]
-
+// `format!` and `vec!`; std's take over when it's there
+#[allow(unused_imports)]
 #[macro_use]
 extern crate alloc;
-#[macro_use]
-extern crate collections;
 
-// for tests
-#[cfg(test)]
+// for tests and the std-only parts
+#[cfg(any(test, feature = "std"))]
 #[macro_use]
 extern crate std;
 
+#[cfg(feature = "smoltcp")]
+extern crate smoltcp;
+#[cfg(feature = "async")]
+extern crate futures_io;
+#[cfg(feature = "async-tokio")]
+extern crate tokio;
+#[cfg(feature = "tls")]
+extern crate rustls;
+#[cfg(feature = "tls")]
+extern crate webpki_roots;
+#[cfg(all(test, feature = "tls"))]
+extern crate rcgen;
+
 mod http;
+#[cfg(any(test, feature = "std"))]
+mod client;
+mod connection;
+mod head;
 mod router;
 mod parser;
+mod percent_encoding;
 mod ssdp;
+mod upnp;
 mod url;
+#[cfg(any(test, feature = "std"))]
+mod server;
+mod writer;
+#[cfg(feature = "smoltcp")]
+mod smoltcp_server;
+#[cfg(feature = "async")]
+mod async_io;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(any(test, feature = "test-support"))]
+mod test_support;
 
 pub use http::*;
+#[cfg(any(test, feature = "std"))]
+pub use client::*;
+pub use connection::*;
+pub use head::*;
 pub use router::*;
 pub use parser::*;
+pub use percent_encoding::*;
 pub use ssdp::*;
+pub use upnp::*;
 pub use url::*;
+#[cfg(any(test, feature = "std"))]
+pub use server::*;
+pub use writer::*;
+#[cfg(feature = "smoltcp")]
+pub use smoltcp_server::*;
+#[cfg(feature = "async")]
+pub use async_io::*;
+#[cfg(feature = "tls")]
+pub use tls::*;
+#[cfg(any(test, feature = "test-support"))]
+pub use test_support::*;
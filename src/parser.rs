@@ -1,18 +1,37 @@
+#![allow(deprecated, clippy::redundant_field_names, clippy::len_zero, clippy::needless_return, clippy::unnecessary_unwrap)]
+
 use core::str::from_utf8;
 use http::*;
-use collections::vec::*;
-use collections::String;
-use collections::string::ToString;
+use head::*;
+use alloc::vec::*;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::collections::BTreeMap;
 
 pub struct HttpParser {
 	buffer: Vec<u8>,
 	pos: usize,
 	line_num: u16,
 	headers_parsed: bool,
+	obs_fold: ObsFoldHandling,
+	last_header: Option<String>,
+	chunked: Option<ChunkedBody>,
+	/// Method of the request a response answers, if known.
+	request_method: Option<HttpMethod>,
 
 	msg: HttpMessage
 }
 
+/// What to do with header values continued on the next line (obs-fold),
+/// which RFC 7230 deprecates.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ObsFoldHandling {
+	/// Fail with `HttpParserError::HeaderError`. The default for requests.
+	Reject,
+	/// Replace the fold with a single space. The default for responses.
+	Unfold,
+}
+
 #[derive(Debug)]
 pub enum HttpParserState {
     MoreDataRequired,
@@ -23,7 +42,23 @@ pub enum HttpParserState {
 pub enum HttpParserError {
     InvalidString,
     HeaderError,
-    LineParseError(String),
+    /// A malformed request or status line, and what's wrong with it.
+    Head(HttpHeadError),
+    /// Malformed chunk size line or chunk delimiter.
+    ChunkError,
+    /// The datagram ended inside the headers or the body.
+    Truncated,
+}
+
+impl HttpParserError {
+	fn from_head_error(e: HttpHeadError) -> HttpParserError {
+		match e {
+			HttpHeadError::InvalidString => HttpParserError::InvalidString,
+			HttpHeadError::InvalidHeader | HttpHeadError::InvalidHeaderName |
+			HttpHeadError::WhitespaceBeforeColon | HttpHeadError::ObsoleteLineFolding => HttpParserError::HeaderError,
+			e => HttpParserError::Head(e)
+		}
+	}
 }
 
 impl HttpParser {
@@ -33,10 +68,29 @@ impl HttpParser {
 			pos: 0,
 			line_num: 0,
 			headers_parsed: false,
+			obs_fold: match msg {
+				HttpMessage::Request(_) => ObsFoldHandling::Reject,
+				HttpMessage::Response(_) => ObsFoldHandling::Unfold
+			},
+			last_header: None,
+			chunked: None,
+			request_method: None,
 			msg: msg
 		}
 	}
 
+	/// Parser for the response to a `method` request. Needed to know that
+	/// responses to HEAD have no body.
+	pub fn new_response_to(method: HttpMethod) -> HttpParser {
+		let mut parser = HttpParser::new_response();
+		parser.request_method = Some(method);
+		parser
+	}
+
+	pub fn set_obs_fold(&mut self, handling: ObsFoldHandling) {
+		self.obs_fold = handling;
+	}
+
 	pub fn new_request() -> HttpParser {
 		HttpParser::new(HttpMessage::Request(HttpRequestMessage::empty()))
 	}
@@ -54,6 +108,8 @@ impl HttpParser {
     }
 
     pub fn read_how_many_bytes(&self) -> u32 {
+        if self.is_complete() { return 0; }
+
         if self.is_first_line_parsed() && self.are_headers_parsed() {
 			match self.msg {
 				HttpMessage::Request(ref req) => {
@@ -71,56 +127,143 @@ impl HttpParser {
             	if r <= 0 { return 0; }
             	return r as u32;
             }
+
+            // a request without a Content-Length has no body
+            if let HttpMessage::Request(_) = self.msg {
+            	return 0;
+            }
         }
 
         return 1;
     }
 
+	/// Has the whole message, including its body, been parsed? Messages
+	/// delimited by the connection closing are never complete.
+	pub fn is_complete(&self) -> bool {
+		self.body_remaining() == Some(0)
+	}
+
+	/// Does the body run until the connection is closed? Then the message
+	/// is only complete once the peer closes it.
+	pub fn is_close_delimited(&self) -> bool {
+		self.headers_parsed && self.chunked.is_none() && self.body_remaining().is_none()
+	}
+
+	/// Body bytes still expected, `None` if the body runs until the
+	/// connection is closed, is chunked, or the headers aren't parsed yet.
+	fn body_remaining(&self) -> Option<usize> {
+		if !self.headers_parsed {
+			return None;
+		}
+
+		match self.msg {
+			HttpMessage::Request(ref req) => {
+				if req.method == HttpMethod::Get || req.method == HttpMethod::Head {
+					return Some(0);
+				}
+			}
+			HttpMessage::Response(ref resp) => {
+				// RFC 7230, section 3.3.3
+				let code = resp.response_code;
+				if self.request_method == Some(HttpMethod::Head) || (100..200).contains(&code) || code == 204 || code == 304 {
+					return Some(0);
+				}
+			}
+		}
+
+		if let Some(ref chunked) = self.chunked {
+			return if chunked.is_done() { Some(0) } else { None };
+		}
+
+		match self.msg.content_length() {
+			Some(len) => {
+				let len = len as usize;
+				let body_len = self.msg.get_body().len();
+				Some(len.saturating_sub(body_len))
+			}
+			None => {
+				match self.msg {
+					HttpMessage::Request(_) => Some(0),
+					HttpMessage::Response(_) => None
+				}
+			}
+		}
+	}
+
 	pub fn parse_bytes(&mut self, data: &[u8]) -> Result<HttpParserState, HttpParserError> {
-		if data.len() == 0 { return Ok(HttpParserState::MoreDataRequired); }
+		let (_, state) = try!(self.parse_partial(data));
+		Ok(state)
+	}
+
+	/// Like `parse_bytes`, but stops at the end of the message. Returns how
+	/// many bytes of `data` were used; the rest belongs to whatever follows
+	/// on the connection, like a pipelined request.
+	pub fn parse_partial(&mut self, data: &[u8]) -> Result<(usize, HttpParserState), HttpParserError> {
+		if self.is_complete() { return Ok((0, HttpParserState::Complete)); }
+		if data.len() == 0 { return Ok((0, HttpParserState::MoreDataRequired)); }
 
 		self.buffer.extend_from_slice(data);
 
-		if self.headers_parsed == false {
+		if !self.headers_parsed {
 			let p = self.pos;
 			for i in p..self.buffer.len(){
 				//println!("i = {}", i);
 				let f = self.buffer[i];
-				if f == '\r' as u8 {
-					if i + 1 < self.buffer.len() {
-						let f2 = self.buffer[i + 1];
-						if f2 == '\n' as u8 {
-							// line found
-							let line = &self.buffer[self.pos..i];
-
-                            self.pos = i + 2;							
-
-                            if line.len() == 0 {
-                                self.line_num += 1;
-                                self.headers_parsed = true;
-                                break;
-                            }
-
-							if self.line_num == 0 {
-								match self.msg {
-									HttpMessage::Request(ref mut m) => try!(HttpParser::parse_first_request_line(m, line)),
-									HttpMessage::Response(ref mut m) => try!(HttpParser::parse_first_response_line(m, line))
-								}
-								
-							} else {
-								try!(HttpParser::parse_header_line(&mut self.msg, line));
-							}
-
-                            self.line_num += 1;
-                        }
-                    }
-                }
+				if f == b'\r' && i + 1 < self.buffer.len() && self.buffer[i + 1] == b'\n' {
+					// line found
+					let line = &self.buffer[self.pos..i];
+
+					self.pos = i + 2;
+
+					if line.len() == 0 {
+						self.line_num += 1;
+						self.headers_parsed = true;
+						try!(self.check_framing());
+						if self.msg.is_chunked() {
+							self.chunked = Some(ChunkedBody::new());
+						}
+						break;
+					}
+
+					if self.line_num == 0 {
+						match self.msg {
+							HttpMessage::Request(ref mut m) => try!(HttpParser::parse_first_request_line(m, line)),
+							HttpMessage::Response(ref mut m) => try!(HttpParser::parse_first_response_line(m, line))
+						}
+
+					} else {
+						let line_str = try!(from_utf8(line).map_err(|_| HttpParserError::InvalidString));
+						if is_obs_fold(line_str) {
+							try!(HttpParser::parse_folded_line(&mut self.msg, &self.last_header, self.obs_fold, line_str));
+						} else {
+							self.last_header = Some(try!(HttpParser::parse_header_line(&mut self.msg, line)));
+						}
+					}
+
+					self.line_num += 1;
+				}
             }
         }
 
-		if self.headers_parsed {
+		let mut unused = 0;
+		if self.headers_parsed && self.chunked.is_some() && self.body_remaining() != Some(0) {
 			{
 				let s = &self.buffer[(self.pos)..];
+				let chunked = self.chunked.as_mut().unwrap();
+				let used = try!(chunked.decode(s, self.msg.get_body_mut()));
+				unused = s.len() - used;
+			}
+			self.buffer.clear();
+			self.pos = 0;
+		} else if self.headers_parsed {
+			{
+				let mut s = &self.buffer[(self.pos)..];
+				if let Some(remaining) = self.body_remaining() {
+					if s.len() > remaining {
+						unused = s.len() - remaining;
+						s = &s[..remaining];
+					}
+				}
 				let body = self.msg.get_body_mut();
 				body.extend_from_slice(s);
 			}
@@ -128,66 +271,39 @@ impl HttpParser {
 			self.pos = 0;
 		}
 
-
-		Ok(HttpParserState::MoreDataRequired)
+		let state = if self.is_complete() { HttpParserState::Complete } else { HttpParserState::MoreDataRequired };
+		Ok((data.len() - unused, state))
 	}
 
 	fn parse_first_request_line(msg: &mut HttpRequestMessage, line: &[u8]) -> Result<(), HttpParserError> {
 		let str = from_utf8(line);
-		if !str.is_ok() { return Err(HttpParserError::InvalidString); }
+		if str.is_err() { return Err(HttpParserError::InvalidString); }
 		let str = str.unwrap();
 
-		let mut middle = str;
-
-		let http_methods = [("GET", HttpMethod::Get), ("HEAD", HttpMethod::Head), ("POST", HttpMethod::Post),
-		                    ("NOTIFY", HttpMethod::Notify), ("M-SEARCH", HttpMethod::MSearch)
-		                   ];
-		let method = {
-			let mut matched = false;
-			for m in &http_methods {
-				if str.starts_with(m.0) {
-					msg.method = m.1;
-					middle = &str[(m.0.len() + 1)..];
+		match parse_request_line(str) {
+			Ok(l) => {
+				msg.method = l.method;
+				msg.url = l.target.to_string();
+				msg.http_version = l.http_version;
 
-					matched = true;
-					break;
-				}
+				Ok(())
 			}
-
-			matched
-		};
-
-		if method == false {
-			return Err(HttpParserError::LineParseError(str.to_string()));
+			Err(e) => Err(HttpParserError::from_head_error(e))
 		}
-
-		if str.ends_with("HTTP/1.1") {
-			msg.http_version = "1.1".to_string();
-		} else if str.ends_with("HTTP/1.0") || str.ends_with("HTTP/1") {
-			msg.http_version = "1.0".to_string();
-		}
-
-		let l = middle.rfind("HTTP/1");
-		if l.is_none() { return Err(HttpParserError::LineParseError(str.to_string())); }
-
-        let url = &middle[..(l.unwrap() - 1)];
-        msg.url = url.to_string();
-
-        return Ok(());
-    }
+	}
 
 	fn parse_first_response_line(msg: &mut HttpResponseMessage, line: &[u8]) -> Result<(), HttpParserError> {
 		let str = from_utf8(line);
-		if !str.is_ok() { return Err(HttpParserError::InvalidString); }
+		if str.is_err() { return Err(HttpParserError::InvalidString); }
 		let str = str.unwrap();
 
 		let split: Vec<&str> = str.splitn(3, " ").collect();
-		if split.len() != 3 { return Err(HttpParserError::LineParseError(str.to_string())); }
+		if split.len() != 3 { return Err(HttpParserError::Head(HttpHeadError::InvalidStatusLine)); }
 
-		match split.get(0) {
-			Some(&"HTTP/1.1") => msg.http_version = "1.1".to_string(),
-			Some(&"HTTP/1.0") | Some(&"HTTP/1") => msg.http_version = "1.0".to_string(),
-			_ => { return Err(HttpParserError::LineParseError(str.to_string())); }
+		match split.first().and_then(|v| HttpVersion::from_str(v)) {
+			Some(v) => msg.http_version = v,
+			None if split.first() == Some(&"HTTP/1") => msg.http_version = HttpVersion::Http10,
+			None => { return Err(HttpParserError::Head(HttpHeadError::InvalidVersion)); }
 		}
 
 		match split.get(1) {
@@ -195,48 +311,128 @@ impl HttpParser {
 				if let Ok(code_num) = code.parse::<u16>() {
 					msg.response_code = code_num;
 				} else {
-					return Err(HttpParserError::LineParseError(str.to_string()));
+					return Err(HttpParserError::Head(HttpHeadError::InvalidStatusLine));
 				}
 			}
-			_ => { return Err(HttpParserError::LineParseError(str.to_string())); }
+			_ => { return Err(HttpParserError::Head(HttpHeadError::InvalidStatusLine)); }
 		}
 
 		match split.get(2) {
 			Some(status) => {
 				msg.response_status = status.to_string();
 			}
-			_ => { return Err(HttpParserError::LineParseError(str.to_string())); }
+			_ => { return Err(HttpParserError::Head(HttpHeadError::InvalidStatusLine)); }
 		}
 
 		Ok(())
 	}
 
-	fn parse_header_line(msg: &mut HttpMessage, line: &[u8]) -> Result<(), HttpParserError> {
+	fn parse_header_line(msg: &mut HttpMessage, line: &[u8]) -> Result<String, HttpParserError> {
 		let str = from_utf8(line);
 		if str.is_ok() {
 			let str = str.unwrap();
-			
-			let sep = str.find(": ");
-			if sep.is_none() {
-				return Err(HttpParserError::HeaderError);
-			}
-			let sep = sep.unwrap();
 
-			let key = &str[0..sep];
-			let val = &str[sep + 2..];
+			let (key, val) = match split_header_line(str) {
+				Ok(kv) => kv,
+				Err(e) => { return Err(HttpParserError::from_head_error(e)); }
+			};
 
-			let headers = match *msg {
-				HttpMessage::Request(ref mut r) => &mut r.headers,
-				HttpMessage::Response(ref mut r) => &mut r.headers
+			let (headers, repeated) = match *msg {
+				HttpMessage::Request(ref mut r) => (&mut r.headers, &mut r.repeated_headers),
+				HttpMessage::Response(ref mut r) => (&mut r.headers, &mut r.repeated_headers)
 			};
+
+			// these can't be combined into one comma-separated list
+			if (key.eq_ignore_ascii_case("Set-Cookie") || key.eq_ignore_ascii_case("Host")) &&
+			   headers.keys().any(|k| k.eq_ignore_ascii_case(key)) {
+				repeated.push((key.to_string(), val.to_string()));
+				return Ok(key.to_string());
+			}
+
+			// other repeated fields are combined into one comma-separated list
+			let mut existing = None;
+			for (k, v) in headers.iter_mut() {
+				if k.eq_ignore_ascii_case(key) {
+					v.push_str(", ");
+					v.push_str(val);
+					existing = Some(k.clone());
+					break;
+				}
+			}
+			if existing.is_some() {
+				return Ok(existing.unwrap());
+			}
+
 			headers.insert(key.to_string(), val.to_string());
 
-            Ok(())
+            Ok(key.to_string())
         } else {
             return Err(HttpParserError::InvalidString);
         }
     }
 
+	/// Both `Transfer-Encoding` and `Content-Length` is how requests get
+	/// smuggled past proxies, so requests like that are refused (RFC 7230,
+	/// section 3.3.3). In a response, `Transfer-Encoding` wins, and the
+	/// connection can't be trusted for another one.
+	fn check_framing(&mut self) -> Result<(), HttpParserError> {
+		let headers = match self.msg {
+			HttpMessage::Request(ref mut r) => {
+				if r.get_raw_header("Transfer-Encoding").is_some() && r.get_raw_header("Content-Length").is_some() {
+					return Err(HttpParserError::HeaderError);
+				}
+				// without chunked last, a request body has no end (RFC 7230, section 3.3.3)
+				if r.get_raw_header("Transfer-Encoding").is_some() && !r.is_chunked() {
+					return Err(HttpParserError::HeaderError);
+				}
+				&mut r.headers
+			}
+			HttpMessage::Response(ref mut r) => {
+				if r.get_raw_header("Transfer-Encoding").is_some() && r.get_raw_header("Content-Length").is_some() {
+					let keys: Vec<String> = r.headers.keys().filter(|k| k.eq_ignore_ascii_case("Content-Length") || k.eq_ignore_ascii_case("Connection")).cloned().collect();
+					for k in keys {
+						r.headers.remove(&k);
+					}
+					r.headers.insert("Connection".to_string(), "close".to_string());
+					return Ok(());
+				}
+				&mut r.headers
+			}
+		};
+
+		check_content_length(headers)
+	}
+
+	fn parse_folded_line(msg: &mut HttpMessage, last_header: &Option<String>, handling: ObsFoldHandling, line: &str) -> Result<(), HttpParserError> {
+		if handling == ObsFoldHandling::Reject {
+			return Err(HttpParserError::HeaderError);
+		}
+
+		// a fold right after the start line has nothing to continue
+		let key = match *last_header {
+			Some(ref k) => k,
+			None => { return Err(HttpParserError::HeaderError); }
+		};
+
+		let (headers, repeated) = match *msg {
+			HttpMessage::Request(ref mut r) => (&mut r.headers, &mut r.repeated_headers),
+			HttpMessage::Response(ref mut r) => (&mut r.headers, &mut r.repeated_headers)
+		};
+		let val = match repeated.last_mut() {
+			Some(h) if h.0.eq_ignore_ascii_case(key) => Some(&mut h.1),
+			_ => headers.get_mut(key)
+		};
+		if let Some(val) = val {
+			let cont = trim_ows(line);
+			if val.len() > 0 && cont.len() > 0 {
+				val.push(' ');
+			}
+			val.push_str(cont);
+		}
+
+		Ok(())
+	}
+
 	pub fn get_message(&self) -> &HttpMessage {
 		&self.msg
 	}
@@ -270,12 +466,146 @@ impl HttpParser {
 	}
 }
 
+/// `Content-Length` has to be one decimal number. Repeated fields that
+/// agree are merged into a list like `5, 5`, which is collapsed back into
+/// one; anything else can't be trusted to frame the body (RFC 7230,
+/// section 3.3.2).
+fn check_content_length(headers: &mut BTreeMap<String, String>) -> Result<(), HttpParserError> {
+	let key = match headers.keys().find(|k| k.eq_ignore_ascii_case("Content-Length")) {
+		Some(k) => k.clone(),
+		None => { return Ok(()); }
+	};
+
+	let len = {
+		let mut lengths = headers[&key].split(',').map(trim_ows);
+		let first = lengths.next().unwrap_or("");
+		if first.len() == 0 || !first.bytes().all(|c| c.is_ascii_digit()) || first.parse::<u32>().is_err() {
+			return Err(HttpParserError::HeaderError);
+		}
+		if lengths.any(|l| l != first) {
+			return Err(HttpParserError::HeaderError);
+		}
+		first.to_string()
+	};
+	headers.insert(key, len);
+	Ok(())
+}
+
+/// Parse a message that arrives whole in one datagram, like the HTTPU
+/// messages of SSDP. A request or a response, depending on whether it
+/// starts with a status line. The end of the datagram ends the message: a
+/// response without a length gets the rest as its body, while a datagram
+/// ending inside the headers or before the announced body length is
+/// `Truncated`. Bytes after the body are ignored.
+pub fn parse_datagram(datagram: &[u8]) -> Result<HttpMessage, HttpParserError> {
+	let mut parser = if datagram.starts_with(b"HTTP/") {
+		HttpParser::new_response()
+	} else {
+		HttpParser::new_request()
+	};
+
+	try!(parser.parse_partial(datagram));
+	if !parser.are_headers_parsed() || !(parser.is_complete() || parser.is_close_delimited()) {
+		return Err(HttpParserError::Truncated);
+	}
+
+	Ok(parser.msg)
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ChunkState {
+	Size,
+	Data(usize),
+	DataEnd,
+	Trailer,
+	Done,
+}
+
+/// Decoder for the chunked transfer coding (RFC 7230, section 4.1).
+/// Chunk extensions and trailer fields are skipped.
+struct ChunkedBody {
+	state: ChunkState,
+	line: Vec<u8>,
+}
+
+/// Longest chunk size or trailer line we're willing to buffer.
+const MAX_CHUNK_LINE: usize = 4096;
+
+impl ChunkedBody {
+	fn new() -> ChunkedBody {
+		ChunkedBody {
+			state: ChunkState::Size,
+			line: Vec::new()
+		}
+	}
+
+	fn is_done(&self) -> bool {
+		self.state == ChunkState::Done
+	}
+
+	/// Append the chunk data in `data` to `body`. Returns how many bytes
+	/// were used, which is less than `data.len()` once the body ends.
+	fn decode(&mut self, data: &[u8], body: &mut Vec<u8>) -> Result<usize, HttpParserError> {
+		let mut i = 0;
+		while i < data.len() {
+			match self.state {
+				ChunkState::Done => break,
+				ChunkState::Data(n) => {
+					let take = ::core::cmp::min(n, data.len() - i);
+					body.extend_from_slice(&data[i..(i + take)]);
+					i += take;
+					self.state = if take == n { ChunkState::DataEnd } else { ChunkState::Data(n - take) };
+				}
+				_ => {
+					let b = data[i];
+					i += 1;
+					if b != b'\n' {
+						if self.line.len() >= MAX_CHUNK_LINE { return Err(HttpParserError::ChunkError); }
+						self.line.push(b);
+						continue;
+					}
+
+					if self.line.last() == Some(&b'\r') {
+						self.line.pop();
+					}
+					self.state = try!(self.line_done());
+					self.line.clear();
+				}
+			}
+		}
+
+		Ok(i)
+	}
+
+	fn line_done(&self) -> Result<ChunkState, HttpParserError> {
+		match self.state {
+			ChunkState::Size => {
+				let line = try!(from_utf8(&self.line).map_err(|_| HttpParserError::ChunkError));
+				let size = match line.find(';') {
+					Some(ext) => &line[..ext],
+					None => line
+				};
+				match usize::from_str_radix(trim_ows(size), 16) {
+					Ok(0) => Ok(ChunkState::Trailer),
+					Ok(n) => Ok(ChunkState::Data(n)),
+					Err(_) => Err(HttpParserError::ChunkError)
+				}
+			}
+			ChunkState::DataEnd if self.line.len() == 0 => Ok(ChunkState::Size),
+			ChunkState::DataEnd => Err(HttpParserError::ChunkError),
+			ChunkState::Trailer if self.line.len() == 0 => Ok(ChunkState::Done),
+			s => Ok(s)
+		}
+	}
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use collections::vec::Vec;
-	use super::super::{HttpRequestMessage};
+    use alloc::vec::Vec;
+    use alloc::string::ToString;
+	use super::super::{HttpRequestMessage, HttpHeaders, HttpVersion, RequestTargetForm};
 
 	use std::io::prelude::*;
 	use std::net::TcpStream;
@@ -331,6 +661,310 @@ Connection: close\r\n\
 
 
 	#[test]
+	pub fn test_malformed_request_line() {
+		let lines = [
+			("GET HTTP/1.1", HttpHeadError::InvalidRequestLine),
+			("GET", HttpHeadError::InvalidRequestLine),
+			("GET /", HttpHeadError::InvalidRequestLine),
+			(" / HTTP/1.1", HttpHeadError::InvalidMethod),
+			("GET / HTTP/1.1 ", HttpHeadError::InvalidRequestLine),
+			("BREW / HTTP/1.1", HttpHeadError::UnknownMethod),
+			("GET index.html HTTP/1.1", HttpHeadError::InvalidRequestTarget)
+		];
+		for &(line, err) in lines.iter() {
+			let mut parser = HttpParser::new_request();
+			let msg = format!("{}\r\n\r\n", line);
+			match parser.parse_bytes(msg.as_bytes()) {
+				Err(HttpParserError::Head(e)) => assert_eq!(e, err, "{:?}", line),
+				r => panic!("unexpected result for {:?}: {:?}", line, r)
+			}
+		}
+
+		for &(line, err) in [("HTTP/1.1 200", HttpHeadError::InvalidStatusLine), ("HTTP/1.1 OK OK", HttpHeadError::InvalidStatusLine), ("HTTP/2 200 OK", HttpHeadError::InvalidVersion)].iter() {
+			let mut parser = HttpParser::new_response();
+			let msg = format!("{}\r\n\r\n", line);
+			match parser.parse_bytes(msg.as_bytes()) {
+				Err(HttpParserError::Head(e)) => assert_eq!(e, err, "{:?}", line),
+				r => panic!("unexpected result for {:?}: {:?}", line, r)
+			}
+		}
+
+		let mut parser = HttpParser::new_request();
+		parser.parse_bytes(b"M-SEARCH * HTTP/1.1\r\n\r\n").unwrap();
+		let req = parser.get_request().unwrap();
+		assert_eq!(req.url, "*");
+		assert_eq!(req.http_version, HttpVersion::Http11);
+		assert_eq!(req.target_form(), Some(RequestTargetForm::Asterisk));
+	}
+
+	#[test]
+	pub fn test_parse_partial() {
+		let msg = b"POST /a HTTP/1.1\r\nContent-Length: 4\r\n\r\nbodyGET /b HTTP/1.1\r\n\r\n";
+
+		let mut parser = HttpParser::new_request();
+		let (used, state) = parser.parse_partial(&msg[..10]).unwrap();
+		assert_eq!(used, 10);
+		match state { HttpParserState::MoreDataRequired => (), s => panic!("{:?}", s) }
+
+		let (used, state) = parser.parse_partial(&msg[10..]).unwrap();
+		match state { HttpParserState::Complete => (), s => panic!("{:?}", s) }
+		assert!(parser.is_complete());
+		assert_eq!(parser.get_request().unwrap().body, b"body");
+
+		let rest = &msg[(10 + used)..];
+		assert_eq!(rest, b"GET /b HTTP/1.1\r\n\r\n");
+
+		let mut parser = HttpParser::new_request();
+		let (used, _) = parser.parse_partial(rest).unwrap();
+		assert_eq!(used, rest.len());
+		assert!(parser.is_complete());
+		assert_eq!(parser.get_request().unwrap().url, "/b");
+
+		// without a Content-Length, a response runs until the connection closes
+		let mut parser = HttpParser::new_response();
+		parser.parse_bytes(b"HTTP/1.1 200 OK\r\n\r\nsome").unwrap();
+		parser.parse_bytes(b" more").unwrap();
+		assert!(!parser.is_complete());
+		assert_eq!(parser.get_response().unwrap().body, b"some more");
+	}
+
+	#[test]
+	pub fn test_http_versions() {
+		let mut parser = HttpParser::new_request();
+		parser.parse_bytes(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+		let req = parser.get_request().unwrap();
+		assert_eq!(req.http_version, HttpVersion::Http10);
+		assert!(!req.keep_alive());
+		assert!(req.is_host_valid());
+
+		let mut parser = HttpParser::new_request();
+		parser.parse_bytes(b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").unwrap();
+		assert!(parser.get_request().unwrap().keep_alive());
+
+		let mut parser = HttpParser::new_request();
+		parser.parse_bytes(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+		let req = parser.get_request().unwrap();
+		assert!(req.keep_alive());
+		assert!(!req.is_host_valid());
+
+		let mut parser = HttpParser::new_request();
+		parser.parse_bytes(b"GET / HTTP/1.1\r\nhost: example.com\r\nConnection: upgrade, close\r\n\r\n").unwrap();
+		let req = parser.get_request().unwrap();
+		assert!(!req.keep_alive());
+		assert!(req.is_host_valid());
+
+		let mut parser = HttpParser::new_request();
+		parser.parse_bytes(b"GET / HTTP/1.1\r\nHost: a.com\r\nHOST: b.com\r\n\r\n").unwrap();
+		let req = parser.get_request().unwrap();
+		assert_eq!(req.get_raw_header("host"), Some(&"a.com".to_string()));
+		assert_eq!(req.repeated_headers, vec![("HOST".to_string(), "b.com".to_string())]);
+		assert!(!req.is_host_valid());
+
+		let mut parser = HttpParser::new_request();
+		parser.parse_bytes(b"GET / HTTP/1.2\r\n\r\n").unwrap();
+		assert_eq!(parser.get_request().unwrap().http_version, HttpVersion::Http11);
+
+		let mut parser = HttpParser::new_response();
+		parser.parse_bytes(b"HTTP/1.0 200 OK\r\n\r\n").unwrap();
+		assert_eq!(parser.get_response().unwrap().http_version, HttpVersion::Http10);
+
+		let mut parser = HttpParser::new_response();
+		parser.parse_bytes(b"HTTP/1 200 OK\r\n\r\n").unwrap();
+		assert_eq!(parser.get_response().unwrap().http_version, HttpVersion::Http10);
+	}
+
+	#[test]
+	pub fn test_header_whitespace() {
+		let msg = b"GET / HTTP/1.1\r\nHost:example.com\r\nKey:  value \t\r\n\r\n";
+		let mut parser = HttpParser::new_request();
+		parser.parse_bytes(msg).unwrap();
+
+		let req = parser.get_request().unwrap();
+		assert_eq!(req.get_raw_header("Host"), Some(&"example.com".to_string()));
+		assert_eq!(req.get_raw_header("Key"), Some(&"value".to_string()));
+
+		let mut parser = HttpParser::new_request();
+		assert!(parser.parse_bytes(b"GET / HTTP/1.1\r\nHost : example.com\r\n\r\n").is_err());
+
+		let mut parser = HttpParser::new_request();
+		assert!(parser.parse_bytes(b"GET / HTTP/1.1\r\nHo(st: example.com\r\n\r\n").is_err());
+	}
+
+	#[test]
+	pub fn test_repeated_headers() {
+		let msg = b"HTTP/1.1 200 OK\r\nSet-Cookie: a=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT\r\nVary: Accept\r\nset-cookie: b=2\r\n ; Path=/\r\nVary: Cookie\r\nContent-Length: 0\r\n\r\n";
+		let mut parser = HttpParser::new_response();
+		parser.parse_bytes(msg).unwrap();
+		let resp = parser.get_response().unwrap();
+		assert_eq!(resp.get_raw_header("Set-Cookie"), Some(&"a=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT".to_string()));
+		assert_eq!(resp.repeated_headers, vec![("set-cookie".to_string(), "b=2 ; Path=/".to_string())]);
+		assert_eq!(resp.get_raw_header("Vary"), Some(&"Accept, Cookie".to_string()));
+		assert!(resp.to_bytes().windows(28).any(|w| w == b"\r\nset-cookie: b=2 ; Path=/\r\n"));
+	}
+
+	#[test]
+	pub fn test_obs_fold() {
+		let msg = b"HTTP/1.1 200 OK\r\nX-Long: first\r\n \t second\r\n\tthird\r\nServer: test\r\n\r\n";
+
+		let mut parser = HttpParser::new_response();
+		parser.parse_bytes(msg).unwrap();
+		let resp = parser.get_response().unwrap();
+		assert_eq!(resp.get_raw_header("X-Long"), Some(&"first second third".to_string()));
+		assert_eq!(resp.get_raw_header("Server"), Some(&"test".to_string()));
+
+		let mut parser = HttpParser::new_response();
+		parser.set_obs_fold(ObsFoldHandling::Reject);
+		assert!(parser.parse_bytes(msg).is_err());
+
+		// requests reject folding unless asked otherwise
+		let msg = b"GET / HTTP/1.1\r\nX-Long: first\r\n second\r\n\r\n";
+		let mut parser = HttpParser::new_request();
+		assert!(parser.parse_bytes(msg).is_err());
+
+		let mut parser = HttpParser::new_request();
+		parser.set_obs_fold(ObsFoldHandling::Unfold);
+		parser.parse_bytes(msg).unwrap();
+		assert_eq!(parser.get_request().unwrap().get_raw_header("X-Long"), Some(&"first second".to_string()));
+
+		// nothing to continue right after the request line
+		let mut parser = HttpParser::new_request();
+		parser.set_obs_fold(ObsFoldHandling::Unfold);
+		assert!(parser.parse_bytes(b"GET / HTTP/1.1\r\n Host: example.com\r\n\r\n").is_err());
+	}
+
+	#[test]
+	pub fn test_chunked_body() {
+		let msg = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, chunked\r\nContent-Length: 100\r\n\r\n\
+4\r\nWiki\r\n5 ; name=value\r\npedia\r\n0\r\nExpires: never\r\n\r\nHTTP/1.1";
+
+		// byte by byte, to hit every state boundary
+		let mut parser = HttpParser::new_response();
+		let mut used = 0;
+		for b in msg.iter() {
+			used += parser.parse_partial(&[*b]).unwrap().0;
+		}
+		assert!(parser.is_complete());
+		assert_eq!(used, msg.len() - b"HTTP/1.1".len());
+		assert_eq!(parser.get_response().unwrap().body, b"Wikipedia");
+		// Content-Length is ignored, and the connection not reused
+		assert!(parser.get_response().unwrap().get_raw_header("Content-Length").is_none());
+		assert!(!parser.get_response().unwrap().keep_alive());
+
+		// a request like that could be smuggled past a proxy
+		let mut parser = HttpParser::new_request();
+		match parser.parse_bytes(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n") {
+			Err(HttpParserError::HeaderError) => (),
+			r => panic!("unexpected result {:?}", r)
+		}
+
+		for te in &["gzip", "chunked, gzip"] {
+			let mut parser = HttpParser::new_request();
+			match parser.parse_bytes(format!("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: {}\r\n\r\nGET / HTTP/1.1\r\n\r\n", te).as_bytes()) {
+				Err(HttpParserError::HeaderError) => (),
+				r => panic!("unexpected result {:?} for {:?}", r, te)
+			}
+		}
+
+		let mut parser = HttpParser::new_response();
+		match parser.parse_bytes(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n") {
+			Err(HttpParserError::ChunkError) => (),
+			r => panic!("unexpected result {:?}", r)
+		}
+
+		let mut parser = HttpParser::new_response();
+		match parser.parse_bytes(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nabc\r\n") {
+			Err(HttpParserError::ChunkError) => (),
+			r => panic!("unexpected result {:?}", r)
+		}
+
+		// no body in a response to HEAD, or a 204
+		let mut parser = HttpParser::new_response_to(HttpMethod::Head);
+		parser.parse_bytes(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n").unwrap();
+		assert!(parser.is_complete());
+		let mut parser = HttpParser::new_response();
+		parser.parse_bytes(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+		assert!(parser.is_complete());
+		let mut parser = HttpParser::new_response();
+		parser.parse_bytes(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+		assert!(!parser.is_complete());
+		assert!(parser.is_close_delimited());
+	}
+
+	#[test]
+	pub fn test_content_length() {
+		for len in &["abc", "-1", "+5", "5, 6", "", "99999999999"] {
+			let mut parser = HttpParser::new_request();
+			match parser.parse_bytes(format!("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: {}\r\n\r\n", len).as_bytes()) {
+				Err(HttpParserError::HeaderError) => (),
+				r => panic!("unexpected result {:?} for {:?}", r, len)
+			}
+		}
+
+		let mut parser = HttpParser::new_response();
+		match parser.parse_bytes(b"HTTP/1.1 200 OK\r\nContent-Length: 5x\r\n\r\nhello") {
+			Err(HttpParserError::HeaderError) => (),
+			r => panic!("unexpected result {:?}", r)
+		}
+
+		// repeats that agree are one length
+		let mut parser = HttpParser::new_request();
+		parser.parse_bytes(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\ncontent-length: 5\r\n\r\nhelloGET").unwrap();
+		assert!(parser.is_complete());
+		let req = parser.get_request().unwrap();
+		assert_eq!(req.get_raw_header("Content-Length"), Some(&"5".to_string()));
+		assert_eq!(req.body, b"hello");
+	}
+
+	#[test]
+	pub fn test_parse_datagram() {
+		let resp = match parse_datagram(b"HTTP/1.1 200 OK\r\nST: ssdp:all\r\nEXT:\r\n\r\n").unwrap() {
+			HttpMessage::Response(r) => r,
+			_ => panic!("not a response")
+		};
+		assert_eq!(resp.response_code, 200);
+		assert_eq!(resp.get_raw_header("ST").unwrap(), "ssdp:all");
+		assert_eq!(resp.body.len(), 0);
+
+		// without a length, the body ends with the datagram
+		match parse_datagram(b"HTTP/1.1 200 OK\r\n\r\nrest").unwrap() {
+			HttpMessage::Response(r) => assert_eq!(r.body, b"rest"),
+			_ => panic!("not a response")
+		}
+
+		match parse_datagram(b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nContent-Length: 2\r\n\r\nabcd").unwrap() {
+			HttpMessage::Request(r) => {
+				assert_eq!(r.method, HttpMethod::Notify);
+				assert_eq!(r.body, b"ab");
+			}
+			_ => panic!("not a request")
+		}
+
+		match parse_datagram(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n").unwrap() {
+			HttpMessage::Response(r) => assert_eq!(r.body, b"abc"),
+			_ => panic!("not a response")
+		}
+
+		let truncated: [&[u8]; 5] = [
+			b"",
+			b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n",
+			b"HTTP/1.1 200 OK\r\nST: ssdp:all",
+			b"NOTIFY * HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc",
+			b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n"
+		];
+		for datagram in truncated.iter() {
+			match parse_datagram(datagram) {
+				Err(HttpParserError::Truncated) => (),
+				r => panic!("{:?}: {:?}", String::from_utf8_lossy(datagram), r.is_ok())
+			}
+		}
+
+		assert!(parse_datagram(b"HTTP/1.1 abc OK\r\n\r\n").is_err());
+	}
+
+	// talks to the network
+	#[test]
+	#[ignore]
+	#[allow(clippy::nonminimal_bool)]
 	pub fn test_http_client() {		
 	    let mut stream = TcpStream::connect("clients3.google.com:80").unwrap();
 
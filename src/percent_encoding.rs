@@ -0,0 +1,344 @@
+#![allow(deprecated, clippy::redundant_field_names, clippy::len_zero, clippy::new_without_default, clippy::match_like_matches_macro)]
+
+use core::fmt;
+use core::str;
+use alloc::string::String;
+use writer::*;
+
+/// Which bytes `percent_encode` escapes. Non-ASCII bytes are always
+/// escaped, whatever the set says.
+///
+/// Apart from the path segment and form sets, the predefined sets leave `%`
+/// alone, so that escapes already in the input stay as they are. Raw data,
+/// that may contain a `%` of its own, needs one of those two.
+pub trait EncodeSet: Clone {
+	fn contains(&self, byte: u8) -> bool;
+}
+
+/// C0 controls and DEL.
+#[derive(Debug, Copy, Clone)]
+pub struct SimpleEncodeSet;
+
+impl EncodeSet for SimpleEncodeSet {
+	fn contains(&self, byte: u8) -> bool {
+		!(0x20..0x7f).contains(&byte)
+	}
+}
+
+/// For queries: the simple set, space, `"`, `#`, `<` and `>`.
+#[derive(Debug, Copy, Clone)]
+pub struct QueryEncodeSet;
+
+impl EncodeSet for QueryEncodeSet {
+	fn contains(&self, byte: u8) -> bool {
+		SimpleEncodeSet.contains(byte) || match byte {
+			b' ' | b'"' | b'#' | b'<' | b'>' => true,
+			_ => false
+		}
+	}
+}
+
+/// For fragments: the simple set, space, `"`, `<`, `>` and `` ` ``.
+#[derive(Debug, Copy, Clone)]
+pub struct FragmentEncodeSet;
+
+impl EncodeSet for FragmentEncodeSet {
+	fn contains(&self, byte: u8) -> bool {
+		SimpleEncodeSet.contains(byte) || match byte {
+			b' ' | b'"' | b'<' | b'>' | b'`' => true,
+			_ => false
+		}
+	}
+}
+
+/// For whole paths: the query set, `` ` ``, `?`, `{` and `}`.
+#[derive(Debug, Copy, Clone)]
+pub struct PathEncodeSet;
+
+impl EncodeSet for PathEncodeSet {
+	fn contains(&self, byte: u8) -> bool {
+		QueryEncodeSet.contains(byte) || match byte {
+			b'`' | b'?' | b'{' | b'}' => true,
+			_ => false
+		}
+	}
+}
+
+/// For a single path segment: the path set, `/` and `%`.
+#[derive(Debug, Copy, Clone)]
+pub struct PathSegmentEncodeSet;
+
+impl EncodeSet for PathSegmentEncodeSet {
+	fn contains(&self, byte: u8) -> bool {
+		PathEncodeSet.contains(byte) || byte == b'/' || byte == b'%'
+	}
+}
+
+/// For usernames and passwords: the path set, `/`, `:`, `;`, `=`, `@`, `[`,
+/// `\`, `]`, `^` and `|`.
+#[derive(Debug, Copy, Clone)]
+pub struct UserinfoEncodeSet;
+
+impl EncodeSet for UserinfoEncodeSet {
+	fn contains(&self, byte: u8) -> bool {
+		PathEncodeSet.contains(byte) || match byte {
+			b'/' | b':' | b';' | b'=' | b'@' | b'[' | b'\\' | b']' | b'^' | b'|' => true,
+			_ => false
+		}
+	}
+}
+
+/// For `application/x-www-form-urlencoded` names and values: everything but
+/// alphanumerics, `*`, `-`, `.` and `_`. `form_urlencode_str` turns spaces
+/// into `+` instead.
+#[derive(Debug, Copy, Clone)]
+pub struct FormUrlencodedEncodeSet;
+
+impl EncodeSet for FormUrlencodedEncodeSet {
+	fn contains(&self, byte: u8) -> bool {
+		!(byte.is_ascii_alphanumeric() || byte == b'*' || byte == b'-' || byte == b'.' || byte == b'_')
+	}
+}
+
+/// Any other set: `CustomEncodeSet::new().with(b' ').with(b'&')`.
+#[derive(Copy, Clone)]
+pub struct CustomEncodeSet {
+	ascii: [bool; 128]
+}
+
+impl CustomEncodeSet {
+	/// The simple set, to add to.
+	pub fn new() -> CustomEncodeSet {
+		CustomEncodeSet::from_set(SimpleEncodeSet)
+	}
+
+	/// A copy of `set`, to change.
+	pub fn from_set<E: EncodeSet>(set: E) -> CustomEncodeSet {
+		let mut ascii = [false; 128];
+		for (byte, encoded) in ascii.iter_mut().enumerate() {
+			*encoded = set.contains(byte as u8);
+		}
+		CustomEncodeSet { ascii: ascii }
+	}
+
+	pub fn with(mut self, byte: u8) -> CustomEncodeSet {
+		if byte < 0x80 {
+			self.ascii[byte as usize] = true;
+		}
+		self
+	}
+
+	pub fn without(mut self, byte: u8) -> CustomEncodeSet {
+		if byte < 0x80 {
+			self.ascii[byte as usize] = false;
+		}
+		self
+	}
+}
+
+impl EncodeSet for CustomEncodeSet {
+	fn contains(&self, byte: u8) -> bool {
+		byte >= 0x80 || self.ascii[byte as usize]
+	}
+}
+
+fn is_encoded<E: EncodeSet>(set: &E, byte: u8) -> bool {
+	byte >= 0x80 || set.contains(byte)
+}
+
+/// `%XX`, with uppercase hex digits.
+pub fn percent_encode_byte(byte: u8) -> &'static str {
+	let i = byte as usize * 3;
+	&ENCODED_BYTES[i..(i + 3)]
+}
+
+static ENCODED_BYTES: &str = "\
+	%00%01%02%03%04%05%06%07%08%09%0A%0B%0C%0D%0E%0F\
+	%10%11%12%13%14%15%16%17%18%19%1A%1B%1C%1D%1E%1F\
+	%20%21%22%23%24%25%26%27%28%29%2A%2B%2C%2D%2E%2F\
+	%30%31%32%33%34%35%36%37%38%39%3A%3B%3C%3D%3E%3F\
+	%40%41%42%43%44%45%46%47%48%49%4A%4B%4C%4D%4E%4F\
+	%50%51%52%53%54%55%56%57%58%59%5A%5B%5C%5D%5E%5F\
+	%60%61%62%63%64%65%66%67%68%69%6A%6B%6C%6D%6E%6F\
+	%70%71%72%73%74%75%76%77%78%79%7A%7B%7C%7D%7E%7F\
+	%80%81%82%83%84%85%86%87%88%89%8A%8B%8C%8D%8E%8F\
+	%90%91%92%93%94%95%96%97%98%99%9A%9B%9C%9D%9E%9F\
+	%A0%A1%A2%A3%A4%A5%A6%A7%A8%A9%AA%AB%AC%AD%AE%AF\
+	%B0%B1%B2%B3%B4%B5%B6%B7%B8%B9%BA%BB%BC%BD%BE%BF\
+	%C0%C1%C2%C3%C4%C5%C6%C7%C8%C9%CA%CB%CC%CD%CE%CF\
+	%D0%D1%D2%D3%D4%D5%D6%D7%D8%D9%DA%DB%DC%DD%DE%DF\
+	%E0%E1%E2%E3%E4%E5%E6%E7%E8%E9%EA%EB%EC%ED%EE%EF\
+	%F0%F1%F2%F3%F4%F5%F6%F7%F8%F9%FA%FB%FC%FD%FE%FF";
+
+/// Percent-encode `input`. Doesn't allocate: the result is an iterator over
+/// the pieces of the output, and can be written out with `{}`.
+pub fn percent_encode<'a, E: EncodeSet>(input: &'a [u8], set: E) -> PercentEncode<'a, E> {
+	PercentEncode {
+		bytes: input,
+		set: set
+	}
+}
+
+/// Percent-encode the UTF-8 bytes of `input`.
+pub fn utf8_percent_encode<'a, E: EncodeSet>(input: &'a str, set: E) -> PercentEncode<'a, E> {
+	percent_encode(input.as_bytes(), set)
+}
+
+/// See `percent_encode`.
+#[derive(Clone)]
+pub struct PercentEncode<'a, E: EncodeSet> {
+	bytes: &'a [u8],
+	set: E
+}
+
+impl<'a, E: EncodeSet> Iterator for PercentEncode<'a, E> {
+	type Item = &'a str;
+
+	/// An escaped byte, or the longest run of bytes left as they are.
+	fn next(&mut self) -> Option<&'a str> {
+		let first = match self.bytes.first() {
+			Some(&first) => first,
+			None => { return None; }
+		};
+		if is_encoded(&self.set, first) {
+			self.bytes = &self.bytes[1..];
+			return Some(percent_encode_byte(first));
+		}
+
+		let run = self.bytes.iter().position(|&b| is_encoded(&self.set, b)).unwrap_or(self.bytes.len());
+		let (unchanged, rest) = self.bytes.split_at(run);
+		self.bytes = rest;
+		// only ASCII is left unencoded
+		Some(str::from_utf8(unchanged).unwrap())
+	}
+}
+
+impl<'a, E: EncodeSet> fmt::Display for PercentEncode<'a, E> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for piece in self.clone() {
+			try!(f.write_str(piece));
+		}
+		Ok(())
+	}
+}
+
+/// Percent-encode `input`, and push the result to `output`.
+pub fn percent_encode_to<E: EncodeSet>(input: &[u8], set: E, output: &mut String) {
+	for piece in percent_encode(input, set) {
+		output.push_str(piece);
+	}
+}
+
+/// Percent-encode `input` into `buf`. Returns the number of bytes written;
+/// fails if the buffer can't hold them.
+pub fn percent_encode_to_slice<E: EncodeSet>(input: &[u8], set: E, buf: &mut [u8]) -> Result<usize, HttpWriteError> {
+	let mut sink = SliceSink::new(buf);
+	for piece in percent_encode(input, set) {
+		try!(sink.write_bytes(piece.as_bytes()));
+	}
+	Ok(sink.written())
+}
+
+/// Percent-encode `input` into a new string.
+pub fn percent_encode_str<E: EncodeSet>(input: &str, set: E) -> String {
+	let mut output = String::with_capacity(input.len());
+	percent_encode_to(input.as_bytes(), set, &mut output);
+	output
+}
+
+/// Encode a form name or value, spaces as `+`. `percent_decode_str` decodes
+/// it.
+pub fn form_urlencode_str(input: &str) -> String {
+	let mut output = String::with_capacity(input.len());
+	for piece in utf8_percent_encode(input, FormUrlencodedEncodeSet) {
+		output.push_str(if piece == "%20" { "+" } else { piece });
+	}
+	output
+}
+
+/// A form body, `name=value&other=value`, as `parse_urlencoded_form` reads
+/// it.
+pub fn form_urlencode(pairs: &[(&str, &str)]) -> String {
+	let mut output = String::new();
+	for &(name, value) in pairs {
+		if output.len() > 0 {
+			output.push('&');
+		}
+		output.push_str(&form_urlencode_str(name));
+		output.push('=');
+		output.push_str(&form_urlencode_str(value));
+	}
+	output
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use http::*;
+	use alloc::vec::Vec;
+	use alloc::string::String;
+	use alloc::string::ToString;
+
+	#[test]
+	pub fn test_percent_encode() {
+		let input = "/a b/\"c\"?d#e{f}`%ü\u{7f}";
+		assert_eq!(percent_encode_str(input, SimpleEncodeSet), "/a b/\"c\"?d#e{f}`%%C3%BC%7F");
+		assert_eq!(percent_encode_str(input, QueryEncodeSet), "/a%20b/%22c%22?d%23e{f}`%%C3%BC%7F");
+		assert_eq!(percent_encode_str(input, FragmentEncodeSet), "/a%20b/%22c%22?d#e{f}%60%%C3%BC%7F");
+		assert_eq!(percent_encode_str(input, PathEncodeSet), "/a%20b/%22c%22%3Fd%23e%7Bf%7D%60%%C3%BC%7F");
+		assert_eq!(percent_encode_str(input, PathSegmentEncodeSet), "%2Fa%20b%2F%22c%22%3Fd%23e%7Bf%7D%60%25%C3%BC%7F");
+		assert_eq!(percent_encode_str("user@host:pw;x=[1]", UserinfoEncodeSet), "user%40host%3Apw%3Bx%3D%5B1%5D");
+		assert_eq!(percent_encode_str("a*b-c.d_e~f+g", FormUrlencodedEncodeSet), "a*b-c.d_e%7Ef%2Bg");
+
+		// custom sets can't leave non-ASCII bytes as they are
+		let set = CustomEncodeSet::from_set(PathEncodeSet).with(b'&').without(b' ').without(0xc3);
+		assert_eq!(percent_encode_str("a b&c?ü", set), "a b%26c%3F%C3%BC");
+		assert_eq!(percent_encode_str("a\nb", CustomEncodeSet::new()), "a%0Ab");
+
+		// pieces, without allocating
+		let pieces: Vec<&str> = percent_encode(b"ab cd", QueryEncodeSet).collect();
+		assert_eq!(pieces, ["ab", "%20", "cd"]);
+		assert_eq!(format!("<{}>", utf8_percent_encode("x y", QueryEncodeSet)), "<x%20y>");
+		assert_eq!(percent_encode(b"", QueryEncodeSet).next(), None);
+
+		let mut buf = [0; 8];
+		assert_eq!(percent_encode_to_slice(b"a b", QueryEncodeSet, &mut buf), Ok(5));
+		assert_eq!(&buf[..5], b"a%20b");
+		assert_eq!(percent_encode_to_slice(b"a b c", QueryEncodeSet, &mut buf), Err(HttpWriteError::BufferTooSmall));
+
+		let mut output = "x=".to_string();
+		percent_encode_to(&[0, 0xff], SimpleEncodeSet, &mut output);
+		assert_eq!(output, "x=%00%FF");
+		for byte in 0..256 {
+			assert_eq!(percent_encode_byte(byte as u8), format!("%{:02X}", byte));
+		}
+	}
+
+	#[test]
+	pub fn test_percent_encode_round_trip() {
+		let inputs = ["", "plain", "a b+c&d=e", "/path/to?x#y", "ünïcödé ✓", "tab\tnew\nline\u{0}", "{`^|\\\"<>}"];
+		for input in inputs.iter() {
+			assert_eq!(lossy_utf8_percent_decode(percent_encode_str(input, SimpleEncodeSet).as_bytes()), *input);
+			assert_eq!(lossy_utf8_percent_decode(percent_encode_str(input, QueryEncodeSet).as_bytes()), *input);
+			assert_eq!(lossy_utf8_percent_decode(percent_encode_str(input, FragmentEncodeSet).as_bytes()), *input);
+			assert_eq!(lossy_utf8_percent_decode(percent_encode_str(input, PathEncodeSet).as_bytes()), *input);
+			assert_eq!(lossy_utf8_percent_decode(percent_encode_str(input, UserinfoEncodeSet).as_bytes()), *input);
+			assert_eq!(percent_decode_str(&form_urlencode_str(input)), *input);
+		}
+
+		// raw data with escapes of its own needs a set with `%` in it
+		let bytes: Vec<u8> = (0..256).map(|b| b as u8).chain(b"%41%zz%".iter().cloned()).collect();
+		let mut encoded = String::new();
+		percent_encode_to(&bytes, PathSegmentEncodeSet, &mut encoded);
+		assert!(encoded.bytes().all(|b| b.is_ascii_graphic()));
+		assert_eq!(percent_decode(encoded.as_bytes()), bytes);
+		assert_eq!(percent_decode(percent_encode_str("100%41", FormUrlencodedEncodeSet).as_bytes()), b"100%41");
+
+		let body = form_urlencode(&[("ssid", "rock & roll"), ("pass", "a+b=c%")]);
+		assert_eq!(body, "ssid=rock+%26+roll&pass=a%2Bb%3Dc%25");
+		let form = parse_urlencoded_form(&body);
+		assert_eq!(form.get("ssid").unwrap(), "rock & roll");
+		assert_eq!(form.get("pass").unwrap(), "a+b=c%");
+	}
+}
@@ -1,8 +1,10 @@
+#![allow(deprecated, bare_trait_objects, clippy::redundant_field_names, clippy::len_zero, clippy::needless_return, clippy::unnecessary_unwrap, clippy::new_without_default)]
+
 use http::*;
-use collections::vec::*;
-use collections::String;
-use collections::string::ToString;
-use collections::BTreeMap;
+use alloc::vec::*;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::collections::BTreeMap;
 use alloc::boxed::Box;
 
 #[derive(Debug, Eq, PartialEq)]
@@ -15,6 +17,10 @@ pub enum HttpRouteError {
 pub trait HttpRoute {
     fn try(&self, msg: &HttpRequestMessage) -> Result<bool, HttpRouteError>;
     fn execute(&self, msg: &HttpRequestMessage) -> Result<HttpResponseMessage, HttpRouteError>;
+
+    /// The response to `msg` has been handed to the transport, after the
+    /// server loop routed it with `HttpConnection::poll_routed`.
+    fn responded(&self, _msg: &HttpRequestMessage, _resp: &HttpResponseMessage) {}
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -107,10 +113,11 @@ impl DynamicUrl {
                         is_match = false;
                         break;
                     } else {
-                        let m = u.splitn(1, "/").collect::<Vec<_>>();
-                        if m.len() == 2 {
+                        let m = u.splitn(2, "/").collect::<Vec<_>>();
+                        if m.len() == 2 && i < self.parts.len() - 1 {
+                            // the next part starts with the slash
                             url_match.vars.insert(var_name.clone(), m[0].to_string());
-                            u = m[1];
+                            u = &u[m[0].len()..];
                         } else if u.ends_with("/") {
                             url_match.vars.insert(var_name.clone(), u[..(u.len()-1)].to_string());
                             u = &u[..(u.len()-1)];
@@ -163,6 +170,7 @@ impl DynamicUrlMatch {
 pub struct HttpRouteDynamicUrl {
     pub url: DynamicUrl,
     pub methods: Vec<HttpMethod>,
+    #[allow(clippy::type_complexity)]
     pub action: Box<Fn(&HttpRequestMessage, &DynamicUrlMatch) -> HttpResponseMessage + Send + Sync>,
 }
 
@@ -232,10 +240,11 @@ impl HttpRoute for HttpRouteStaticUrl {
     }
 }
 
+#[allow(clippy::borrowed_box)]
 pub fn http_router<'a>(routes: &'a [Box<HttpRoute + Send + Sync + 'static>], req: &HttpRequestMessage) -> Result<&'a Box<HttpRoute + Send + Sync + 'static>, HttpRouteError> {
     for route in routes {
-        let t = route.try(&req);
-        if t.is_ok() && t.unwrap() == true {
+        let t = route.try(req);
+        if t.is_ok() && t.unwrap() {
             return Ok(route);
         }
     }
@@ -243,11 +252,65 @@ pub fn http_router<'a>(routes: &'a [Box<HttpRoute + Send + Sync + 'static>], req
     return Err(HttpRouteError::NoRouteFound);
 }
 
+/// An ordered list of routes; the first one that accepts a request handles it.
+pub struct HttpRouter {
+    routes: Vec<Box<HttpRoute + Send + Sync + 'static>>,
+}
+
+impl HttpRouter {
+    pub fn new() -> HttpRouter {
+        HttpRouter {
+            routes: Vec::new()
+        }
+    }
+
+    pub fn from_routes(routes: Vec<Box<HttpRoute + Send + Sync + 'static>>) -> HttpRouter {
+        HttpRouter {
+            routes: routes
+        }
+    }
+
+    pub fn add_route<R: HttpRoute + Send + Sync + 'static>(&mut self, route: R) {
+        self.routes.push(Box::new(route));
+    }
+
+    pub fn routes(&self) -> &[Box<HttpRoute + Send + Sync + 'static>] {
+        &self.routes
+    }
+
+    #[allow(clippy::borrowed_box)]
+    pub fn find_route(&self, req: &HttpRequestMessage) -> Result<&Box<HttpRoute + Send + Sync + 'static>, HttpRouteError> {
+        http_router(&self.routes, req)
+    }
+
+    /// Route the request and execute the matching route.
+    pub fn handle(&self, req: &HttpRequestMessage) -> Result<HttpResponseMessage, HttpRouteError> {
+        let route = try!(self.find_route(req));
+        route.execute(req)
+    }
+
+    /// Tell the route that handled `req` that `resp` went out.
+    pub fn responded(&self, req: &HttpRequestMessage, resp: &HttpResponseMessage) {
+        if let Ok(route) = self.find_route(req) {
+            route.responded(req, resp);
+        }
+    }
+
+    /// Like `handle`, but routing errors are turned into 404 and 500 responses.
+    pub fn respond(&self, req: &HttpRequestMessage) -> HttpResponseMessage {
+        match self.handle(req) {
+            Ok(resp) => resp,
+            Err(HttpRouteError::NoRouteFound) => HttpResponseMessage::new_status(404, "Not Found"),
+            Err(_) => HttpResponseMessage::new_status(500, "Internal Server Error")
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use collections::String;
+    use alloc::string::String;
 
     #[test]
     pub fn test_dynamic_urls() {
@@ -283,4 +346,36 @@ mod tests {
             println!("url match: {:?}", m);
         }
     }
+
+    #[test]
+    pub fn test_dynamic_url_inner_var() {
+        let r = DynamicUrl::parse_str("/user/:id/edit").unwrap();
+
+        let m = r.match_url("/user/5/edit").unwrap();
+        assert_eq!(m.get("id").map(|s| s.as_str()), Some("5"));
+        assert!(r.match_url("/user/5/view").is_none());
+        assert!(r.match_url("/user/5").is_none());
+
+        // a variable at the end still doesn't take more path segments
+        let r = DynamicUrl::parse_str("/test/:id/").unwrap();
+        assert_eq!(r.match_url("/test/123/").unwrap().get("id").map(|s| s.as_str()), Some("123"));
+        assert!(r.match_url("/test/123/xyz").is_none());
+    }
+
+    #[test]
+    pub fn test_router() {
+        let mut router = HttpRouter::new();
+        router.add_route(HttpRouteStaticUrl::new_get("/", |_| HttpResponseMessage::text_utf8("index")));
+        router.add_route(HttpRouteDynamicUrl::new(DynamicUrl::parse_str("/test/:id").unwrap(), HttpMethod::Get, |_, vars| {
+            HttpResponseMessage::text_utf8(vars.get("id").unwrap())
+        }));
+
+        let resp = router.handle(&HttpRequestMessage::new_get("/test/5", "localhost")).unwrap();
+        assert_eq!(resp.body, b"5");
+
+        let resp = router.handle(&HttpRequestMessage::new_get("/", "localhost")).unwrap();
+        assert_eq!(resp.body, b"index");
+
+        assert_eq!(router.handle(&HttpRequestMessage::new_get("/nope", "localhost")).err(), Some(HttpRouteError::NoRouteFound));
+    }
 }
@@ -0,0 +1,775 @@
+#![allow(deprecated, clippy::redundant_field_names, clippy::new_without_default)]
+
+use std::net::{TcpListener, TcpStream, SocketAddr, Shutdown};
+use std::io;
+use std::io::{Read, Write};
+use std::cmp;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+use alloc::vec::Vec;
+
+use connection::*;
+use http::*;
+use router::*;
+use alloc::string::String;
+use alloc::string::ToString;
+
+#[cfg(feature = "tls")]
+use rustls;
+
+/// How often idle kept-alive connections check for a shutdown.
+const SHUTDOWN_POLL_MS: u64 = 250;
+
+#[derive(Debug)]
+pub enum HttpServerError {
+	Io(io::Error),
+}
+
+impl From<io::Error> for HttpServerError {
+	fn from(e: io::Error) -> HttpServerError {
+		HttpServerError::Io(e)
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpServerConfig {
+	/// Largest accepted request line and headers, in bytes.
+	pub max_head_size: usize,
+	/// Largest accepted request body, in bytes.
+	pub max_body_size: usize,
+	/// Threads serving connections. At least one is started.
+	pub workers: usize,
+	/// Accepted connections waiting for a free worker. Beyond that, clients
+	/// get a 503 right away.
+	pub max_queued_connections: usize,
+	/// Seconds sent in `Retry-After` with the 503.
+	pub retry_after: u32,
+	/// Serve several requests per connection, as allowed by the client's
+	/// HTTP version and `Connection` header.
+	pub keep_alive: bool,
+	/// Close a kept-alive connection after this many requests.
+	pub max_requests_per_connection: Option<usize>,
+	/// Longest wait for the first byte of a request, including between
+	/// requests on a kept-alive connection.
+	pub idle_timeout: Option<Duration>,
+	/// Longest wait for each read once a request has started.
+	pub read_timeout: Option<Duration>,
+	/// Longest time to receive a whole request, so clients trickling in
+	/// bytes can't hold a worker.
+	pub request_timeout: Option<Duration>,
+	pub write_timeout: Option<Duration>,
+	/// Serve HTTPS instead of plain HTTP.
+	#[cfg(feature = "tls")]
+	pub tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl Default for HttpServerConfig {
+	fn default() -> HttpServerConfig {
+		HttpServerConfig {
+			max_head_size: 8 * 1024,
+			max_body_size: 64 * 1024,
+			workers: 4,
+			max_queued_connections: 16,
+			retry_after: 1,
+			keep_alive: true,
+			max_requests_per_connection: Some(100),
+			idle_timeout: Some(Duration::from_secs(10)),
+			read_timeout: Some(Duration::from_secs(10)),
+			request_timeout: Some(Duration::from_secs(30)),
+			write_timeout: Some(Duration::from_secs(30)),
+			#[cfg(feature = "tls")]
+			tls: None
+		}
+	}
+}
+
+pub struct HttpServerBuilder {
+	addr: String,
+	router: HttpRouter,
+	config: HttpServerConfig,
+}
+
+impl HttpServerBuilder {
+	pub fn new() -> HttpServerBuilder {
+		HttpServerBuilder {
+			addr: "127.0.0.1:8088".to_string(),
+			router: HttpRouter::new(),
+			config: HttpServerConfig::default()
+		}
+	}
+
+	/// Address to listen on. Port 0 picks a free port, see `HttpServer::local_addr`.
+	pub fn bind(mut self, addr: &str) -> HttpServerBuilder {
+		self.addr = addr.to_string();
+		self
+	}
+
+	pub fn router(mut self, router: HttpRouter) -> HttpServerBuilder {
+		self.router = router;
+		self
+	}
+
+	pub fn route<R: HttpRoute + Send + Sync + 'static>(mut self, route: R) -> HttpServerBuilder {
+		self.router.add_route(route);
+		self
+	}
+
+	pub fn max_head_size(mut self, bytes: usize) -> HttpServerBuilder {
+		self.config.max_head_size = bytes;
+		self
+	}
+
+	pub fn max_body_size(mut self, bytes: usize) -> HttpServerBuilder {
+		self.config.max_body_size = bytes;
+		self
+	}
+
+	pub fn workers(mut self, workers: usize) -> HttpServerBuilder {
+		self.config.workers = workers;
+		self
+	}
+
+	pub fn max_queued_connections(mut self, connections: usize) -> HttpServerBuilder {
+		self.config.max_queued_connections = connections;
+		self
+	}
+
+	pub fn retry_after(mut self, seconds: u32) -> HttpServerBuilder {
+		self.config.retry_after = seconds;
+		self
+	}
+
+	pub fn keep_alive(mut self, keep_alive: bool) -> HttpServerBuilder {
+		self.config.keep_alive = keep_alive;
+		self
+	}
+
+	pub fn max_requests_per_connection(mut self, requests: Option<usize>) -> HttpServerBuilder {
+		self.config.max_requests_per_connection = requests;
+		self
+	}
+
+	pub fn idle_timeout(mut self, timeout: Option<Duration>) -> HttpServerBuilder {
+		self.config.idle_timeout = timeout;
+		self
+	}
+
+	pub fn request_timeout(mut self, timeout: Option<Duration>) -> HttpServerBuilder {
+		self.config.request_timeout = timeout;
+		self
+	}
+
+	pub fn read_timeout(mut self, timeout: Option<Duration>) -> HttpServerBuilder {
+		self.config.read_timeout = timeout;
+		self
+	}
+
+	pub fn write_timeout(mut self, timeout: Option<Duration>) -> HttpServerBuilder {
+		self.config.write_timeout = timeout;
+		self
+	}
+
+	/// Serve HTTPS, see `tls_server_config`.
+	#[cfg(feature = "tls")]
+	pub fn tls(mut self, config: Arc<rustls::ServerConfig>) -> HttpServerBuilder {
+		self.config.tls = Some(config);
+		self
+	}
+
+	/// Bind the listening socket.
+	pub fn build(self) -> io::Result<HttpServer> {
+		let listener = try!(TcpListener::bind(self.addr.as_str()));
+		let local_addr = try!(listener.local_addr());
+
+		Ok(HttpServer {
+			listener: listener,
+			shared: Arc::new(ServerShared {
+				router: self.router,
+				config: self.config,
+				local_addr: local_addr,
+				shutdown: AtomicBool::new(false)
+			})
+		})
+	}
+}
+
+struct ServerShared {
+	router: HttpRouter,
+	config: HttpServerConfig,
+	local_addr: SocketAddr,
+	shutdown: AtomicBool,
+}
+
+pub struct HttpServer {
+	listener: TcpListener,
+	shared: Arc<ServerShared>,
+}
+
+/// Stops a running `HttpServer` from another thread.
+#[derive(Clone)]
+pub struct HttpServerHandle {
+	shared: Arc<ServerShared>,
+}
+
+impl HttpServerHandle {
+	/// Stop accepting new connections. `HttpServer::run` returns once the
+	/// requests already being served are answered; idle kept-alive
+	/// connections are closed.
+	pub fn shutdown(&self) {
+		self.shared.shutdown.store(true, Ordering::SeqCst);
+
+		// wake up the blocking accept()
+		let mut addr = self.shared.local_addr;
+		if addr.ip().is_unspecified() {
+			match addr {
+				SocketAddr::V4(_) => addr.set_ip("127.0.0.1".parse().unwrap()),
+				SocketAddr::V6(_) => addr.set_ip("::1".parse().unwrap())
+			}
+		}
+		let _ = TcpStream::connect(addr);
+	}
+}
+
+impl HttpServer {
+	pub fn local_addr(&self) -> SocketAddr {
+		self.shared.local_addr
+	}
+
+	pub fn handle(&self) -> HttpServerHandle {
+		HttpServerHandle {
+			shared: self.shared.clone()
+		}
+	}
+
+	/// Accept connections and hand them to the worker pool, until shut down.
+	pub fn run(&self) -> io::Result<()> {
+		let (sender, receiver) = sync_channel::<TcpStream>(self.shared.config.max_queued_connections);
+		let receiver = Arc::new(Mutex::new(receiver));
+
+		let mut workers = Vec::new();
+		for i in 0..cmp::max(self.shared.config.workers, 1) {
+			let shared = self.shared.clone();
+			let receiver = receiver.clone();
+			let worker = try!(thread::Builder::new()
+				.name(format!("http-worker-{}", i))
+				.spawn(move || shared.worker(receiver)));
+			workers.push(worker);
+		}
+
+		for stream in self.listener.incoming() {
+			if self.shared.shutdown.load(Ordering::SeqCst) {
+				break;
+			}
+
+			let stream = match stream {
+				Ok(s) => s,
+				Err(_) => { continue; }
+			};
+
+			match sender.try_send(stream) {
+				Ok(()) => (),
+				Err(TrySendError::Full(stream)) => self.shared.reject_busy(stream),
+				Err(TrySendError::Disconnected(_)) => break
+			}
+		}
+
+		// workers finish the queued connections, then see the channel closed
+		drop(sender);
+		for worker in workers {
+			let _ = worker.join();
+		}
+
+		Ok(())
+	}
+
+	/// Serve a single connection on the calling thread.
+	pub fn handle_connection(&self, stream: TcpStream) -> Result<(), HttpServerError> {
+		self.shared.handle_connection(stream)
+	}
+}
+
+/// Serve one connection over `stream` on the calling thread, until either
+/// side closes it. For transports other than TCP, like `MemoryStream`.
+pub fn serve_stream<S: HttpServerStream>(stream: S, router: &HttpRouter, config: &HttpServerConfig) -> Result<(), HttpServerError> {
+	let shutdown = AtomicBool::new(false);
+	let connection = ConnectionLoop {
+		router: router,
+		config: config,
+		shutdown: &shutdown
+	};
+	connection.serve(stream)
+}
+
+impl ServerShared {
+	fn worker(&self, receiver: Arc<Mutex<Receiver<TcpStream>>>) {
+		loop {
+			let stream = {
+				let receiver = receiver.lock().unwrap();
+				receiver.recv()
+			};
+
+			match stream {
+				// whatever goes wrong with one connection, the worker carries on
+				Ok(stream) => { let _ = catch_unwind(AssertUnwindSafe(|| self.handle_connection(stream))); }
+				Err(_) => { return; }
+			}
+		}
+	}
+
+	/// All workers are busy and the queue is full.
+	fn reject_busy(&self, stream: TcpStream) {
+		// answering would need a TLS handshake on the accepting thread
+		#[cfg(feature = "tls")]
+		{
+			if self.config.tls.is_some() {
+				let _ = stream.shutdown(Shutdown::Both);
+				return;
+			}
+		}
+
+		let mut stream = stream;
+		let mut resp = HttpResponseMessage::new_status(503, "Service Unavailable");
+		resp.headers.insert("Retry-After".to_string(), self.config.retry_after.to_string());
+		resp.headers.insert("Content-Length".to_string(), resp.body.len().to_string());
+		resp.headers.insert("Connection".to_string(), "close".to_string());
+
+		// a fresh socket's send buffer takes the whole answer; if it
+		// doesn't, the client gets dropped rather than stall the accept loop
+		if stream.set_nonblocking(true).is_ok() {
+			let _ = stream.write_all(&resp.to_bytes());
+		}
+		let _ = stream.shutdown(Shutdown::Both);
+	}
+
+	fn handle_connection(&self, stream: TcpStream) -> Result<(), HttpServerError> {
+		try!(stream.set_write_timeout(self.config.write_timeout));
+
+		#[cfg(feature = "tls")]
+		{
+			if let Some(ref tls) = self.config.tls {
+				let conn = try!(rustls::ServerConnection::new(tls.clone()).map_err(io::Error::other));
+				return self.connection_loop().serve(rustls::StreamOwned::new(conn, stream));
+			}
+		}
+
+		self.connection_loop().serve(stream)
+	}
+
+	fn connection_loop(&self) -> ConnectionLoop<'_> {
+		ConnectionLoop {
+			router: &self.router,
+			config: &self.config,
+			shutdown: &self.shutdown
+		}
+	}
+}
+
+/// The server side of a single connection.
+struct ConnectionLoop<'a> {
+	router: &'a HttpRouter,
+	config: &'a HttpServerConfig,
+	shutdown: &'a AtomicBool,
+}
+
+impl<'a> ConnectionLoop<'a> {
+	fn connection_config(&self) -> HttpConnectionConfig {
+		HttpConnectionConfig {
+			max_head_size: self.config.max_head_size,
+			max_body_size: self.config.max_body_size,
+			keep_alive: self.config.keep_alive,
+			max_requests: self.config.max_requests_per_connection
+		}
+	}
+
+	/// How long the next read may block.
+	fn read_timeout(&self, conn: &HttpConnection, request_started: Option<Instant>, idle_since: Option<Instant>) -> Option<Duration> {
+		if conn.is_idle() {
+			// wake up now and then, to notice a shutdown
+			let poll = Duration::from_millis(SHUTDOWN_POLL_MS);
+			return Some(match time_left(self.config.idle_timeout, idle_since) {
+				Some(t) if t < poll => t,
+				_ => poll
+			});
+		}
+
+		let remaining = time_left(self.config.request_timeout, request_started);
+
+		match (self.config.read_timeout, remaining) {
+			(Some(r), Some(t)) => Some(if r < t { r } else { t }),
+			(r, None) => r,
+			(None, t) => t
+		}
+	}
+
+	/// Route the pending requests. A route that panics gets the client a 500
+	/// and the connection closed.
+	fn poll_routed(&self, conn: &mut HttpConnection) -> Option<HttpConnectionEvent> {
+		match catch_unwind(AssertUnwindSafe(|| conn.poll_routed(self.router))) {
+			Ok(event) => event,
+			Err(_) => {
+				let mut resp = HttpResponseMessage::new_status(500, "Internal Server Error");
+				resp.headers.insert("Connection".to_string(), "close".to_string());
+				match conn.respond(resp) {
+					Ok(()) => None,
+					// it was the route hearing about a response already sent
+					Err(_) => Some(HttpConnectionEvent::Close)
+				}
+			}
+		}
+	}
+
+	fn serve<S: HttpServerStream>(&self, stream: S) -> Result<(), HttpServerError> {
+		let mut stream = stream;
+
+		let mut conn = HttpConnection::with_config(self.connection_config());
+		let mut request_started = None;
+		let mut idle_since = None;
+		let mut buf = [0; 1024];
+
+		loop {
+			while let Some(event) = self.poll_routed(&mut conn) {
+				if let HttpConnectionEvent::Close = event {
+					stream.close();
+					return Ok(());
+				}
+			}
+
+			if conn.wants_transmit() {
+				let n = conn.poll_transmit(&mut buf);
+				try!(stream.write_all(&buf[..n]));
+				continue;
+			}
+
+			if conn.is_idle() {
+				// between requests is a good time to let go when shutting down
+				if self.shutdown.load(Ordering::SeqCst) {
+					stream.close();
+					return Ok(());
+				}
+				request_started = None;
+				if idle_since.is_none() {
+					idle_since = Some(Instant::now());
+				}
+			} else {
+				idle_since = None;
+			}
+			try!(stream.set_read_timeout(self.read_timeout(&conn, request_started, idle_since)));
+
+			let read_bytes = match stream.read(&mut buf) {
+				Ok(n) => n,
+				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+					let idle_expired = match (self.config.idle_timeout, idle_since) {
+						(Some(t), Some(since)) => since.elapsed() >= t,
+						_ => false
+					};
+					if !conn.is_idle() || idle_expired {
+						conn.timed_out();
+					}
+					continue;
+				}
+				// TLS peers that just drop the connection
+				Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+				Err(e) => { return Err(HttpServerError::Io(e)); }
+			};
+
+			if read_bytes == 0 {
+				conn.peer_closed();
+			} else {
+				if request_started.is_none() {
+					request_started = Some(Instant::now());
+				}
+				conn.received(&buf[..read_bytes]);
+			}
+		}
+	}
+}
+
+/// A byte stream a connection can be served over: TCP, TLS on top of it,
+/// or a `MemoryStream` in tests.
+pub trait HttpServerStream: Read + Write {
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+	/// Done with the connection, close it.
+	fn close(&mut self);
+}
+
+impl HttpServerStream for TcpStream {
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		TcpStream::set_read_timeout(self, timeout)
+	}
+
+	fn close(&mut self) {
+		let _ = self.shutdown(Shutdown::Both);
+	}
+}
+
+#[cfg(feature = "tls")]
+impl HttpServerStream for rustls::StreamOwned<rustls::ServerConnection, TcpStream> {
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.sock.set_read_timeout(timeout)
+	}
+
+	fn close(&mut self) {
+		self.conn.send_close_notify();
+		let _ = self.flush();
+		let _ = self.sock.shutdown(Shutdown::Both);
+	}
+}
+
+/// Time until `timeout` runs out, counting from `since`.
+fn time_left(timeout: Option<Duration>, since: Option<Instant>) -> Option<Duration> {
+	match (timeout, since) {
+		(Some(t), Some(since)) => {
+			let elapsed = since.elapsed();
+			// a zero timeout means "block forever" to set_read_timeout
+			Some(if elapsed >= t { Duration::from_millis(1) } else { t - elapsed })
+		}
+		_ => None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::io::{Read, Write};
+	use std::net::{TcpStream, Shutdown};
+	use std::thread;
+	use std::time::{Duration, Instant};
+	use alloc::vec::Vec;
+	use alloc::string::String;
+
+	fn request(addr: SocketAddr, raw: &[u8]) -> Vec<u8> {
+		let mut stream = TcpStream::connect(addr).unwrap();
+		stream.write_all(raw).unwrap();
+		// done sending, so a kept-alive connection closes after the response;
+		// a busy server may have answered and closed already
+		let _ = stream.shutdown(Shutdown::Write);
+		let mut resp = Vec::new();
+		stream.read_to_end(&mut resp).unwrap();
+		resp
+	}
+
+	#[test]
+	pub fn test_server_routes_and_shuts_down() {
+		let server = HttpServerBuilder::new()
+			.bind("127.0.0.1:0")
+			.route(HttpRouteStaticUrl::new_get("/", |_| HttpResponseMessage::text_utf8("hello")))
+			.max_head_size(256)
+			.build()
+			.unwrap();
+		let addr = server.local_addr();
+		let handle = server.handle();
+		let t = thread::spawn(move || server.run().unwrap());
+
+		let resp = request(addr, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+		assert!(resp.starts_with(b"HTTP/1.1 200 OK\r\n"));
+		assert!(resp.ends_with(b"\r\n\r\nhello"));
+
+		let resp = request(addr, b"GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n");
+		assert!(resp.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+
+		let resp = request(addr, b"GET / HTTP/1.1\r\n\r\n");
+		assert!(resp.starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+
+		let resp = request(addr, b"GET HTTP/1.1\r\n\r\n");
+		assert!(resp.starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+
+		let mut big = b"GET / HTTP/1.1\r\nHost: localhost\r\n".to_vec();
+		for _ in 0..20 {
+			big.extend_from_slice(b"X-Padding: 0123456789\r\n");
+		}
+		big.extend_from_slice(b"\r\n");
+		let resp = request(addr, &big);
+		assert!(resp.starts_with(b"HTTP/1.1 431 "));
+
+		handle.shutdown();
+		t.join().unwrap();
+	}
+
+	#[test]
+	pub fn test_server_saturated() {
+		let server = HttpServerBuilder::new()
+			.bind("127.0.0.1:0")
+			.route(HttpRouteStaticUrl::new_get("/", |_| HttpResponseMessage::text_utf8("hello")))
+			.workers(1)
+			.max_queued_connections(1)
+			.retry_after(5)
+			.idle_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let addr = server.local_addr();
+		let handle = server.handle();
+		let t = thread::spawn(move || server.run().unwrap());
+
+		// one idle client keeps the only worker busy, the next one waits in the queue
+		let busy = TcpStream::connect(addr).unwrap();
+		thread::sleep(Duration::from_millis(100));
+		let queued = TcpStream::connect(addr).unwrap();
+		thread::sleep(Duration::from_millis(100));
+
+		let resp = request(addr, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+		assert!(resp.starts_with(b"HTTP/1.1 503 Service Unavailable\r\n"));
+		assert!(String::from_utf8_lossy(&resp).contains("Retry-After: 5\r\n"));
+
+		drop(busy);
+		drop(queued);
+		handle.shutdown();
+		t.join().unwrap();
+	}
+
+	#[test]
+	pub fn test_server_route_panics() {
+		let server = HttpServerBuilder::new()
+			.bind("127.0.0.1:0")
+			.route(HttpRouteStaticUrl::new_get("/", |_| HttpResponseMessage::text_utf8("hello")))
+			.route(HttpRouteStaticUrl::new_get("/panic", |_| panic!("route failed")))
+			.workers(0)
+			.build()
+			.unwrap();
+		let addr = server.local_addr();
+		let handle = server.handle();
+		let t = thread::spawn(move || server.run().unwrap());
+
+		// the one worker started anyway outlives the panics
+		for _ in 0..2 {
+			let resp = request(addr, b"GET /panic HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+			let resp = String::from_utf8_lossy(&resp);
+			assert!(resp.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+			assert!(resp.contains("Connection: close\r\n"));
+			assert_eq!(resp.matches("HTTP/1.1 ").count(), 1);
+		}
+		let resp = request(addr, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+		assert!(resp.ends_with(b"\r\n\r\nhello"));
+
+		handle.shutdown();
+		t.join().unwrap();
+	}
+
+	#[test]
+	pub fn test_server_timeouts() {
+		let server = HttpServerBuilder::new()
+			.bind("127.0.0.1:0")
+			.route(HttpRouteStaticUrl::new_get("/", |_| HttpResponseMessage::text_utf8("hello")))
+			.idle_timeout(Some(Duration::from_millis(100)))
+			.read_timeout(Some(Duration::from_millis(200)))
+			.request_timeout(Some(Duration::from_millis(300)))
+			.build()
+			.unwrap();
+		let addr = server.local_addr();
+		let handle = server.handle();
+		let t = thread::spawn(move || server.run().unwrap());
+
+		// idle connections are just closed
+		let mut stream = TcpStream::connect(addr).unwrap();
+		let mut resp = Vec::new();
+		stream.read_to_end(&mut resp).unwrap();
+		assert_eq!(resp.len(), 0);
+
+		// a slow client gets a 408 once the request deadline passes, even
+		// though every read on its own is within the read timeout
+		let started = Instant::now();
+		let mut stream = TcpStream::connect(addr).unwrap();
+		for b in b"GET / HTTP/1.1\r\nHost: local".iter() {
+			if stream.write_all(&[*b]).is_err() { break; }
+			thread::sleep(Duration::from_millis(50));
+		}
+		let mut resp = Vec::new();
+		let _ = stream.read_to_end(&mut resp);
+		assert!(resp.starts_with(b"HTTP/1.1 408 Request Timeout\r\n"));
+		assert!(started.elapsed() < Duration::from_secs(2));
+
+		handle.shutdown();
+		t.join().unwrap();
+	}
+
+	#[test]
+	pub fn test_server_keep_alive() {
+		let server = HttpServerBuilder::new()
+			.bind("127.0.0.1:0")
+			.route(HttpRouteStaticUrl::new_get("/", |_| HttpResponseMessage::text_utf8("hello")))
+			.max_requests_per_connection(Some(3))
+			.build()
+			.unwrap();
+		let addr = server.local_addr();
+		let handle = server.handle();
+		let t = thread::spawn(move || server.run().unwrap());
+
+		// sequential requests on one socket, the last one asks to close
+		let mut stream = TcpStream::connect(addr).unwrap();
+		let mut buf = [0; 1024];
+		stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+		let n = stream.read(&mut buf).unwrap();
+		assert!(buf[..n].ends_with(b"\r\n\r\nhello"));
+		assert!(!String::from_utf8_lossy(&buf[..n]).contains("Connection: close"));
+
+		stream.write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").unwrap();
+		let n = stream.read(&mut buf).unwrap();
+		assert!(String::from_utf8_lossy(&buf[..n]).contains("Connection: keep-alive\r\n"));
+
+		stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+		let mut resp = Vec::new();
+		stream.read_to_end(&mut resp).unwrap();
+		assert!(resp.starts_with(b"HTTP/1.1 200 OK\r\n"));
+		assert!(String::from_utf8_lossy(&resp).contains("Connection: close\r\n"));
+
+		// HTTP/1.0 closes by default
+		let resp = request(addr, b"GET / HTTP/1.0\r\n\r\n");
+		assert!(String::from_utf8_lossy(&resp).contains("Connection: close\r\n"));
+
+		// the server closes once the request limit is reached
+		let mut stream = TcpStream::connect(addr).unwrap();
+		for _ in 0..3 {
+			stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+		}
+		let mut resp = Vec::new();
+		stream.read_to_end(&mut resp).unwrap();
+		let resp = String::from_utf8_lossy(&resp);
+		assert_eq!(resp.matches("HTTP/1.1 200 OK").count(), 3);
+		assert_eq!(resp.matches("Connection: close").count(), 1);
+		assert!(resp.rsplit("HTTP/1.1 200 OK").next().unwrap().contains("Connection: close\r\n"));
+
+		handle.shutdown();
+		t.join().unwrap();
+	}
+
+	#[test]
+	pub fn test_server_expect_continue() {
+		let server = HttpServerBuilder::new()
+			.bind("127.0.0.1:0")
+			.route(HttpRouteDynamicUrl::new(DynamicUrl::parse_str("/echo").unwrap(), HttpMethod::Post, |req, _| {
+				HttpResponseMessage::text_utf8(&String::from_utf8_lossy(&req.body))
+			}))
+			.max_body_size(16)
+			.build()
+			.unwrap();
+		let addr = server.local_addr();
+		let handle = server.handle();
+		let t = thread::spawn(move || server.run().unwrap());
+
+		let mut stream = TcpStream::connect(addr).unwrap();
+		stream.write_all(b"POST /echo HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n").unwrap();
+		let mut buf = [0; 1024];
+		let n = stream.read(&mut buf).unwrap();
+		assert_eq!(&buf[..n], b"HTTP/1.1 100 Continue\r\n\r\n");
+
+		stream.write_all(b"hello").unwrap();
+		let n = stream.read(&mut buf).unwrap();
+		assert!(buf[..n].starts_with(b"HTTP/1.1 200 OK\r\n"));
+		assert!(buf[..n].ends_with(b"\r\n\r\nhello"));
+
+		// too large: the final status comes instead of the go-ahead
+		let resp = request(addr, b"POST /echo HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 100\r\n\r\n");
+		assert!(resp.starts_with(b"HTTP/1.1 413 "));
+
+		let resp = request(addr, b"POST /echo HTTP/1.1\r\nHost: localhost\r\nExpect: something\r\nContent-Length: 1\r\n\r\n");
+		assert!(resp.starts_with(b"HTTP/1.1 417 Expectation Failed\r\n"));
+
+		handle.shutdown();
+		t.join().unwrap();
+	}
+}
@@ -0,0 +1,274 @@
+#![allow(clippy::redundant_field_names, clippy::len_zero)]
+
+use smoltcp::iface::{SocketHandle, SocketSet};
+use smoltcp::socket::tcp;
+
+use connection::*;
+use router::*;
+use alloc::vec::*;
+
+struct SocketSlot {
+	handle: SocketHandle,
+	conn: Option<HttpConnection>,
+	peer_closed: bool,
+}
+
+/// Serves HTTP over a pool of smoltcp TCP sockets. Every socket handles one
+/// connection at a time, so the number of sockets bounds concurrency. Call
+/// `poll` after each `Interface::poll`.
+pub struct SmoltcpHttpServer {
+	port: u16,
+	config: HttpConnectionConfig,
+	slots: Vec<SocketSlot>,
+}
+
+impl SmoltcpHttpServer {
+	/// `handles` are TCP sockets already added to the socket set, each with
+	/// its own RX and TX buffers.
+	pub fn new(port: u16, handles: &[SocketHandle]) -> SmoltcpHttpServer {
+		SmoltcpHttpServer::with_config(port, handles, HttpConnectionConfig::default())
+	}
+
+	pub fn with_config(port: u16, handles: &[SocketHandle], config: HttpConnectionConfig) -> SmoltcpHttpServer {
+		SmoltcpHttpServer {
+			port: port,
+			config: config,
+			slots: handles.iter().map(|h| SocketSlot { handle: *h, conn: None, peer_closed: false }).collect()
+		}
+	}
+
+	/// Number of sockets currently serving a connection.
+	pub fn active_connections(&self) -> usize {
+		self.slots.iter().filter(|s| s.conn.is_some()).count()
+	}
+
+	/// Move data between the sockets and their connections. Returns true if
+	/// anything happened, so the interface should be polled again soon.
+	pub fn poll(&mut self, sockets: &mut SocketSet, router: &HttpRouter) -> bool {
+		let mut activity = false;
+
+		for slot in &mut self.slots {
+			let socket = sockets.get_mut::<tcp::Socket>(slot.handle);
+
+			if !socket.is_open() {
+				// fresh socket, or the previous connection is gone: listen again
+				slot.conn = None;
+				slot.peer_closed = false;
+				let _ = socket.listen(self.port);
+				continue;
+			}
+
+			if slot.conn.is_none() {
+				if !socket.is_active() || socket.is_listening() { continue; }
+				slot.conn = Some(HttpConnection::with_config(self.config.clone()));
+			}
+
+			let conn = slot.conn.as_mut().unwrap();
+
+			// what the connection can't take yet stays in the RX buffer, so the
+			// window closes and the peer slows down
+			while socket.can_recv() && conn.can_receive() {
+				let capacity = conn.receive_capacity();
+				let received = socket.recv(|buf| {
+					let n = ::core::cmp::min(buf.len(), capacity);
+					conn.received(&buf[..n]);
+					(n, n)
+				});
+				match received {
+					Ok(n) if n > 0 => activity = true,
+					_ => break
+				}
+			}
+
+			if !socket.may_recv() && !slot.peer_closed {
+				slot.peer_closed = true;
+				conn.peer_closed();
+			}
+
+			let mut close = drain_events(conn, router);
+
+			// partial sends: only hand out what fits in the TX buffer, the
+			// connection remembers where it stopped
+			while conn.wants_transmit() && socket.can_send() {
+				let sent = socket.send(|buf| {
+					let n = conn.poll_transmit(buf);
+					(n, n)
+				});
+				match sent {
+					Ok(n) if n > 0 => activity = true,
+					_ => break
+				}
+			}
+
+			close |= drain_events(conn, router);
+
+			if close {
+				// queued data is still sent before the FIN
+				socket.close();
+				activity = true;
+			}
+		}
+
+		activity
+	}
+}
+
+fn drain_events(conn: &mut HttpConnection, router: &HttpRouter) -> bool {
+	let mut close = false;
+	while let Some(event) = conn.poll_routed(router) {
+		if let HttpConnectionEvent::Close = event {
+			close = true;
+		}
+	}
+	close
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use http::*;
+
+	use smoltcp::iface::{Config, Interface, SocketSet};
+	use smoltcp::phy::{Loopback, Medium};
+	use smoltcp::socket::tcp;
+	use smoltcp::time::{Duration, Instant};
+	use smoltcp::wire::{HardwareAddress, IpAddress, IpCidr};
+	use alloc::vec::Vec;
+	use alloc::string::String;
+
+	fn tcp_socket<'a>() -> tcp::Socket<'a> {
+		tcp::Socket::new(tcp::SocketBuffer::new(vec![0; 256]), tcp::SocketBuffer::new(vec![0; 64]))
+	}
+
+	#[test]
+	pub fn test_smoltcp_server_loopback() {
+		let mut device = Loopback::new(Medium::Ip);
+		let mut now = Instant::from_millis(0);
+		let mut iface = Interface::new(Config::new(HardwareAddress::Ip), &mut device, now);
+		iface.update_ip_addrs(|addrs| {
+			addrs.push(IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8)).unwrap();
+		});
+
+		let mut sockets = SocketSet::new(Vec::new());
+		let server_handles = [sockets.add(tcp_socket()), sockets.add(tcp_socket())];
+		let client_handles = [sockets.add(tcp_socket()), sockets.add(tcp_socket())];
+
+		let mut router = HttpRouter::new();
+		router.add_route(HttpRouteDynamicUrl::new(DynamicUrl::parse_str("/test/:id").unwrap(), HttpMethod::Get, |_, vars| {
+			// long enough to need several sends through the 64 byte TX buffer
+			HttpResponseMessage::text_utf8(&format!("id {} - 0123456789012345678901234567890123456789", vars.get("id").unwrap()))
+		}));
+
+		let mut server = SmoltcpHttpServer::new(8080, &server_handles);
+		let mut responses = [Vec::new(), Vec::new()];
+		let mut requested = false;
+
+		for _ in 0..1000 {
+			iface.poll(now, &mut device, &mut sockets);
+			server.poll(&mut sockets, &router);
+
+			for (i, handle) in client_handles.iter().enumerate() {
+				let socket = sockets.get_mut::<tcp::Socket>(*handle);
+				if !requested {
+					socket.connect(iface.context(), (IpAddress::v4(127, 0, 0, 1), 8080), 50000 + i as u16).unwrap();
+				}
+				if socket.may_send() && socket.send_queue() == 0 && responses[i].len() == 0 {
+					let req = format!("GET /test/{} HTTP/1.1\r\nHost: localhost\r\n\r\n", i);
+					if socket.send_slice(req.as_bytes()).unwrap() == req.len() {
+						// mark as sent
+						responses[i].push(0);
+					}
+				}
+				while socket.can_recv() {
+					socket.recv(|buf| {
+						responses[i].extend_from_slice(buf);
+						(buf.len(), ())
+					}).unwrap();
+				}
+				if socket.state() == tcp::State::CloseWait {
+					socket.close();
+				}
+			}
+			requested = true;
+
+			if client_handles.iter().all(|h| !sockets.get::<tcp::Socket>(*h).is_open()) {
+				break;
+			}
+			now += Duration::from_millis(10);
+		}
+
+		for (i, resp) in responses.iter().enumerate().take(2) {
+			let resp = &resp[1..];
+			assert!(resp.starts_with(b"HTTP/1.1 200 OK\r\n"));
+			let expected = format!("\r\n\r\nid {} - 0123456789012345678901234567890123456789", i);
+			assert!(resp.ends_with(expected.as_bytes()));
+		}
+
+		// both sockets are listening again, once out of TIME-WAIT
+		for _ in 0..200 {
+			iface.poll(now, &mut device, &mut sockets);
+			server.poll(&mut sockets, &router);
+			now += Duration::from_millis(100);
+		}
+		assert_eq!(server.active_connections(), 0);
+		assert!(server_handles.iter().all(|h| sockets.get::<tcp::Socket>(*h).is_listening()));
+	}
+
+	#[test]
+	pub fn test_smoltcp_server_backpressure() {
+		let mut device = Loopback::new(Medium::Ip);
+		let mut now = Instant::from_millis(0);
+		let mut iface = Interface::new(Config::new(HardwareAddress::Ip), &mut device, now);
+		iface.update_ip_addrs(|addrs| {
+			addrs.push(IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8)).unwrap();
+		});
+
+		let mut sockets = SocketSet::new(Vec::new());
+		let server_handle = sockets.add(tcp_socket());
+		let client_handle = sockets.add(tcp::Socket::new(tcp::SocketBuffer::new(vec![0; 256]), tcp::SocketBuffer::new(vec![0; 256])));
+
+		let mut router = HttpRouter::new();
+		router.add_route(HttpRouteDynamicUrl::new(DynamicUrl::parse_str("/test/:id").unwrap(), HttpMethod::Get, |_, vars| {
+			HttpResponseMessage::text_utf8(&format!("id {} - 0123456789012345678901234567890123456789", vars.get("id").unwrap()))
+		}));
+
+		// three pipelined requests don't fit in the connection at once; the
+		// rest waits in the socket until the first one is answered
+		let config = HttpConnectionConfig { keep_alive: true, max_requests: Some(3), max_head_size: 40, max_body_size: 0 };
+		let mut server = SmoltcpHttpServer::with_config(8080, &[server_handle], config);
+		let mut response = Vec::new();
+		let mut sent = false;
+
+		for i in 0..1000 {
+			iface.poll(now, &mut device, &mut sockets);
+			server.poll(&mut sockets, &router);
+
+			let socket = sockets.get_mut::<tcp::Socket>(client_handle);
+			if i == 0 {
+				socket.connect(iface.context(), (IpAddress::v4(127, 0, 0, 1), 8080), 50000).unwrap();
+			}
+			if socket.may_send() && !sent {
+				let reqs = b"GET /test/1 HTTP/1.1\r\nHost: a\r\n\r\nGET /test/2 HTTP/1.1\r\nHost: a\r\n\r\nGET /test/3 HTTP/1.1\r\nHost: a\r\n\r\n";
+				assert_eq!(socket.send_slice(reqs).unwrap(), reqs.len());
+				sent = true;
+			}
+			while socket.can_recv() {
+				socket.recv(|buf| {
+					response.extend_from_slice(buf);
+					(buf.len(), ())
+				}).unwrap();
+			}
+			if socket.state() == tcp::State::CloseWait {
+				socket.close();
+			}
+			if !socket.is_open() {
+				break;
+			}
+			now += Duration::from_millis(10);
+		}
+
+		let response = String::from_utf8(response).unwrap();
+		assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), 3);
+		assert!(response.ends_with("\r\n\r\nid 3 - 0123456789012345678901234567890123456789"));
+	}
+}
@@ -1,3 +1,813 @@
+#![allow(deprecated, clippy::redundant_field_names, clippy::should_implement_trait, clippy::len_zero)]
+
+use http::*;
+use parser::*;
+use url::*;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::string::ToString;
+
+/// `HOST` of multicast SSDP messages.
+pub const SSDP_MULTICAST_HOST: &str = "239.255.255.250:1900";
+/// `HOST` of multicast SSDP messages over IPv6, link-local scope.
+pub const SSDP_MULTICAST_HOST_V6: &str = "[FF02::C]:1900";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SsdpError {
+	/// Not an M-SEARCH or NOTIFY request, or not a 200 response.
+	WrongMessageType,
+	/// SSDP requests are sent to `*`.
+	InvalidRequestTarget,
+	MissingHeader(&'static str),
+	/// `MAN` isn't `"ssdp:discover"`.
+	InvalidMan,
+	/// `MX` isn't a number of seconds from 1 up.
+	InvalidMx,
+	InvalidNts,
+	/// `CACHE-CONTROL` without a `max-age`.
+	InvalidMaxAge,
+	/// `USN` doesn't start with `uuid:`.
+	InvalidUsn,
+	InvalidLocation,
+	/// `BOOTID.UPNP.ORG` or a similar field isn't a number.
+	InvalidNumber(&'static str),
+	/// Not an HTTP message at all.
+	InvalidMessage,
+}
+
+/// M-SEARCH request, looking for devices and services.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SsdpSearchRequest {
+	pub host: String,
+	/// Search target: `ssdp:all`, `upnp:rootdevice`, a `uuid:` or a device
+	/// or service type.
+	pub st: String,
+	/// Seconds devices may wait before answering, to spread the replies.
+	/// Required for multicast searches, absent for unicast ones.
+	pub mx: Option<u8>,
+	pub user_agent: Option<String>,
+}
+
+impl SsdpSearchRequest {
+	pub fn new_multicast(st: &str, mx: u8) -> SsdpSearchRequest {
+		SsdpSearchRequest {
+			host: SSDP_MULTICAST_HOST.to_string(),
+			st: st.to_string(),
+			mx: Some(mx),
+			user_agent: None
+		}
+	}
+
+	pub fn is_multicast(&self) -> bool {
+		is_multicast_host(&self.host)
+	}
+
+	/// The MX values above 5 are treated as 5 (UPnP Device Architecture 1.1,
+	/// section 1.3.2).
+	pub fn from_request(req: &HttpRequestMessage) -> Result<SsdpSearchRequest, SsdpError> {
+		if req.method != HttpMethod::MSearch { return Err(SsdpError::WrongMessageType); }
+		if req.url != "*" { return Err(SsdpError::InvalidRequestTarget); }
+
+		let host = try!(required_header(req, "HOST"));
+		let man = try!(required_header(req, "MAN"));
+		if !man.trim().eq_ignore_ascii_case("\"ssdp:discover\"") {
+			return Err(SsdpError::InvalidMan);
+		}
+		let st = try!(required_header(req, "ST"));
+
+		let mx = match req.get_raw_header("MX") {
+			Some(mx) => {
+				match mx.trim().parse::<u32>() {
+					Ok(0) | Err(_) => { return Err(SsdpError::InvalidMx); }
+					Ok(mx) => Some(if mx > 5 { 5 } else { mx as u8 })
+				}
+			}
+			None if is_multicast_host(host) => { return Err(SsdpError::MissingHeader("MX")); }
+			None => None
+		};
+
+		Ok(SsdpSearchRequest {
+			host: host.to_string(),
+			st: st.to_string(),
+			mx: mx,
+			user_agent: req.get_raw_header("USER-AGENT").cloned()
+		})
+	}
+
+	pub fn to_request(&self) -> HttpRequestMessage {
+		let mut req = HttpRequestMessage::empty();
+		req.method = HttpMethod::MSearch;
+		req.url = "*".to_string();
+		req.headers.insert("HOST".to_string(), self.host.clone());
+		req.headers.insert("MAN".to_string(), "\"ssdp:discover\"".to_string());
+		req.headers.insert("ST".to_string(), self.st.clone());
+		if let Some(mx) = self.mx {
+			req.headers.insert("MX".to_string(), mx.to_string());
+		}
+		if let Some(ref user_agent) = self.user_agent {
+			req.headers.insert("USER-AGENT".to_string(), user_agent.clone());
+		}
+		req
+	}
+}
+
+/// Unicast answer to an M-SEARCH.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SsdpSearchResponse {
+	/// Seconds the advertisement stays valid.
+	pub max_age: u32,
+	/// URL of the device description.
+	pub location: String,
+	pub st: String,
+	pub usn: String,
+	pub server: Option<String>,
+	pub boot_id: Option<u32>,
+	pub config_id: Option<u32>,
+}
+
+impl SsdpSearchResponse {
+	pub fn from_response(resp: &HttpResponseMessage) -> Result<SsdpSearchResponse, SsdpError> {
+		if resp.response_code != 200 { return Err(SsdpError::WrongMessageType); }
+
+		Ok(SsdpSearchResponse {
+			max_age: try!(max_age(resp)),
+			location: try!(location(resp)),
+			st: try!(required_header(resp, "ST")).to_string(),
+			usn: try!(usn(resp)),
+			server: resp.get_raw_header("SERVER").cloned(),
+			boot_id: try!(optional_number(resp, "BOOTID.UPNP.ORG")),
+			config_id: try!(optional_number(resp, "CONFIGID.UPNP.ORG"))
+		})
+	}
+
+	pub fn to_response(&self) -> HttpResponseMessage {
+		let mut resp = HttpResponseMessage::empty();
+		resp.response_code = 200;
+		resp.response_status = "OK".to_string();
+		resp.headers.insert("CACHE-CONTROL".to_string(), format!("max-age={}", self.max_age));
+		resp.headers.insert("EXT".to_string(), String::new());
+		resp.headers.insert("LOCATION".to_string(), self.location.clone());
+		resp.headers.insert("ST".to_string(), self.st.clone());
+		resp.headers.insert("USN".to_string(), self.usn.clone());
+		if let Some(ref server) = self.server {
+			resp.headers.insert("SERVER".to_string(), server.clone());
+		}
+		insert_number(&mut resp.headers, "BOOTID.UPNP.ORG", self.boot_id);
+		insert_number(&mut resp.headers, "CONFIGID.UPNP.ORG", self.config_id);
+		resp
+	}
+}
+
+/// The `NTS` of a NOTIFY.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SsdpNotifyType {
+	Alive,
+	ByeBye,
+	Update,
+}
+
+impl SsdpNotifyType {
+	pub fn as_str(&self) -> &'static str {
+		match *self {
+			SsdpNotifyType::Alive => "ssdp:alive",
+			SsdpNotifyType::ByeBye => "ssdp:byebye",
+			SsdpNotifyType::Update => "ssdp:update"
+		}
+	}
+
+	pub fn from_str(s: &str) -> Option<SsdpNotifyType> {
+		match s {
+			"ssdp:alive" => Some(SsdpNotifyType::Alive),
+			"ssdp:byebye" => Some(SsdpNotifyType::ByeBye),
+			"ssdp:update" => Some(SsdpNotifyType::Update),
+			_ => None
+		}
+	}
+}
+
+/// Multicast advertisement of a device or service. `max_age` is required
+/// for ssdp:alive, `location` for ssdp:alive and ssdp:update, and
+/// `next_boot_id` for ssdp:update.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SsdpNotify {
+	pub host: String,
+	pub nts: SsdpNotifyType,
+	/// Notification type: `upnp:rootdevice`, a `uuid:` or a device or
+	/// service type.
+	pub nt: String,
+	pub usn: String,
+	pub max_age: Option<u32>,
+	pub location: Option<String>,
+	pub server: Option<String>,
+	pub boot_id: Option<u32>,
+	pub config_id: Option<u32>,
+	pub next_boot_id: Option<u32>,
+}
+
+impl SsdpNotify {
+	pub fn new_alive(nt: &str, usn: &str, location: &str, max_age: u32) -> SsdpNotify {
+		SsdpNotify {
+			host: SSDP_MULTICAST_HOST.to_string(),
+			nts: SsdpNotifyType::Alive,
+			nt: nt.to_string(),
+			usn: usn.to_string(),
+			max_age: Some(max_age),
+			location: Some(location.to_string()),
+			server: None,
+			boot_id: None,
+			config_id: None,
+			next_boot_id: None
+		}
+	}
+
+	pub fn new_byebye(nt: &str, usn: &str) -> SsdpNotify {
+		SsdpNotify {
+			host: SSDP_MULTICAST_HOST.to_string(),
+			nts: SsdpNotifyType::ByeBye,
+			nt: nt.to_string(),
+			usn: usn.to_string(),
+			max_age: None,
+			location: None,
+			server: None,
+			boot_id: None,
+			config_id: None,
+			next_boot_id: None
+		}
+	}
+
+	pub fn from_request(req: &HttpRequestMessage) -> Result<SsdpNotify, SsdpError> {
+		if req.method != HttpMethod::Notify { return Err(SsdpError::WrongMessageType); }
+		if req.url != "*" { return Err(SsdpError::InvalidRequestTarget); }
+
+		let nts = try!(required_header(req, "NTS"));
+		let nts = try!(SsdpNotifyType::from_str(nts.trim()).ok_or(SsdpError::InvalidNts));
+
+		let mut notify = SsdpNotify {
+			host: try!(required_header(req, "HOST")).to_string(),
+			nts: nts,
+			nt: try!(required_header(req, "NT")).to_string(),
+			usn: try!(usn(req)),
+			max_age: None,
+			location: None,
+			server: req.get_raw_header("SERVER").cloned(),
+			boot_id: try!(optional_number(req, "BOOTID.UPNP.ORG")),
+			config_id: try!(optional_number(req, "CONFIGID.UPNP.ORG")),
+			next_boot_id: None
+		};
+
+		match nts {
+			SsdpNotifyType::Alive => {
+				notify.max_age = Some(try!(max_age(req)));
+				notify.location = Some(try!(location(req)));
+			}
+			SsdpNotifyType::Update => {
+				notify.location = Some(try!(location(req)));
+				notify.next_boot_id = try!(optional_number(req, "NEXTBOOTID.UPNP.ORG"));
+				if notify.next_boot_id.is_none() {
+					return Err(SsdpError::MissingHeader("NEXTBOOTID.UPNP.ORG"));
+				}
+			}
+			SsdpNotifyType::ByeBye => ()
+		}
+
+		Ok(notify)
+	}
+
+	pub fn to_request(&self) -> HttpRequestMessage {
+		let mut req = HttpRequestMessage::empty();
+		req.method = HttpMethod::Notify;
+		req.url = "*".to_string();
+		req.headers.insert("HOST".to_string(), self.host.clone());
+		req.headers.insert("NT".to_string(), self.nt.clone());
+		req.headers.insert("NTS".to_string(), self.nts.as_str().to_string());
+		req.headers.insert("USN".to_string(), self.usn.clone());
+		if let Some(max_age) = self.max_age {
+			req.headers.insert("CACHE-CONTROL".to_string(), format!("max-age={}", max_age));
+		}
+		if let Some(ref location) = self.location {
+			req.headers.insert("LOCATION".to_string(), location.clone());
+		}
+		if let Some(ref server) = self.server {
+			req.headers.insert("SERVER".to_string(), server.clone());
+		}
+		insert_number(&mut req.headers, "BOOTID.UPNP.ORG", self.boot_id);
+		insert_number(&mut req.headers, "CONFIGID.UPNP.ORG", self.config_id);
+		insert_number(&mut req.headers, "NEXTBOOTID.UPNP.ORG", self.next_boot_id);
+		req
+	}
+}
+
+/// Datagram socket the SSDP code sends through. `Addr` is whatever the
+/// network stack addresses peers with.
+pub trait SsdpSocket {
+	type Addr: Clone;
+	type Error;
+
+	fn send_to(&mut self, buf: &[u8], addr: &Self::Addr) -> Result<(), Self::Error>;
+}
+
+/// A root device the responder advertises, with the service types it
+/// offers.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SsdpDevice {
+	/// Unique device name, `uuid:...`.
+	pub udn: String,
+	/// `urn:schemas-upnp-org:device:Basic:1` or similar.
+	pub device_type: String,
+	pub service_types: Vec<String>,
+	/// URL of the device description.
+	pub location: String,
+}
+
+impl SsdpDevice {
+	pub fn new(udn: &str, device_type: &str, location: &str) -> SsdpDevice {
+		SsdpDevice {
+			udn: udn.to_string(),
+			device_type: device_type.to_string(),
+			service_types: Vec::new(),
+			location: location.to_string()
+		}
+	}
+
+	pub fn service(mut self, service_type: &str) -> SsdpDevice {
+		self.service_types.push(service_type.to_string());
+		self
+	}
+
+	/// The NT and USN pairs of every advertisement of this device.
+	fn targets(&self) -> Vec<(String, String)> {
+		let mut targets = Vec::new();
+		targets.push(("upnp:rootdevice".to_string(), format!("{}::upnp:rootdevice", self.udn)));
+		targets.push((self.udn.clone(), self.udn.clone()));
+		targets.push((self.device_type.clone(), format!("{}::{}", self.udn, self.device_type)));
+		for service_type in &self.service_types {
+			targets.push((service_type.clone(), format!("{}::{}", self.udn, service_type)));
+		}
+		targets
+	}
+
+	/// The ST and USN pairs answering a search for `st`. A device or service
+	/// type matches older versions of itself too, and is then reported with
+	/// the searched version.
+	fn search_targets(&self, st: &str) -> Vec<(String, String)> {
+		if st == "ssdp:all" {
+			return self.targets();
+		}
+
+		let mut targets = Vec::new();
+		if st == "upnp:rootdevice" {
+			targets.push((st.to_string(), format!("{}::upnp:rootdevice", self.udn)));
+		} else if st == self.udn {
+			targets.push((st.to_string(), self.udn.clone()));
+		} else {
+			let types = Some(&self.device_type).into_iter().chain(self.service_types.iter());
+			for t in types {
+				if type_supports(t, st) {
+					targets.push((st.to_string(), format!("{}::{}", self.udn, st)));
+				}
+			}
+		}
+		targets
+	}
+}
+
+/// Does `ours`, like `urn:schemas-upnp-org:service:Dimming:2`, support the
+/// searched `st`, the same type with the same or an older version?
+fn type_supports(ours: &str, st: &str) -> bool {
+	match (ours.rfind(':'), st.rfind(':')) {
+		(Some(i), Some(j)) if ours[..i] == st[..j] => {
+			match (ours[i + 1..].parse::<u32>(), st[j + 1..].parse::<u32>()) {
+				(Ok(ours), Ok(st)) => st <= ours,
+				_ => ours == st
+			}
+		}
+		_ => ours == st
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct SsdpResponderConfig {
+	/// Seconds our advertisements stay valid. They are repeated every half
+	/// of that.
+	pub max_age: u32,
+	/// `SERVER` header, "OS/version UPnP/1.1 product/version".
+	pub server: String,
+	pub boot_id: Option<u32>,
+	pub config_id: Option<u32>,
+	/// Seed of the random response delays. Devices on the same network
+	/// should use different ones, derived from their MAC address for example.
+	pub random_seed: u32,
+	/// Search answers waiting for their delay, at most. Searches that would
+	/// go over are ignored.
+	pub max_pending: usize,
+}
+
+impl Default for SsdpResponderConfig {
+	fn default() -> SsdpResponderConfig {
+		SsdpResponderConfig {
+			max_age: 1800,
+			server: concat!("unknown/0 UPnP/1.1 core_mini_http/", env!("CARGO_PKG_VERSION")).to_string(),
+			boot_id: None,
+			config_id: None,
+			random_seed: 0x2545_f491,
+			max_pending: 64
+		}
+	}
+}
+
+struct PendingSsdpResponse<A> {
+	due: u64,
+	to: A,
+	/// The `ST` that was searched for.
+	st: String,
+	response: SsdpSearchResponse,
+}
+
+/// Advertises devices over SSDP and answers searches for them, on any
+/// datagram transport. Feed it received datagrams with `received` and call
+/// `poll` by `poll_at` at the latest. Times are in milliseconds, from any
+/// fixed point.
+pub struct SsdpResponder<A> {
+	config: SsdpResponderConfig,
+	devices: Vec<SsdpDevice>,
+	multicast: A,
+	pending: Vec<PendingSsdpResponse<A>>,
+	/// `None` once shut down.
+	next_alive: Option<u64>,
+	rng: u32,
+}
+
+impl<A: Clone + PartialEq> SsdpResponder<A> {
+	/// `multicast` is the SSDP group address, 239.255.255.250:1900.
+	pub fn new(config: SsdpResponderConfig, multicast: A) -> SsdpResponder<A> {
+		let rng = if config.random_seed == 0 { 1 } else { config.random_seed };
+		SsdpResponder {
+			config: config,
+			devices: Vec::new(),
+			multicast: multicast,
+			pending: Vec::new(),
+			next_alive: Some(0),
+			rng: rng
+		}
+	}
+
+	/// Add a device. It is announced with the next `poll`.
+	pub fn add_device(&mut self, device: SsdpDevice) {
+		self.devices.push(device);
+		if self.next_alive.is_some() {
+			self.next_alive = Some(0);
+		}
+	}
+
+	pub fn devices(&self) -> &[SsdpDevice] {
+		&self.devices
+	}
+
+	/// Handle a datagram received on the SSDP port. Answers to valid
+	/// searches are queued; other messages are ignored.
+	pub fn received(&mut self, datagram: &[u8], from: A, now: u64) -> Result<(), SsdpError> {
+		let req = match try!(parse_datagram(datagram).map_err(|_| SsdpError::InvalidMessage)) {
+			HttpMessage::Request(req) => req,
+			HttpMessage::Response(_) => { return Ok(()); }
+		};
+		if req.method != HttpMethod::MSearch {
+			return Ok(());
+		}
+
+		let search = try!(SsdpSearchRequest::from_request(&req));
+		self.handle_search(&search, from, now);
+		Ok(())
+	}
+
+	/// Queue the answers to a search. Multicast searches are answered after
+	/// a random delay below MX, to keep devices from replying all at once.
+	/// MX is taken as 1 to 5, like `SsdpSearchRequest::from_request` does.
+	/// A search repeated while its answers are still waiting is ignored.
+	pub fn handle_search(&mut self, search: &SsdpSearchRequest, from: A, now: u64) {
+		if self.next_alive.is_none() {
+			return;
+		}
+		if self.pending.iter().any(|p| p.to == from && p.st == search.st) {
+			return;
+		}
+
+		let mut answers = Vec::new();
+		for device in &self.devices {
+			for (st, usn) in device.search_targets(&search.st) {
+				answers.push(SsdpSearchResponse {
+					max_age: self.config.max_age,
+					location: device.location.clone(),
+					st: st,
+					usn: usn,
+					server: Some(self.config.server.clone()),
+					boot_id: self.config.boot_id,
+					config_id: self.config.config_id
+				});
+			}
+		}
+
+		if self.pending.len() + answers.len() > self.config.max_pending {
+			return;
+		}
+
+		for response in answers {
+			let delay = match search.mx {
+				Some(mx) => (self.next_random() % (mx.clamp(1, 5) as u32 * 1000)) as u64,
+				None => 0
+			};
+			self.pending.push(PendingSsdpResponse { due: now + delay, to: from.clone(), st: search.st.clone(), response: response });
+		}
+	}
+
+	/// Send the answers and advertisements that are due.
+	pub fn poll<S: SsdpSocket<Addr = A>>(&mut self, socket: &mut S, now: u64) -> Result<(), S::Error> {
+		while let Some(i) = self.pending.iter().position(|p| p.due <= now) {
+			let pending = self.pending.swap_remove(i);
+			try!(socket.send_to(&pending.response.to_response().to_bytes(), &pending.to));
+		}
+
+		match self.next_alive {
+			Some(t) if t <= now => {
+				self.next_alive = Some(now + self.config.max_age as u64 * 500);
+				for notify in self.notifications(SsdpNotifyType::Alive) {
+					try!(socket.send_to(&notify.to_request().to_bytes(), &self.multicast));
+				}
+			}
+			_ => ()
+		}
+
+		Ok(())
+	}
+
+	/// When `poll` has something to send next.
+	pub fn poll_at(&self) -> Option<u64> {
+		let pending = self.pending.iter().map(|p| p.due).min();
+		match (pending, self.next_alive) {
+			(Some(a), Some(b)) => Some(core::cmp::min(a, b)),
+			(a, b) => a.or(b)
+		}
+	}
+
+	/// Say ssdp:byebye for every device, and stop answering.
+	pub fn shutdown<S: SsdpSocket<Addr = A>>(&mut self, socket: &mut S) -> Result<(), S::Error> {
+		self.next_alive = None;
+		self.pending.clear();
+
+		for notify in self.notifications(SsdpNotifyType::ByeBye) {
+			try!(socket.send_to(&notify.to_request().to_bytes(), &self.multicast));
+		}
+		Ok(())
+	}
+
+	fn notifications(&self, nts: SsdpNotifyType) -> Vec<SsdpNotify> {
+		let mut notifications = Vec::new();
+		for device in &self.devices {
+			for (nt, usn) in device.targets() {
+				let mut notify = match nts {
+					SsdpNotifyType::Alive => SsdpNotify::new_alive(&nt, &usn, &device.location, self.config.max_age),
+					_ => SsdpNotify::new_byebye(&nt, &usn)
+				};
+				if nts == SsdpNotifyType::Alive {
+					notify.server = Some(self.config.server.clone());
+				}
+				notify.boot_id = self.config.boot_id;
+				notify.config_id = self.config.config_id;
+				notifications.push(notify);
+			}
+		}
+		notifications
+	}
+
+	/// xorshift32, plenty for spreading delays.
+	fn next_random(&mut self) -> u32 {
+		let mut x = self.rng;
+		x ^= x << 13;
+		x ^= x >> 17;
+		x ^= x << 5;
+		self.rng = x;
+		x
+	}
+}
+
+#[cfg(any(test, feature = "std"))]
+impl SsdpSocket for ::std::net::UdpSocket {
+	type Addr = ::std::net::SocketAddr;
+	type Error = ::std::io::Error;
+
+	fn send_to(&mut self, buf: &[u8], addr: &::std::net::SocketAddr) -> Result<(), ::std::io::Error> {
+		::std::net::UdpSocket::send_to(self, buf, addr).map(|_| ())
+	}
+}
+
+/// A device or service found by `SsdpControlPoint`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SsdpDiscovered<A> {
+	pub usn: String,
+	/// The ST of the response or the NT of the advertisement.
+	pub target: String,
+	pub location: String,
+	pub server: Option<String>,
+	pub boot_id: Option<u32>,
+	/// Where the last message about it came from.
+	pub from: A,
+	/// Forgotten at this time unless advertised again.
+	pub expires: u64,
+}
+
+/// Finds devices: sends searches, collects the responses within the MX
+/// window, and follows ssdp:alive, ssdp:update and ssdp:byebye
+/// advertisements. Entries are keyed by USN. Feed it received datagrams
+/// with `received` and call `expire` by `poll_at`. Times are in
+/// milliseconds, as with `SsdpResponder`.
+pub struct SsdpControlPoint<A> {
+	multicast: A,
+	user_agent: Option<String>,
+	discovered: Vec<SsdpDiscovered<A>>,
+	/// Responses arriving later are ignored.
+	search_until: Option<u64>,
+}
+
+impl<A: Clone> SsdpControlPoint<A> {
+	/// `multicast` is the SSDP group address, 239.255.255.250:1900.
+	pub fn new(multicast: A) -> SsdpControlPoint<A> {
+		SsdpControlPoint {
+			multicast: multicast,
+			user_agent: None,
+			discovered: Vec::new(),
+			search_until: None
+		}
+	}
+
+	/// `USER-AGENT` of the searches.
+	pub fn set_user_agent(&mut self, user_agent: &str) {
+		self.user_agent = Some(user_agent.to_string());
+	}
+
+	/// Multicast a search for `st`. Devices answer within `mx` seconds.
+	pub fn search<S: SsdpSocket<Addr = A>>(&mut self, socket: &mut S, st: &str, mx: u8, now: u64) -> Result<(), S::Error> {
+		let mut search = SsdpSearchRequest::new_multicast(st, mx);
+		search.user_agent = self.user_agent.clone();
+		try!(socket.send_to(&search.to_request().to_bytes(), &self.multicast));
+
+		let until = now + mx as u64 * 1000;
+		match self.search_until {
+			Some(t) if t >= until => (),
+			_ => self.search_until = Some(until)
+		}
+		Ok(())
+	}
+
+	pub fn is_searching(&self, now: u64) -> bool {
+		match self.search_until {
+			Some(t) => now < t,
+			None => false
+		}
+	}
+
+	/// Handle a datagram received on the SSDP port or on the socket the
+	/// searches went out from. Searches of other control points are ignored.
+	pub fn received(&mut self, datagram: &[u8], from: A, now: u64) -> Result<(), SsdpError> {
+		match try!(parse_datagram(datagram).map_err(|_| SsdpError::InvalidMessage)) {
+			HttpMessage::Response(ref resp) => {
+				let resp = try!(SsdpSearchResponse::from_response(resp));
+				if self.is_searching(now) {
+					self.update(SsdpDiscovered {
+						usn: resp.usn,
+						target: resp.st,
+						location: resp.location,
+						server: resp.server,
+						boot_id: resp.boot_id,
+						from: from,
+						expires: now + resp.max_age as u64 * 1000
+					});
+				}
+			}
+			HttpMessage::Request(ref req) if req.method == HttpMethod::Notify => {
+				let notify = try!(SsdpNotify::from_request(req));
+				self.notified(notify, from, now);
+			}
+			HttpMessage::Request(_) => ()
+		}
+		Ok(())
+	}
+
+	fn notified(&mut self, notify: SsdpNotify, from: A, now: u64) {
+		match notify.nts {
+			SsdpNotifyType::Alive => {
+				self.update(SsdpDiscovered {
+					usn: notify.usn,
+					target: notify.nt,
+					location: notify.location.unwrap_or_default(),
+					server: notify.server,
+					boot_id: notify.boot_id,
+					from: from,
+					expires: now + notify.max_age.unwrap_or(0) as u64 * 1000
+				});
+			}
+			SsdpNotifyType::Update => {
+				// only refreshes what we already know
+				if let Some(d) = self.discovered.iter_mut().find(|d| d.usn == notify.usn) {
+					d.location = notify.location.unwrap_or_default();
+					d.boot_id = notify.next_boot_id;
+					d.from = from;
+				}
+			}
+			SsdpNotifyType::ByeBye => {
+				self.discovered.retain(|d| d.usn != notify.usn);
+			}
+		}
+	}
+
+	fn update(&mut self, discovered: SsdpDiscovered<A>) {
+		if let Some(d) = self.discovered.iter_mut().find(|d| d.usn == discovered.usn) {
+			*d = discovered;
+			return;
+		}
+		self.discovered.push(discovered);
+	}
+
+	/// Forget what wasn't advertised again in time, and end the search
+	/// window once it's over.
+	pub fn expire(&mut self, now: u64) {
+		self.discovered.retain(|d| d.expires > now);
+		if !self.is_searching(now) {
+			self.search_until = None;
+		}
+	}
+
+	/// When `expire` has something to do next.
+	pub fn poll_at(&self) -> Option<u64> {
+		let expiry = self.discovered.iter().map(|d| d.expires).min();
+		match (expiry, self.search_until) {
+			(Some(a), Some(b)) => Some(core::cmp::min(a, b)),
+			(a, b) => a.or(b)
+		}
+	}
+
+	pub fn discovered(&self) -> &[SsdpDiscovered<A>] {
+		&self.discovered
+	}
+
+	pub fn find(&self, usn: &str) -> Option<&SsdpDiscovered<A>> {
+		self.discovered.iter().find(|d| d.usn == usn)
+	}
+}
+
+fn is_multicast_host(host: &str) -> bool {
+	host == SSDP_MULTICAST_HOST || host.eq_ignore_ascii_case(SSDP_MULTICAST_HOST_V6)
+}
+
+fn required_header<'a, H: HttpHeaders>(msg: &'a H, name: &'static str) -> Result<&'a str, SsdpError> {
+	match msg.get_raw_header(name) {
+		Some(v) if v.trim().len() > 0 => Ok(v.trim()),
+		_ => Err(SsdpError::MissingHeader(name))
+	}
+}
+
+fn optional_number<H: HttpHeaders>(msg: &H, name: &'static str) -> Result<Option<u32>, SsdpError> {
+	match msg.get_raw_header(name) {
+		Some(v) => v.trim().parse::<u32>().map(Some).map_err(|_| SsdpError::InvalidNumber(name)),
+		None => Ok(None)
+	}
+}
+
+fn insert_number(headers: &mut ::alloc::collections::BTreeMap<String, String>, name: &str, value: Option<u32>) {
+	if let Some(v) = value {
+		headers.insert(name.to_string(), v.to_string());
+	}
+}
+
+/// The `max-age` directive of `CACHE-CONTROL`.
+fn max_age<H: HttpHeaders>(msg: &H) -> Result<u32, SsdpError> {
+	let cache_control = try!(required_header(msg, "CACHE-CONTROL"));
+	for directive in cache_control.split(',') {
+		let mut parts = directive.splitn(2, '=');
+		let name = parts.next().unwrap_or("").trim();
+		if name.eq_ignore_ascii_case("max-age") {
+			return parts.next().and_then(|v| v.trim().parse::<u32>().ok()).ok_or(SsdpError::InvalidMaxAge);
+		}
+	}
+
+	Err(SsdpError::InvalidMaxAge)
+}
+
+fn location<H: HttpHeaders>(msg: &H) -> Result<String, SsdpError> {
+	let location = try!(required_header(msg, "LOCATION"));
+	match Url::parse(location) {
+		Ok(ref url) if url.scheme == "http" || url.scheme == "https" => Ok(location.to_string()),
+		_ => Err(SsdpError::InvalidLocation)
+	}
+}
+
+fn usn<H: HttpHeaders>(msg: &H) -> Result<String, SsdpError> {
+	let usn = try!(required_header(msg, "USN"));
+	match usn.get(..5) {
+		Some(prefix) if prefix.eq_ignore_ascii_case("uuid:") => (),
+		_ => { return Err(SsdpError::InvalidUsn); }
+	}
+	Ok(usn.to_string())
+}
+
 #[cfg(test)]
 mod ssdp_tests {
 	
@@ -6,8 +816,8 @@ mod ssdp_tests {
 	use super::super::{HttpParser, HttpMessage, HttpRequestMessage};
 
 
-	use core::prelude::*;
-	use collections::vec::Vec;
+	use alloc::vec::Vec;
+	use alloc::string::ToString;
 
 	#[test]
 	pub fn test_ssdp_parsing() {
@@ -32,6 +842,349 @@ mod ssdp_tests {
 		let req = parser.get_request();
 		println!("parsed: {:?}", req);		
 	}
+
+	fn parse_request(raw: &str) -> HttpRequestMessage {
+		match parse_datagram(raw.as_bytes()).unwrap() {
+			HttpMessage::Request(req) => req,
+			_ => panic!("not a request")
+		}
+	}
+
+	#[test]
+	pub fn test_ssdp_search_request() {
+		let req = parse_request("M-SEARCH * HTTP/1.1\r\n\
+								 HOST: 239.255.255.250:1900\r\n\
+								 MAN: \"ssdp:discover\"\r\n\
+								 MX: 120\r\n\
+								 ST: upnp:rootdevice\r\n\
+								 \r\n");
+		let search = SsdpSearchRequest::from_request(&req).unwrap();
+		assert!(search.is_multicast());
+		assert_eq!(search.st, "upnp:rootdevice");
+		assert_eq!(search.mx, Some(5));
+		assert_eq!(SsdpSearchRequest::from_request(&search.to_request()).unwrap(), search);
+
+		let mut req = SsdpSearchRequest::new_multicast("ssdp:all", 2).to_request();
+		req.headers.insert("MAN".to_string(), "ssdp:discover".to_string());
+		assert_eq!(SsdpSearchRequest::from_request(&req), Err(SsdpError::InvalidMan));
+
+		let mut req = SsdpSearchRequest::new_multicast("ssdp:all", 2).to_request();
+		req.headers.remove("MX");
+		assert_eq!(SsdpSearchRequest::from_request(&req), Err(SsdpError::MissingHeader("MX")));
+		req.headers.insert("HOST".to_string(), "192.168.1.10:1900".to_string());
+		assert_eq!(SsdpSearchRequest::from_request(&req).unwrap().mx, None);
+		req.headers.insert("MX".to_string(), "0".to_string());
+		assert_eq!(SsdpSearchRequest::from_request(&req), Err(SsdpError::InvalidMx));
+
+		let mut req = SsdpSearchRequest::new_multicast("ssdp:all", 2).to_request();
+		req.url = "/".to_string();
+		assert_eq!(SsdpSearchRequest::from_request(&req), Err(SsdpError::InvalidRequestTarget));
+		req.method = HttpMethod::Notify;
+		assert_eq!(SsdpSearchRequest::from_request(&req), Err(SsdpError::WrongMessageType));
+	}
+
+	#[test]
+	pub fn test_ssdp_search_response() {
+		let response = SsdpSearchResponse {
+			max_age: 1800,
+			location: "http://192.168.1.10:8080/description.xml".to_string(),
+			st: "upnp:rootdevice".to_string(),
+			usn: "uuid:2fac1234-31f8-11b4-a222-08002b34c003::upnp:rootdevice".to_string(),
+			server: Some("unix/5.1 UPnP/2.0 core_mini_http/1.0".to_string()),
+			boot_id: Some(3),
+			config_id: None
+		};
+		let mut resp = response.to_response();
+		assert_eq!(resp.get_raw_header("EXT").unwrap(), "");
+		assert_eq!(SsdpSearchResponse::from_response(&resp).unwrap(), response);
+
+		resp.headers.insert("CACHE-CONTROL".to_string(), "no-cache, Max-Age = 60".to_string());
+		assert_eq!(SsdpSearchResponse::from_response(&resp).unwrap().max_age, 60);
+		resp.headers.insert("CACHE-CONTROL".to_string(), "no-cache".to_string());
+		assert_eq!(SsdpSearchResponse::from_response(&resp), Err(SsdpError::InvalidMaxAge));
+
+		let mut resp = response.to_response();
+		resp.headers.insert("USN".to_string(), "device-1".to_string());
+		assert_eq!(SsdpSearchResponse::from_response(&resp), Err(SsdpError::InvalidUsn));
+		// the prefix check must not slice into a multi-byte character
+		for usn in &["uuidä-1", "uuiä…"] {
+			resp.headers.insert("USN".to_string(), usn.to_string());
+			assert_eq!(SsdpSearchResponse::from_response(&resp), Err(SsdpError::InvalidUsn));
+		}
+
+		let mut resp = response.to_response();
+		resp.headers.insert("LOCATION".to_string(), "description.xml".to_string());
+		assert_eq!(SsdpSearchResponse::from_response(&resp), Err(SsdpError::InvalidLocation));
+
+		let mut resp = response.to_response();
+		resp.headers.insert("BOOTID.UPNP.ORG".to_string(), "x".to_string());
+		assert_eq!(SsdpSearchResponse::from_response(&resp), Err(SsdpError::InvalidNumber("BOOTID.UPNP.ORG")));
+
+		let mut resp = response.to_response();
+		resp.response_code = 404;
+		assert_eq!(SsdpSearchResponse::from_response(&resp), Err(SsdpError::WrongMessageType));
+	}
+
+	#[test]
+	pub fn test_ssdp_notify() {
+		let usn = "uuid:2fac1234-31f8-11b4-a222-08002b34c003::upnp:rootdevice";
+		let req = parse_request("NOTIFY * HTTP/1.1\r\n\
+								 HOST: 239.255.255.250:1900\r\n\
+								 CACHE-CONTROL: max-age=1800\r\n\
+								 LOCATION: http://192.168.1.10:8080/description.xml\r\n\
+								 NT: upnp:rootdevice\r\n\
+								 NTS: ssdp:alive\r\n\
+								 USN: uuid:2fac1234-31f8-11b4-a222-08002b34c003::upnp:rootdevice\r\n\
+								 \r\n");
+		let alive = SsdpNotify::from_request(&req).unwrap();
+		assert_eq!(alive, SsdpNotify::new_alive("upnp:rootdevice", usn, "http://192.168.1.10:8080/description.xml", 1800));
+		assert_eq!(SsdpNotify::from_request(&alive.to_request()).unwrap(), alive);
+
+		let mut req = alive.to_request();
+		req.headers.remove("CACHE-CONTROL");
+		assert_eq!(SsdpNotify::from_request(&req), Err(SsdpError::MissingHeader("CACHE-CONTROL")));
+
+		// byebye carries neither a location nor a max-age
+		let byebye = SsdpNotify::new_byebye("upnp:rootdevice", usn);
+		let req = byebye.to_request();
+		assert_eq!(req.get_raw_header("NTS").unwrap(), "ssdp:byebye");
+		assert_eq!(SsdpNotify::from_request(&req).unwrap(), byebye);
+
+		let mut update = SsdpNotify::new_alive("upnp:rootdevice", usn, "http://192.168.1.10:8080/description.xml", 1800);
+		update.nts = SsdpNotifyType::Update;
+		update.max_age = None;
+		update.boot_id = Some(1);
+		let mut req = update.to_request();
+		assert_eq!(SsdpNotify::from_request(&req), Err(SsdpError::MissingHeader("NEXTBOOTID.UPNP.ORG")));
+		update.next_boot_id = Some(2);
+		assert_eq!(SsdpNotify::from_request(&update.to_request()).unwrap(), update);
+
+		req.headers.insert("NTS".to_string(), "ssdp:gone".to_string());
+		assert_eq!(SsdpNotify::from_request(&req), Err(SsdpError::InvalidNts));
+	}
+
+	struct FakeSocket {
+		sent: Vec<(Vec<u8>, &'static str)>,
+	}
+
+	impl SsdpSocket for FakeSocket {
+		type Addr = &'static str;
+		type Error = ();
+
+		fn send_to(&mut self, buf: &[u8], addr: &&'static str) -> Result<(), ()> {
+			self.sent.push((buf.to_vec(), *addr));
+			Ok(())
+		}
+	}
+
+	impl FakeSocket {
+		fn take_notifications(&mut self) -> Vec<SsdpNotify> {
+			self.sent.drain(..).map(|(raw, to)| {
+				assert_eq!(to, "multicast");
+				match parse_datagram(&raw).unwrap() {
+					HttpMessage::Request(req) => SsdpNotify::from_request(&req).unwrap(),
+					_ => panic!("not a request")
+				}
+			}).collect()
+		}
+
+		fn take_responses(&mut self, client: &str) -> Vec<SsdpSearchResponse> {
+			self.sent.drain(..).map(|(raw, to)| {
+				assert_eq!(to, client);
+				match parse_datagram(&raw).unwrap() {
+					HttpMessage::Response(resp) => SsdpSearchResponse::from_response(&resp).unwrap(),
+					_ => panic!("not a response")
+				}
+			}).collect()
+		}
+	}
+
+	fn search(st: &str, mx: Option<u8>) -> Vec<u8> {
+		let mut search = SsdpSearchRequest::new_multicast(st, 1);
+		search.mx = mx;
+		if mx.is_none() {
+			search.host = "192.168.1.10:1900".to_string();
+		}
+		search.to_request().to_bytes()
+	}
+
+	#[test]
+	pub fn test_ssdp_responder() {
+		let udn = "uuid:2fac1234-31f8-11b4-a222-08002b34c003";
+		let config = SsdpResponderConfig { max_age: 100, boot_id: Some(7), max_pending: 8, .. SsdpResponderConfig::default() };
+		let mut responder = SsdpResponder::new(config, "multicast");
+		responder.add_device(SsdpDevice::new(udn, "urn:schemas-upnp-org:device:DimmableLight:2", "http://192.168.1.10/desc.xml")
+			.service("urn:schemas-upnp-org:service:SwitchPower:1"));
+		let mut socket = FakeSocket { sent: Vec::new() };
+
+		// announced right away, then every half max-age
+		let mut now = 0;
+		responder.poll(&mut socket, now).unwrap();
+		let alive = socket.take_notifications();
+		let nts: Vec<&str> = alive.iter().map(|n| n.nt.as_str()).collect();
+		assert_eq!(nts, ["upnp:rootdevice", udn, "urn:schemas-upnp-org:device:DimmableLight:2", "urn:schemas-upnp-org:service:SwitchPower:1"]);
+		assert_eq!(alive[0].usn, format!("{}::upnp:rootdevice", udn));
+		assert!(alive.iter().all(|n| n.nts == SsdpNotifyType::Alive && n.max_age == Some(100) && n.boot_id == Some(7)));
+		assert_eq!(responder.poll_at(), Some(50_000));
+
+		// multicast searches are answered within MX, once while the answers
+		// are waiting
+		now = 1000;
+		responder.received(&search("ssdp:all", Some(3)), "client", now).unwrap();
+		responder.received(&search("ssdp:all", Some(3)), "client", now).unwrap();
+		let due = responder.poll_at().unwrap();
+		assert!((1000..4000).contains(&due));
+		responder.poll(&mut socket, 3999).unwrap();
+		let responses = socket.take_responses("client");
+		assert_eq!(responses.len(), 4);
+		assert!(responses.iter().all(|r| r.location == "http://192.168.1.10/desc.xml" && r.max_age == 100));
+		assert_eq!(responder.poll_at(), Some(50_000));
+
+		// searches built by hand are held to the same MX range
+		responder.handle_search(&SsdpSearchRequest::new_multicast("upnp:rootdevice", 0), "client", now);
+		responder.handle_search(&SsdpSearchRequest::new_multicast(udn, 120), "client", now);
+		responder.poll(&mut socket, 5999).unwrap();
+		assert_eq!(socket.take_responses("client").len(), 2);
+		assert_eq!(responder.poll_at(), Some(50_000));
+
+		// there's only so much room for waiting answers
+		for from in &["a", "b", "c"] {
+			responder.received(&search("ssdp:all", Some(1)), from, now).unwrap();
+		}
+		responder.poll(&mut socket, 1999).unwrap();
+		let to: Vec<&str> = socket.sent.drain(..).map(|(_, to)| to).collect();
+		assert_eq!(to.len(), 8);
+		assert!(!to.contains(&"c"));
+
+		// older versions of our types match, and are answered as such;
+		// unicast searches are answered at once
+		responder.received(&search("urn:schemas-upnp-org:device:DimmableLight:1", None), "client", now).unwrap();
+		responder.received(&search("urn:schemas-upnp-org:device:DimmableLight:3", None), "client", now).unwrap();
+		responder.received(&search(udn, None), "client", now).unwrap();
+		responder.poll(&mut socket, now).unwrap();
+		let responses = socket.take_responses("client");
+		assert_eq!(responses.len(), 2);
+		assert_eq!(responses[0].st, "urn:schemas-upnp-org:device:DimmableLight:1");
+		assert_eq!(responses[0].usn, format!("{}::urn:schemas-upnp-org:device:DimmableLight:1", udn));
+		assert_eq!(responses[1].usn, udn);
+
+		// other traffic is ignored, broken searches are reported
+		let notify = SsdpNotify::new_byebye("upnp:rootdevice", "uuid:other").to_request().to_bytes();
+		responder.received(&notify, "client", now).unwrap();
+		let mut bad = SsdpSearchRequest::new_multicast("ssdp:all", 1).to_request();
+		bad.headers.remove("MAN");
+		assert_eq!(responder.received(&bad.to_bytes(), "client", now), Err(SsdpError::MissingHeader("MAN")));
+		assert_eq!(responder.received(b"\x00\x01", "client", now), Err(SsdpError::InvalidMessage));
+
+		now = 50_000;
+		responder.poll(&mut socket, now).unwrap();
+		assert_eq!(socket.take_notifications().len(), 4);
+		assert_eq!(responder.poll_at(), Some(100_000));
+
+		// byebye, and silence after that
+		responder.received(&search("ssdp:all", Some(1)), "client", now).unwrap();
+		responder.shutdown(&mut socket).unwrap();
+		let byebye = socket.take_notifications();
+		assert_eq!(byebye.len(), 4);
+		assert!(byebye.iter().all(|n| n.nts == SsdpNotifyType::ByeBye && n.location.is_none()));
+		assert_eq!(responder.poll_at(), None);
+		responder.received(&search("ssdp:all", Some(1)), "client", now).unwrap();
+		responder.poll(&mut socket, now + 200_000).unwrap();
+		assert!(socket.sent.is_empty());
+	}
+
+	fn recv(socket: &::std::net::UdpSocket) -> (Vec<u8>, ::std::net::SocketAddr) {
+		let mut buf = [0; 2048];
+		let (n, from) = socket.recv_from(&mut buf).unwrap();
+		(buf[..n].to_vec(), from)
+	}
+
+	#[test]
+	pub fn test_ssdp_control_point() {
+		use std::net::UdpSocket;
+		use std::time::Duration;
+
+		// over loopback, each side's unicast address stands in for the group
+		let mut device_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let mut cp_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+		device_socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		cp_socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		let device_addr = device_socket.local_addr().unwrap();
+		let cp_addr = cp_socket.local_addr().unwrap();
+
+		let udn = "uuid:2fac1234-31f8-11b4-a222-08002b34c003";
+		let config = SsdpResponderConfig { max_age: 60, .. SsdpResponderConfig::default() };
+		let mut responder = SsdpResponder::new(config, cp_addr);
+		responder.add_device(SsdpDevice::new(udn, "urn:schemas-upnp-org:device:Basic:1", "http://127.0.0.1/desc.xml")
+			.service("urn:schemas-upnp-org:service:SwitchPower:1"));
+		let mut cp = SsdpControlPoint::new(device_addr);
+
+		// the device announces itself
+		let mut now = 0;
+		responder.poll(&mut device_socket, now).unwrap();
+		for _ in 0..4 {
+			let (notify, from) = recv(&cp_socket);
+			cp.received(&notify, from, now).unwrap();
+		}
+		assert_eq!(cp.discovered().len(), 4);
+		let root_usn = format!("{}::upnp:rootdevice", udn);
+		let found = cp.find(&root_usn).unwrap();
+		assert_eq!(found.location, "http://127.0.0.1/desc.xml");
+		assert_eq!(found.from, device_addr);
+		assert_eq!(cp.poll_at(), Some(60_000));
+
+		// a search answered twice for the same USN refreshes one entry
+		now = 10_000;
+		cp.search(&mut cp_socket, "upnp:rootdevice", 2, now).unwrap();
+		cp.search(&mut cp_socket, "upnp:rootdevice", 2, now).unwrap();
+		assert!(cp.is_searching(now));
+		assert_eq!(cp.poll_at(), Some(12_000));
+		// the responder ignores a repeat while the answer is waiting, so
+		// the second search arrives after the first was answered
+		now = 11_999;
+		for _ in 0..2 {
+			let (search, from) = recv(&device_socket);
+			assert_eq!(from, cp_addr);
+			responder.received(&search, from, 10_000).unwrap();
+			responder.poll(&mut device_socket, 13_999).unwrap();
+			let (resp, from) = recv(&cp_socket);
+			cp.received(&resp, from, now).unwrap();
+		}
+		assert_eq!(cp.discovered().len(), 4);
+		assert_eq!(cp.find(&root_usn).unwrap().expires, 11_999 + 60_000);
+
+		// responses after the MX window are ignored
+		now = 12_000;
+		cp.expire(now);
+		assert!(!cp.is_searching(now));
+		assert_eq!(cp.poll_at(), Some(60_000));
+		responder.received(&search("upnp:rootdevice", None), cp_addr, now).unwrap();
+		responder.poll(&mut device_socket, now).unwrap();
+		let (resp, from) = recv(&cp_socket);
+		cp.received(&resp, from, now).unwrap();
+		assert_eq!(cp.find(&root_usn).unwrap().expires, 11_999 + 60_000);
+
+		let mut update = SsdpNotify::new_alive(udn, udn, "http://127.0.0.1:8080/desc.xml", 60);
+		update.nts = SsdpNotifyType::Update;
+		update.next_boot_id = Some(2);
+		cp.received(&update.to_request().to_bytes(), device_addr, now).unwrap();
+		assert_eq!(cp.find(udn).unwrap().location, "http://127.0.0.1:8080/desc.xml");
+		assert_eq!(cp.find(udn).unwrap().boot_id, Some(2));
+
+		// byebye removes entries, expiry the rest; searches are ignored
+		let byebye = SsdpNotify::new_byebye(udn, udn).to_request().to_bytes();
+		cp.received(&byebye, device_addr, now).unwrap();
+		assert!(cp.find(udn).is_none());
+		assert_eq!(cp.discovered().len(), 3);
+		cp.received(&search("ssdp:all", Some(1)), cp_addr, now).unwrap();
+		assert_eq!(cp.discovered().len(), 3);
+
+		cp.expire(60_000);
+		assert_eq!(cp.discovered().len(), 1);
+		cp.expire(11_999 + 60_000);
+		assert!(cp.discovered().is_empty());
+		assert_eq!(cp.poll_at(), None);
+	}
 }
 
 
@@ -0,0 +1,317 @@
+#![allow(clippy::redundant_field_names, clippy::len_zero)]
+
+use std::cell::Cell;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use http::*;
+use parser::*;
+use router::*;
+use server::*;
+use alloc::vec::Vec;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::string::ToString;
+
+struct Pipe {
+	data: VecDeque<u8>,
+	closed: bool,
+}
+
+struct SharedPipe {
+	pipe: Mutex<Pipe>,
+	readable: Condvar,
+}
+
+impl SharedPipe {
+	fn new() -> Arc<SharedPipe> {
+		Arc::new(SharedPipe {
+			pipe: Mutex::new(Pipe { data: VecDeque::new(), closed: false }),
+			readable: Condvar::new()
+		})
+	}
+
+	fn close(&self) {
+		self.pipe.lock().unwrap().closed = true;
+		self.readable.notify_all();
+	}
+}
+
+/// One end of an in-memory connection, see `memory_duplex`. Behaves like a
+/// blocking `TcpStream`: reads wait for data, return 0 once the other end
+/// is closed, and honour the read timeout.
+pub struct MemoryStream {
+	incoming: Arc<SharedPipe>,
+	outgoing: Arc<SharedPipe>,
+	read_timeout: Cell<Option<Duration>>,
+}
+
+/// Two connected `MemoryStream`s. Writes never block; the buffers grow as
+/// needed.
+pub fn memory_duplex() -> (MemoryStream, MemoryStream) {
+	let a = SharedPipe::new();
+	let b = SharedPipe::new();
+
+	let left = MemoryStream { incoming: a.clone(), outgoing: b.clone(), read_timeout: Cell::new(None) };
+	let right = MemoryStream { incoming: b, outgoing: a, read_timeout: Cell::new(None) };
+	(left, right)
+}
+
+impl MemoryStream {
+	/// Stop writing. The other end reads 0 once it got everything.
+	pub fn shutdown_write(&self) {
+		self.outgoing.close();
+	}
+}
+
+impl Read for MemoryStream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let deadline = self.read_timeout.get().map(|t| Instant::now() + t);
+		let mut pipe = self.incoming.pipe.lock().unwrap();
+
+		loop {
+			if pipe.data.len() > 0 {
+				let n = ::core::cmp::min(buf.len(), pipe.data.len());
+				for (i, b) in pipe.data.drain(..n).enumerate() {
+					buf[i] = b;
+				}
+				return Ok(n);
+			}
+			if pipe.closed {
+				return Ok(0);
+			}
+
+			pipe = match deadline {
+				Some(deadline) => {
+					let now = Instant::now();
+					if now >= deadline {
+						return Err(io::ErrorKind::TimedOut.into());
+					}
+					self.incoming.readable.wait_timeout(pipe, deadline - now).unwrap().0
+				}
+				None => self.incoming.readable.wait(pipe).unwrap()
+			};
+		}
+	}
+}
+
+impl Write for MemoryStream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let mut pipe = self.outgoing.pipe.lock().unwrap();
+		if pipe.closed {
+			return Err(io::ErrorKind::BrokenPipe.into());
+		}
+		pipe.data.extend(buf.iter().cloned());
+		self.outgoing.readable.notify_all();
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl HttpServerStream for MemoryStream {
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.read_timeout.set(timeout);
+		Ok(())
+	}
+
+	fn close(&mut self) {
+		self.outgoing.close();
+		self.incoming.close();
+	}
+}
+
+impl Drop for MemoryStream {
+	fn drop(&mut self) {
+		self.outgoing.close();
+		self.incoming.close();
+	}
+}
+
+/// Send `request` through the server loop serving `router`, and return
+/// everything it answered until it closed the connection.
+pub fn serve_bytes(request: &[u8], router: &HttpRouter, config: &HttpServerConfig) -> Vec<u8> {
+	let (mut client, server) = memory_duplex();
+	client.write_all(request).unwrap();
+	client.shutdown_write();
+
+	// the buffers hold everything, so the server can run to completion first
+	let _ = serve_stream(server, router, config);
+
+	let mut resp = Vec::new();
+	client.read_to_end(&mut resp).unwrap();
+	resp
+}
+
+/// Starts requests for testing routers, without sockets:
+/// `TestClient::get("/test/5").send(&router)`.
+pub struct TestClient;
+
+impl TestClient {
+	pub fn get(url: &str) -> TestRequest {
+		TestRequest::new(HttpMethod::Get, url)
+	}
+
+	pub fn post(url: &str) -> TestRequest {
+		TestRequest::new(HttpMethod::Post, url)
+	}
+
+	pub fn request(method: HttpMethod, url: &str) -> TestRequest {
+		TestRequest::new(method, url)
+	}
+}
+
+/// Request builder. The request gets `Host: localhost` unless told otherwise.
+pub struct TestRequest {
+	req: HttpRequestMessage,
+	config: HttpServerConfig,
+}
+
+impl TestRequest {
+	pub fn new(method: HttpMethod, url: &str) -> TestRequest {
+		let mut req = HttpRequestMessage::new_get(url, "localhost");
+		req.method = method;
+
+		TestRequest {
+			req: req,
+			config: HttpServerConfig::default()
+		}
+	}
+
+	/// Set a header, replacing one with the same name.
+	pub fn header(mut self, name: &str, value: &str) -> TestRequest {
+		let existing = self.req.headers.keys().find(|k| k.eq_ignore_ascii_case(name)).cloned();
+		if let Some(k) = existing {
+			self.req.headers.remove(&k);
+		}
+		self.req.headers.insert(name.to_string(), value.to_string());
+		self
+	}
+
+	/// Set the body, and its `Content-Length`.
+	pub fn body(mut self, body: &[u8]) -> TestRequest {
+		self.req.body = body.to_vec();
+		self.header("Content-Length", &body.len().to_string())
+	}
+
+	pub fn version(mut self, version: HttpVersion) -> TestRequest {
+		self.req.http_version = version;
+		self
+	}
+
+	/// Limits of the server loop `send` runs the request through.
+	pub fn config(mut self, config: HttpServerConfig) -> TestRequest {
+		self.config = config;
+		self
+	}
+
+	pub fn build(self) -> HttpRequestMessage {
+		self.req
+	}
+
+	/// Run the request through the server loop serving `router`, over an
+	/// in-memory connection. Panics if the answer isn't a valid response.
+	pub fn send(self, router: &HttpRouter) -> HttpResponseMessage {
+		let raw = serve_bytes(&self.req.to_bytes(), router, &self.config);
+
+		let mut parser = HttpParser::new_response_to(self.req.method);
+		parser.parse_bytes(&raw).unwrap();
+		assert!(parser.is_complete(), "incomplete response: {}", String::from_utf8_lossy(&raw));
+		parser.into_response().unwrap()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::io::{Read, Write};
+	use std::thread;
+	use std::time::Duration;
+	use alloc::string::String;
+	use alloc::string::ToString;
+
+	fn router() -> HttpRouter {
+		let mut router = HttpRouter::new();
+		router.add_route(HttpRouteDynamicUrl::new(DynamicUrl::parse_str("/test/:id").unwrap(), HttpMethod::Get, |_, vars| {
+			HttpResponseMessage::text_utf8(vars.get("id").unwrap())
+		}));
+		router.add_route(HttpRouteDynamicUrl::new(DynamicUrl::parse_str("/echo").unwrap(), HttpMethod::Post, |req, _| {
+			let mut resp = HttpResponseMessage::text_utf8(&String::from_utf8_lossy(&req.body));
+			if let Some(v) = req.get_raw_header("x-tag") {
+				resp.headers.insert("X-Tag".to_string(), v.clone());
+			}
+			resp
+		}));
+		router
+	}
+
+	#[test]
+	pub fn test_test_client() {
+		let router = router();
+
+		let resp = TestClient::get("/test/5").send(&router);
+		assert_eq!(resp.response_code, 200);
+		assert_eq!(resp.body, b"5");
+
+		let resp = TestClient::post("/echo").header("X-Tag", "a").body(b"hello").send(&router);
+		assert_eq!(resp.body, b"hello");
+		assert_eq!(resp.get_raw_header("X-Tag").unwrap(), "a");
+
+		assert_eq!(TestClient::get("/missing").send(&router).response_code, 404);
+		assert_eq!(TestClient::get("/test/5").version(HttpVersion::Http20).send(&router).response_code, 505);
+
+		let raw = serve_bytes(b"GET /test/1 HTTP/1.1\r\n\r\n", &router, &HttpServerConfig::default());
+		assert!(raw.starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+	}
+
+	#[test]
+	pub fn test_serve_bytes_parsed() {
+		let mut router = HttpRouter::new();
+		router.add_route(HttpRouteStaticUrl::new_get("/generate_204", |_| HttpResponseMessage::new_status(204, "No Content")));
+
+		let request = HttpRequestMessage::new_get("/generate_204", "clients3.google.com");
+		let raw = serve_bytes(&request.to_bytes(), &router, &HttpServerConfig::default());
+
+		let mut response_parser = HttpParser::new_response();
+		for chunk in raw.chunks(16) {
+			response_parser.parse_bytes(chunk).unwrap();
+		}
+
+		assert_eq!(response_parser.read_how_many_bytes(), 0);
+		let resp = response_parser.get_response().unwrap();
+		assert_eq!(resp.response_code, 204);
+		assert_eq!(resp.response_status, "No Content");
+	}
+
+	#[test]
+	pub fn test_memory_duplex_server_loop() {
+		let (mut client, server) = memory_duplex();
+		let t = thread::spawn(move || {
+			let config = HttpServerConfig { idle_timeout: Some(Duration::from_millis(50)), .. HttpServerConfig::default() };
+			serve_stream(server, &router(), &config).unwrap();
+		});
+
+		// two requests on the same connection, then the server gives up on
+		// the idle client
+		let mut buf = [0; 1024];
+		for id in &["1", "2"] {
+			client.write_all(format!("GET /test/{} HTTP/1.1\r\nHost: a\r\n\r\n", id).as_bytes()).unwrap();
+			let n = client.read(&mut buf).unwrap();
+			assert!(buf[..n].ends_with(format!("\r\n\r\n{}", id).as_bytes()));
+		}
+		assert_eq!(client.read(&mut buf).unwrap(), 0);
+		t.join().unwrap();
+
+		let (mut a, b) = memory_duplex();
+		b.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+		let mut b = b;
+		assert_eq!(b.read(&mut buf).unwrap_err().kind(), io::ErrorKind::TimedOut);
+		drop(b);
+		assert!(a.write(b"x").is_err());
+	}
+}
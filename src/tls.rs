@@ -0,0 +1,101 @@
+#![allow(deprecated)]
+
+use std::sync::Arc;
+use alloc::vec::Vec;
+
+use rustls;
+use webpki_roots;
+
+/// Server TLS setup from a DER encoded certificate chain, leaf first, and
+/// the PKCS#8 or RSA private key of the leaf. For `HttpServerBuilder::tls`.
+pub fn tls_server_config(cert_chain: Vec<Vec<u8>>, private_key: Vec<u8>) -> Result<Arc<rustls::ServerConfig>, rustls::Error> {
+	let cert_chain = cert_chain.into_iter().map(rustls::Certificate).collect();
+	let config = try!(rustls::ServerConfig::builder()
+		.with_safe_defaults()
+		.with_no_client_auth()
+		.with_single_cert(cert_chain, rustls::PrivateKey(private_key)));
+
+	Ok(Arc::new(config))
+}
+
+/// Client TLS setup trusting the Mozilla root certificates. What
+/// `HttpClient` uses unless told otherwise.
+pub fn tls_client_config() -> Arc<rustls::ClientConfig> {
+	let mut roots = rustls::RootCertStore::empty();
+	roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+		rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(ta.subject, ta.spki, ta.name_constraints)
+	}));
+
+	Arc::new(client_config(roots))
+}
+
+/// Client TLS setup trusting only the given DER encoded certificates, like
+/// a device's self-signed one.
+pub fn tls_client_config_with_roots(roots: &[Vec<u8>]) -> Result<Arc<rustls::ClientConfig>, rustls::Error> {
+	let mut store = rustls::RootCertStore::empty();
+	for cert in roots {
+		try!(store.add(&rustls::Certificate(cert.clone())).map_err(|e| rustls::Error::General(format!("{:?}", e))));
+	}
+
+	Ok(Arc::new(client_config(store)))
+}
+
+fn client_config(roots: rustls::RootCertStore) -> rustls::ClientConfig {
+	rustls::ClientConfig::builder()
+		.with_safe_defaults()
+		.with_root_certificates(roots)
+		.with_no_client_auth()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use client::*;
+	use http::*;
+	use router::*;
+	use server::*;
+	use url::*;
+
+	use rcgen;
+	use std::thread;
+	use alloc::string::ToString;
+
+	#[test]
+	pub fn test_https_client_and_server() {
+		let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+		let cert_der = cert.serialize_der().unwrap();
+		let server_tls = tls_server_config(vec![cert_der.clone()], cert.serialize_private_key_der()).unwrap();
+
+		let server = HttpServerBuilder::new()
+			.bind("127.0.0.1:0")
+			.route(HttpRouteStaticUrl::new_get("/", |_| HttpResponseMessage::text_utf8("secret")))
+			.tls(server_tls)
+			.build()
+			.unwrap();
+		let port = server.local_addr().port();
+		let handle = server.handle();
+		let t = thread::spawn(move || server.run().unwrap());
+
+		let url = Url::parse(&format!("https://localhost:{}/", port)).unwrap();
+		let config = HttpClientConfig { tls: Some(tls_client_config_with_roots(&[cert_der]).unwrap()), .. HttpClientConfig::default() };
+		let client = HttpClient::with_config(config);
+		for _ in 0..2 {
+			let resp = client.get(&url).unwrap();
+			assert_eq!(resp.response_code, 200);
+			assert_eq!(resp.body, b"secret");
+			assert_eq!(client.idle_connections(), 1);
+		}
+		drop(client);
+
+		// the self-signed certificate isn't trusted by default
+		assert!(HttpClient::new().get(&url).is_err());
+
+		// nor does plain HTTP get through
+		let url = Url::parse(&format!("http://localhost:{}/", port)).unwrap();
+		let config = HttpClientConfig { max_idle_per_host: 0, .. HttpClientConfig::default() };
+		assert!(HttpClient::with_config(config).get(&url).is_err());
+
+		handle.shutdown();
+		t.join().unwrap();
+	}
+}
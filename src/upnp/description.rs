@@ -0,0 +1,547 @@
+#![allow(clippy::redundant_field_names, clippy::len_zero, clippy::new_without_default)]
+
+use http::*;
+use router::*;
+use super::xml::*;
+use alloc::vec::Vec;
+use alloc::string::String;
+use alloc::string::ToString;
+
+/// Content type of description documents and SOAP bodies.
+pub const UPNP_XML_CONTENT_TYPE: &str = "text/xml; charset=\"utf-8\"";
+
+/// A device, root or embedded, as described in the device description
+/// document. `manufacturer` and `model_name` are required by UPnP, so
+/// they are always written, empty if not set.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UpnpDevice {
+	/// `urn:schemas-upnp-org:device:BinaryLight:1` or similar.
+	pub device_type: String,
+	pub friendly_name: String,
+	/// Unique device name, `uuid:...`. The same as announced over SSDP.
+	pub udn: String,
+	pub manufacturer: String,
+	pub manufacturer_url: Option<String>,
+	pub model_description: Option<String>,
+	pub model_name: String,
+	pub model_number: Option<String>,
+	pub model_url: Option<String>,
+	pub serial_number: Option<String>,
+	pub presentation_url: Option<String>,
+	pub services: Vec<UpnpService>,
+	/// Embedded devices.
+	pub devices: Vec<UpnpDevice>,
+}
+
+impl UpnpDevice {
+	pub fn new(device_type: &str, friendly_name: &str, udn: &str) -> UpnpDevice {
+		UpnpDevice {
+			device_type: device_type.to_string(),
+			friendly_name: friendly_name.to_string(),
+			udn: udn.to_string(),
+			manufacturer: String::new(),
+			manufacturer_url: None,
+			model_description: None,
+			model_name: String::new(),
+			model_number: None,
+			model_url: None,
+			serial_number: None,
+			presentation_url: None,
+			services: Vec::new(),
+			devices: Vec::new()
+		}
+	}
+
+	pub fn manufacturer(mut self, manufacturer: &str) -> UpnpDevice {
+		self.manufacturer = manufacturer.to_string();
+		self
+	}
+
+	pub fn manufacturer_url(mut self, url: &str) -> UpnpDevice {
+		self.manufacturer_url = Some(url.to_string());
+		self
+	}
+
+	pub fn model_description(mut self, description: &str) -> UpnpDevice {
+		self.model_description = Some(description.to_string());
+		self
+	}
+
+	pub fn model_name(mut self, model_name: &str) -> UpnpDevice {
+		self.model_name = model_name.to_string();
+		self
+	}
+
+	pub fn model_number(mut self, model_number: &str) -> UpnpDevice {
+		self.model_number = Some(model_number.to_string());
+		self
+	}
+
+	pub fn model_url(mut self, url: &str) -> UpnpDevice {
+		self.model_url = Some(url.to_string());
+		self
+	}
+
+	pub fn serial_number(mut self, serial_number: &str) -> UpnpDevice {
+		self.serial_number = Some(serial_number.to_string());
+		self
+	}
+
+	pub fn presentation_url(mut self, url: &str) -> UpnpDevice {
+		self.presentation_url = Some(url.to_string());
+		self
+	}
+
+	/// Services with the default URLs get this device's UDN in them,
+	/// `/upnp/<udn>/<id>/...`, so the same service on two devices doesn't
+	/// collide. Route the service as found in `services` afterwards.
+	pub fn service(mut self, service: UpnpService) -> UpnpDevice {
+		let mut service = service;
+		if service.has_default_urls() {
+			let udn = self.udn.trim_start_matches("uuid:");
+			let id = service_id_name(&service.service_id).to_string();
+			service.scpd_url = format!("/upnp/{}/{}/scpd.xml", udn, id);
+			service.control_url = format!("/upnp/{}/{}/control", udn, id);
+			service.event_sub_url = format!("/upnp/{}/{}/event", udn, id);
+		}
+		self.services.push(service);
+		self
+	}
+
+	pub fn device(mut self, device: UpnpDevice) -> UpnpDevice {
+		self.devices.push(device);
+		self
+	}
+
+	/// This device's services and those of its embedded devices.
+	pub fn all_services(&self) -> Vec<&UpnpService> {
+		let mut services: Vec<&UpnpService> = self.services.iter().collect();
+		for device in &self.devices {
+			services.extend(device.all_services());
+		}
+		services
+	}
+
+	/// The device description document, with this as the root device.
+	pub fn to_xml(&self) -> String {
+		let mut w = XmlWriter::new();
+		w.start_root("root", "urn:schemas-upnp-org:device-1-0");
+		w.start("specVersion");
+		w.element("major", "1");
+		w.element("minor", "1");
+		w.end("specVersion");
+		self.write_device(&mut w);
+		w.end("root");
+		w.into_string()
+	}
+
+	fn write_device(&self, w: &mut XmlWriter) {
+		w.start("device");
+		w.element("deviceType", &self.device_type);
+		w.element("friendlyName", &self.friendly_name);
+		w.element("manufacturer", &self.manufacturer);
+		w.optional_element("manufacturerURL", &self.manufacturer_url);
+		w.optional_element("modelDescription", &self.model_description);
+		w.element("modelName", &self.model_name);
+		w.optional_element("modelNumber", &self.model_number);
+		w.optional_element("modelURL", &self.model_url);
+		w.optional_element("serialNumber", &self.serial_number);
+		w.element("UDN", &self.udn);
+
+		if self.services.len() > 0 {
+			w.start("serviceList");
+			for service in &self.services {
+				w.start("service");
+				w.element("serviceType", &service.service_type);
+				w.element("serviceId", &service.service_id);
+				w.element("SCPDURL", &service.scpd_url);
+				w.element("controlURL", &service.control_url);
+				w.element("eventSubURL", &service.event_sub_url);
+				w.end("service");
+			}
+			w.end("serviceList");
+		}
+
+		if self.devices.len() > 0 {
+			w.start("deviceList");
+			for device in &self.devices {
+				device.write_device(w);
+			}
+			w.end("deviceList");
+		}
+
+		w.optional_element("presentationURL", &self.presentation_url);
+		w.end("device");
+	}
+}
+
+/// A service of a device, with where its description, control and
+/// eventing live.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UpnpService {
+	/// `urn:schemas-upnp-org:service:SwitchPower:1` or similar.
+	pub service_type: String,
+	/// `urn:upnp-org:serviceId:SwitchPower` or similar, unique within the
+	/// device.
+	pub service_id: String,
+	pub scpd_url: String,
+	pub control_url: String,
+	pub event_sub_url: String,
+	pub scpd: UpnpScpd,
+}
+
+impl UpnpService {
+	/// The URLs default to `/upnp/<id>/scpd.xml`, `/upnp/<id>/control`
+	/// and `/upnp/<id>/event`, `<id>` being the last part of `service_id`.
+	/// `UpnpDevice::service` adds the device to them.
+	pub fn new(service_type: &str, service_id: &str) -> UpnpService {
+		let id = service_id_name(service_id);
+
+		UpnpService {
+			service_type: service_type.to_string(),
+			service_id: service_id.to_string(),
+			scpd_url: format!("/upnp/{}/scpd.xml", id),
+			control_url: format!("/upnp/{}/control", id),
+			event_sub_url: format!("/upnp/{}/event", id),
+			scpd: UpnpScpd::new()
+		}
+	}
+
+	pub fn urls(mut self, scpd_url: &str, control_url: &str, event_sub_url: &str) -> UpnpService {
+		self.scpd_url = scpd_url.to_string();
+		self.control_url = control_url.to_string();
+		self.event_sub_url = event_sub_url.to_string();
+		self
+	}
+
+	pub fn scpd(mut self, scpd: UpnpScpd) -> UpnpService {
+		self.scpd = scpd;
+		self
+	}
+
+	fn has_default_urls(&self) -> bool {
+		let id = service_id_name(&self.service_id);
+		self.scpd_url == format!("/upnp/{}/scpd.xml", id) &&
+			self.control_url == format!("/upnp/{}/control", id) &&
+			self.event_sub_url == format!("/upnp/{}/event", id)
+	}
+}
+
+/// `SwitchPower` for `urn:upnp-org:serviceId:SwitchPower`.
+fn service_id_name(service_id: &str) -> &str {
+	match service_id.rfind(':') {
+		Some(i) => &service_id[i + 1..],
+		None => service_id
+	}
+}
+
+/// Service control protocol description: the actions of a service and
+/// its state variables.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UpnpScpd {
+	pub actions: Vec<UpnpAction>,
+	pub state_variables: Vec<UpnpStateVariable>,
+}
+
+impl UpnpScpd {
+	pub fn new() -> UpnpScpd {
+		UpnpScpd {
+			actions: Vec::new(),
+			state_variables: Vec::new()
+		}
+	}
+
+	pub fn action(mut self, action: UpnpAction) -> UpnpScpd {
+		self.actions.push(action);
+		self
+	}
+
+	pub fn state_variable(mut self, variable: UpnpStateVariable) -> UpnpScpd {
+		self.state_variables.push(variable);
+		self
+	}
+
+	pub fn find_action(&self, name: &str) -> Option<&UpnpAction> {
+		self.actions.iter().find(|a| a.name == name)
+	}
+
+	pub fn find_state_variable(&self, name: &str) -> Option<&UpnpStateVariable> {
+		self.state_variables.iter().find(|v| v.name == name)
+	}
+
+	pub fn to_xml(&self) -> String {
+		let mut w = XmlWriter::new();
+		w.start_root("scpd", "urn:schemas-upnp-org:service-1-0");
+		w.start("specVersion");
+		w.element("major", "1");
+		w.element("minor", "1");
+		w.end("specVersion");
+
+		if self.actions.len() > 0 {
+			w.start("actionList");
+			for action in &self.actions {
+				w.start("action");
+				w.element("name", &action.name);
+				if action.arguments.len() > 0 {
+					w.start("argumentList");
+					for arg in &action.arguments {
+						w.start("argument");
+						w.element("name", &arg.name);
+						w.element("direction", arg.direction.as_str());
+						w.element("relatedStateVariable", &arg.related_state_variable);
+						w.end("argument");
+					}
+					w.end("argumentList");
+				}
+				w.end("action");
+			}
+			w.end("actionList");
+		}
+
+		w.start("serviceStateTable");
+		for var in &self.state_variables {
+			w.start_with_attributes("stateVariable", &[("sendEvents", if var.send_events { "yes" } else { "no" })]);
+			w.element("name", &var.name);
+			w.element("dataType", &var.data_type);
+			w.optional_element("defaultValue", &var.default_value);
+			if var.allowed_values.len() > 0 {
+				w.start("allowedValueList");
+				for v in &var.allowed_values {
+					w.element("allowedValue", v);
+				}
+				w.end("allowedValueList");
+			}
+			w.end("stateVariable");
+		}
+		w.end("serviceStateTable");
+
+		w.end("scpd");
+		w.into_string()
+	}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UpnpDirection {
+	In,
+	Out,
+}
+
+impl UpnpDirection {
+	pub fn as_str(&self) -> &'static str {
+		match *self {
+			UpnpDirection::In => "in",
+			UpnpDirection::Out => "out"
+		}
+	}
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UpnpAction {
+	pub name: String,
+	/// In arguments first, then out arguments, each in call order.
+	pub arguments: Vec<UpnpArgument>,
+}
+
+impl UpnpAction {
+	pub fn new(name: &str) -> UpnpAction {
+		UpnpAction {
+			name: name.to_string(),
+			arguments: Vec::new()
+		}
+	}
+
+	pub fn argument_in(mut self, name: &str, related_state_variable: &str) -> UpnpAction {
+		self.arguments.push(UpnpArgument::new(name, UpnpDirection::In, related_state_variable));
+		self
+	}
+
+	pub fn argument_out(mut self, name: &str, related_state_variable: &str) -> UpnpAction {
+		self.arguments.push(UpnpArgument::new(name, UpnpDirection::Out, related_state_variable));
+		self
+	}
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UpnpArgument {
+	pub name: String,
+	pub direction: UpnpDirection,
+	/// The state variable giving the argument its type.
+	pub related_state_variable: String,
+}
+
+impl UpnpArgument {
+	pub fn new(name: &str, direction: UpnpDirection, related_state_variable: &str) -> UpnpArgument {
+		UpnpArgument {
+			name: name.to_string(),
+			direction: direction,
+			related_state_variable: related_state_variable.to_string()
+		}
+	}
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UpnpStateVariable {
+	pub name: String,
+	/// UPnP data type: `boolean`, `ui4`, `i4`, `string`...
+	pub data_type: String,
+	pub send_events: bool,
+	pub default_value: Option<String>,
+	pub allowed_values: Vec<String>,
+}
+
+impl UpnpStateVariable {
+	pub fn new(name: &str, data_type: &str) -> UpnpStateVariable {
+		UpnpStateVariable {
+			name: name.to_string(),
+			data_type: data_type.to_string(),
+			send_events: false,
+			default_value: None,
+			allowed_values: Vec::new()
+		}
+	}
+
+	pub fn evented(mut self) -> UpnpStateVariable {
+		self.send_events = true;
+		self
+	}
+
+	pub fn default_value(mut self, value: &str) -> UpnpStateVariable {
+		self.default_value = Some(value.to_string());
+		self
+	}
+
+	pub fn allowed_value(mut self, value: &str) -> UpnpStateVariable {
+		self.allowed_values.push(value.to_string());
+		self
+	}
+}
+
+/// Serves the description document of a root device at the given path,
+/// and the SCPD of each of its services, embedded devices included, at
+/// their `scpd_url`. The documents are rendered once, up front. Answers
+/// GET and HEAD, whatever the query string.
+pub struct UpnpDescriptionRoute {
+	documents: Vec<(String, String)>,
+}
+
+impl UpnpDescriptionRoute {
+	pub fn new(path: &str, device: &UpnpDevice) -> UpnpDescriptionRoute {
+		let mut documents = vec![(path.to_string(), device.to_xml())];
+		for service in device.all_services() {
+			debug_assert!(documents.iter().all(|d| d.0 != service.scpd_url), "{} is served twice", service.scpd_url);
+			documents.push((service.scpd_url.clone(), service.scpd.to_xml()));
+		}
+
+		UpnpDescriptionRoute {
+			documents: documents
+		}
+	}
+
+	fn find(&self, url: &str) -> Option<&String> {
+		let path = url.split('?').next().unwrap_or(url);
+		self.documents.iter().find(|d| d.0 == path).map(|d| &d.1)
+	}
+}
+
+impl HttpRoute for UpnpDescriptionRoute {
+	fn try(&self, msg: &HttpRequestMessage) -> Result<bool, HttpRouteError> {
+		Ok((msg.method == HttpMethod::Get || msg.method == HttpMethod::Head) && self.find(&msg.url).is_some())
+	}
+
+	fn execute(&self, msg: &HttpRequestMessage) -> Result<HttpResponseMessage, HttpRouteError> {
+		match self.find(&msg.url) {
+			Some(xml) => {
+				let mut resp = HttpResponseMessage::text_utf8(xml);
+				resp.headers.insert("Content-Type".to_string(), UPNP_XML_CONTENT_TYPE.to_string());
+				Ok(resp)
+			}
+			None => Err(HttpRouteError::NoRouteFound)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use test_support::*;
+
+	fn light() -> UpnpDevice {
+		let scpd = UpnpScpd::new()
+			.action(UpnpAction::new("SetTarget").argument_in("newTargetValue", "Target"))
+			.action(UpnpAction::new("GetStatus").argument_out("ResultStatus", "Status"))
+			.state_variable(UpnpStateVariable::new("Target", "boolean").default_value("0"))
+			.state_variable(UpnpStateVariable::new("Status", "boolean").evented());
+
+		UpnpDevice::new("urn:schemas-upnp-org:device:BinaryLight:1", "Kitchen & Hall", "uuid:light-1")
+			.manufacturer("ACME")
+			.model_name("Light <1>")
+			.serial_number("42")
+			.service(UpnpService::new("urn:schemas-upnp-org:service:SwitchPower:1", "urn:upnp-org:serviceId:SwitchPower").scpd(scpd))
+			.device(UpnpDevice::new("urn:schemas-upnp-org:device:Sensor:1", "Sensor", "uuid:sensor-1")
+				.service(UpnpService::new("urn:schemas-upnp-org:service:Temperature:1", "urn:upnp-org:serviceId:Temp")))
+	}
+
+	#[test]
+	pub fn test_device_description() {
+		let xml = light().to_xml();
+		assert!(xml.starts_with("<?xml version=\"1.0\"?>\n<root xmlns=\"urn:schemas-upnp-org:device-1-0\">\n"));
+		assert!(xml.contains("  <specVersion>\n    <major>1</major>\n    <minor>1</minor>\n  </specVersion>\n"));
+		assert!(xml.contains("<friendlyName>Kitchen &amp; Hall</friendlyName>"));
+		assert!(xml.contains("<modelName>Light &lt;1&gt;</modelName>"));
+		assert!(xml.contains("<serialNumber>42</serialNumber>"));
+		assert!(!xml.contains("modelURL"));
+		assert!(xml.contains("<SCPDURL>/upnp/light-1/SwitchPower/scpd.xml</SCPDURL>"));
+		assert!(xml.contains("<controlURL>/upnp/light-1/SwitchPower/control</controlURL>"));
+		assert!(xml.contains("<eventSubURL>/upnp/light-1/SwitchPower/event</eventSubURL>"));
+		assert!(xml.contains("<SCPDURL>/upnp/sensor-1/Temp/scpd.xml</SCPDURL>"));
+		assert!(xml.contains("<deviceList>\n      <device>\n        <deviceType>urn:schemas-upnp-org:device:Sensor:1</deviceType>"));
+		assert!(xml.ends_with("  </device>\n</root>\n"));
+
+		let scpd = light().services[0].scpd.to_xml();
+		assert!(scpd.contains("<scpd xmlns=\"urn:schemas-upnp-org:service-1-0\">"));
+		assert!(scpd.contains("<name>SetTarget</name>\n      <argumentList>\n        <argument>\n          <name>newTargetValue</name>\n          <direction>in</direction>\n          <relatedStateVariable>Target</relatedStateVariable>"));
+		assert!(scpd.contains("<stateVariable sendEvents=\"no\">\n      <name>Target</name>\n      <dataType>boolean</dataType>\n      <defaultValue>0</defaultValue>\n    </stateVariable>"));
+		assert!(scpd.contains("<stateVariable sendEvents=\"yes\">\n      <name>Status</name>"));
+	}
+
+	#[test]
+	pub fn test_embedded_service_urls() {
+		// the same service on two devices
+		let switch = UpnpService::new("urn:schemas-upnp-org:service:SwitchPower:1", "urn:upnp-org:serviceId:SwitchPower");
+		let device = UpnpDevice::new("urn:schemas-upnp-org:device:BinaryLight:1", "Hall", "uuid:light-1")
+			.service(switch.clone())
+			.device(UpnpDevice::new("urn:schemas-upnp-org:device:BinaryLight:1", "Porch", "uuid:light-2").service(switch.clone()));
+		let urls: Vec<&str> = device.all_services().iter().map(|s| s.control_url.as_str()).collect();
+		assert_eq!(urls, ["/upnp/light-1/SwitchPower/control", "/upnp/light-2/SwitchPower/control"]);
+
+		// URLs set by hand are left alone
+		let device = UpnpDevice::new("urn:schemas-upnp-org:device:BinaryLight:1", "Hall", "uuid:light-1")
+			.service(switch.urls("/scpd.xml", "/control", "/event"));
+		assert_eq!(device.services[0].control_url, "/control");
+	}
+
+	#[test]
+	pub fn test_description_route() {
+		let device = light();
+		let mut router = HttpRouter::new();
+		router.add_route(UpnpDescriptionRoute::new("/description.xml", &device));
+
+		let resp = TestClient::get("/description.xml").send(&router);
+		assert_eq!(resp.response_code, 200);
+		assert_eq!(resp.get_raw_header("Content-Type").unwrap(), "text/xml; charset=\"utf-8\"");
+		assert_eq!(resp.body, device.to_xml().as_bytes());
+
+		let resp = TestClient::get("/upnp/light-1/SwitchPower/scpd.xml").send(&router);
+		assert_eq!(resp.body, device.services[0].scpd.to_xml().as_bytes());
+		let resp = TestClient::get("/upnp/sensor-1/Temp/scpd.xml?x=1").send(&router);
+		assert!(String::from_utf8(resp.body).unwrap().contains("<serviceStateTable>\n  </serviceStateTable>"));
+
+		let resp = TestClient::request(HttpMethod::Head, "/description.xml").send(&router);
+		assert_eq!(resp.response_code, 200);
+		assert_eq!(resp.get_raw_header("Content-Length").unwrap(), &device.to_xml().len().to_string());
+		assert!(resp.body.is_empty());
+
+		assert_eq!(TestClient::get("/upnp/light-1/SwitchPower/control").send(&router).response_code, 404);
+		assert_eq!(TestClient::post("/description.xml").send(&router).response_code, 404);
+	}
+}
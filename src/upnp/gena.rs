@@ -0,0 +1,685 @@
+#![allow(deprecated, clippy::redundant_field_names, clippy::len_zero, clippy::new_without_default)]
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use client::*;
+use http::*;
+use router::*;
+use url::*;
+use super::description::*;
+use super::xml::*;
+use alloc::vec::Vec;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::string::ToString;
+
+#[derive(Debug, Clone)]
+pub struct GenaConfig {
+	/// Longest subscription granted, in seconds. Longer and infinite
+	/// requests get this, as do requests without a `TIMEOUT`.
+	pub max_timeout: u32,
+	/// Subscriptions to all services together. New ones past that are
+	/// answered with 503.
+	pub max_subscriptions: usize,
+	/// Callback URLs kept per subscription; the rest are ignored.
+	pub max_callbacks: usize,
+	/// Events waiting for a subscriber. One that falls further behind is
+	/// dropped.
+	pub max_queued_events: usize,
+	/// For sending the NOTIFY requests. Redirects aren't followed.
+	pub client: HttpClientConfig,
+}
+
+impl Default for GenaConfig {
+	fn default() -> GenaConfig {
+		GenaConfig {
+			max_timeout: 1800,
+			max_subscriptions: 32,
+			max_callbacks: 4,
+			max_queued_events: 16,
+			client: HttpClientConfig {
+				connect_timeout: Some(Duration::from_secs(5)),
+				read_timeout: Some(Duration::from_secs(5)),
+				write_timeout: Some(Duration::from_secs(5)),
+				max_redirects: 0,
+				.. HttpClientConfig::default()
+			}
+		}
+	}
+}
+
+struct GenaSubscription {
+	sid: String,
+	event_sub_url: String,
+	callbacks: Vec<Url>,
+	expires: Instant,
+	/// SEQ of the next event.
+	seq: u32,
+	/// Nothing else is queued before the initial event with every variable.
+	initial_queued: bool,
+	/// Events not sent yet: SEQ and body.
+	queue: VecDeque<(u32, String)>,
+	/// A thread is sending the queued events.
+	delivering: bool,
+}
+
+struct GenaService {
+	event_sub_url: String,
+	/// Current values of the evented state variables.
+	variables: Vec<(String, String)>,
+}
+
+struct GenaState {
+	services: Vec<GenaService>,
+	subscriptions: Vec<GenaSubscription>,
+	sid_counter: u64,
+}
+
+/// A subscription rejected, with the status to answer.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum GenaError {
+	/// 400: SID together with CALLBACK or NT.
+	IncompatibleHeaders,
+	/// 412: missing or invalid CALLBACK or NT, or an unknown SID.
+	PreconditionFailed,
+	/// 503: no room for another subscription.
+	TooManySubscriptions,
+}
+
+impl GenaError {
+	fn to_response(self) -> HttpResponseMessage {
+		match self {
+			GenaError::IncompatibleHeaders => HttpResponseMessage::new_status(400, "Bad Request"),
+			GenaError::PreconditionFailed => HttpResponseMessage::new_status(412, "Precondition Failed"),
+			GenaError::TooManySubscriptions => HttpResponseMessage::new_status(503, "Service Unavailable")
+		}
+	}
+}
+
+/// Eventing of a device's services (UPnP Device Architecture, section 4).
+/// Tracks subscriptions, which `GenaRoute` takes, and sends the changes of
+/// evented state variables to the subscribers as NOTIFY requests. Each
+/// subscriber has a queue of events, sent in SEQ order by a thread of its
+/// own while there are any; a subscriber that doesn't take one is dropped.
+pub struct GenaPublisher {
+	config: GenaConfig,
+	shared: Arc<GenaShared>,
+	random: RandomState,
+}
+
+/// What the delivery threads need.
+struct GenaShared {
+	client: HttpClient,
+	state: Mutex<GenaState>,
+}
+
+impl GenaPublisher {
+	pub fn new() -> GenaPublisher {
+		GenaPublisher::with_config(GenaConfig::default())
+	}
+
+	pub fn with_config(config: GenaConfig) -> GenaPublisher {
+		GenaPublisher {
+			shared: Arc::new(GenaShared {
+				client: HttpClient::with_config(config.client.clone()),
+				state: Mutex::new(GenaState { services: Vec::new(), subscriptions: Vec::new(), sid_counter: 0 })
+			}),
+			config: config,
+			random: RandomState::new()
+		}
+	}
+
+	/// Accept subscriptions at the service's `event_sub_url`. Its evented
+	/// state variables start at their default value, or empty.
+	pub fn add_service(&self, service: &UpnpService) {
+		let variables = service.scpd.state_variables.iter()
+			.filter(|v| v.send_events)
+			.map(|v| (v.name.clone(), v.default_value.clone().unwrap_or_default()))
+			.collect();
+
+		let mut state = self.shared.state.lock().unwrap();
+		state.services.retain(|s| s.event_sub_url != service.event_sub_url);
+		state.services.push(GenaService { event_sub_url: service.event_sub_url.clone(), variables: variables });
+	}
+
+	pub fn set_variable(&self, service: &UpnpService, name: &str, value: &str) -> usize {
+		self.set_variables(service, &[(name, value)])
+	}
+
+	/// Update evented state variables, and queue the ones that changed for
+	/// the subscribers, in one event. Variables that aren't evented are
+	/// ignored. Returns how many subscribers the event is queued for.
+	pub fn set_variables(&self, service: &UpnpService, variables: &[(&str, &str)]) -> usize {
+		let mut state = self.shared.state.lock().unwrap();
+		let mut changed = Vec::new();
+		{
+			let s = match state.services.iter_mut().find(|s| s.event_sub_url == service.event_sub_url) {
+				Some(s) => s,
+				None => { return 0; }
+			};
+			for &(name, value) in variables {
+				match s.variables.iter_mut().find(|v| v.0 == name) {
+					Some(ref v) if v.1 == value => continue,
+					Some(v) => v.1 = value.to_string(),
+					None => continue
+				}
+				changed.push((name.to_string(), value.to_string()));
+			}
+		}
+		if changed.len() == 0 {
+			return 0;
+		}
+
+		// a subscriber this far behind isn't taking events
+		let max_queued = self.config.max_queued_events;
+		state.subscriptions.retain(|s| s.event_sub_url != service.event_sub_url || s.queue.len() < max_queued);
+
+		let body = property_set_xml(&changed);
+		let now = Instant::now();
+		let mut queued = 0;
+		let mut start = Vec::new();
+		for sub in state.subscriptions.iter_mut() {
+			if sub.event_sub_url != service.event_sub_url || !sub.initial_queued || sub.expires <= now {
+				continue;
+			}
+			let seq = next_seq(sub);
+			sub.queue.push_back((seq, body.clone()));
+			if !sub.delivering {
+				sub.delivering = true;
+				start.push(sub.sid.clone());
+			}
+			queued += 1;
+		}
+		drop(state);
+
+		for sid in start {
+			self.start_delivery(sid);
+		}
+		queued
+	}
+
+	/// Queue every evented variable for a new subscriber, with SEQ 0.
+	fn queue_initial_event(&self, sid: &str) {
+		{
+			let mut state = self.shared.state.lock().unwrap();
+			let GenaState { ref services, ref mut subscriptions, .. } = *state;
+			let sub = match subscriptions.iter_mut().find(|s| s.sid == sid && !s.initial_queued) {
+				Some(sub) => sub,
+				None => { return; }
+			};
+			let variables = match services.iter().find(|s| s.event_sub_url == sub.event_sub_url) {
+				Some(s) => s.variables.clone(),
+				None => Vec::new()
+			};
+			sub.initial_queued = true;
+			let seq = next_seq(sub);
+			sub.queue.push_back((seq, property_set_xml(&variables)));
+			if sub.delivering {
+				return;
+			}
+			sub.delivering = true;
+		}
+
+		self.start_delivery(sid.to_string());
+	}
+
+	/// Send the subscriber's queued events on a thread, until there are no
+	/// more. The first one that isn't taken ends the subscription.
+	fn start_delivery(&self, sid: String) {
+		let shared = self.shared.clone();
+		thread::spawn(move || {
+			loop {
+				let (callbacks, seq, body) = {
+					let mut state = shared.state.lock().unwrap();
+					let sub = match state.subscriptions.iter_mut().find(|s| s.sid == sid) {
+						Some(sub) => sub,
+						None => { return; }
+					};
+					match sub.queue.pop_front() {
+						Some((seq, body)) => (sub.callbacks.clone(), seq, body),
+						None => {
+							sub.delivering = false;
+							return;
+						}
+					}
+				};
+
+				if !shared.deliver(&sid, &callbacks, seq, &body) {
+					shared.state.lock().unwrap().subscriptions.retain(|s| s.sid != sid);
+					return;
+				}
+			}
+		});
+	}
+
+	/// Handle a SUBSCRIBE, new or renewal. Returns the SID and the granted
+	/// timeout in seconds.
+	fn subscribe(&self, req: &HttpRequestMessage) -> Result<(String, u32), GenaError> {
+		let timeout = parse_timeout(req.get_raw_header("TIMEOUT"), self.config.max_timeout);
+		let expires = Instant::now() + Duration::from_secs(timeout as u64);
+		let callback = req.get_raw_header("CALLBACK");
+		let nt = req.get_raw_header("NT");
+
+		let mut state = self.shared.state.lock().unwrap();
+		state.remove_expired(Instant::now());
+
+		if let Some(sid) = req.get_raw_header("SID") {
+			if callback.is_some() || nt.is_some() {
+				return Err(GenaError::IncompatibleHeaders);
+			}
+			let sub = try!(state.subscriptions.iter_mut()
+				.find(|s| &s.sid == sid && s.event_sub_url == req.url)
+				.ok_or(GenaError::PreconditionFailed));
+			sub.expires = expires;
+			return Ok((sub.sid.clone(), timeout));
+		}
+
+		if nt.map(|nt| nt.trim()) != Some("upnp:event") {
+			return Err(GenaError::PreconditionFailed);
+		}
+		let mut callbacks = try!(callback.and_then(|c| parse_callbacks(c)).ok_or(GenaError::PreconditionFailed));
+		callbacks.truncate(self.config.max_callbacks);
+		if !state.services.iter().any(|s| s.event_sub_url == req.url) {
+			return Err(GenaError::PreconditionFailed);
+		}
+		if state.subscriptions.len() >= self.config.max_subscriptions {
+			return Err(GenaError::TooManySubscriptions);
+		}
+
+		state.sid_counter += 1;
+		let sid = self.new_sid(state.sid_counter);
+		state.subscriptions.push(GenaSubscription {
+			sid: sid.clone(),
+			event_sub_url: req.url.clone(),
+			callbacks: callbacks,
+			expires: expires,
+			seq: 0,
+			initial_queued: false,
+			queue: VecDeque::new(),
+			delivering: false
+		});
+		Ok((sid, timeout))
+	}
+
+	/// Handle an UNSUBSCRIBE.
+	fn unsubscribe(&self, req: &HttpRequestMessage) -> Result<(), GenaError> {
+		if req.get_raw_header("CALLBACK").is_some() || req.get_raw_header("NT").is_some() {
+			return Err(GenaError::IncompatibleHeaders);
+		}
+		let sid = try!(req.get_raw_header("SID").ok_or(GenaError::PreconditionFailed));
+
+		let mut state = self.shared.state.lock().unwrap();
+		let before = state.subscriptions.len();
+		state.subscriptions.retain(|s| &s.sid != sid || s.event_sub_url != req.url);
+		if state.subscriptions.len() == before {
+			return Err(GenaError::PreconditionFailed);
+		}
+		Ok(())
+	}
+
+	/// Drop the subscriptions not renewed by `now`. Expired ones never get
+	/// events, this only frees them.
+	pub fn expire(&self, now: Instant) {
+		self.shared.state.lock().unwrap().remove_expired(now);
+	}
+
+	/// Live subscriptions to the service.
+	pub fn subscriber_count(&self, service: &UpnpService) -> usize {
+		let now = Instant::now();
+		self.shared.state.lock().unwrap().subscriptions.iter()
+			.filter(|s| s.event_sub_url == service.event_sub_url && s.expires > now)
+			.count()
+	}
+
+	/// A random, unique subscription id.
+	fn new_sid(&self, counter: u64) -> String {
+		let mut h = self.random.build_hasher();
+		h.write_u64(counter);
+		let a = h.finish();
+		let mut h = self.random.build_hasher();
+		h.write_u64(!counter);
+		let b = h.finish();
+
+		format!("uuid:{:08x}-{:04x}-{:04x}-{:04x}-{:012x}", a >> 32, (a >> 16) & 0xffff, a & 0xffff, b >> 48, b & 0xffff_ffff_ffff)
+	}
+}
+
+impl GenaState {
+	fn remove_expired(&mut self, now: Instant) {
+		self.subscriptions.retain(|s| s.expires > now);
+	}
+}
+
+impl GenaShared {
+	/// Try the callbacks in order until one takes the event.
+	fn deliver(&self, sid: &str, callbacks: &[Url], seq: u32, body: &str) -> bool {
+		for url in callbacks {
+			let mut req = self.client.request(HttpMethod::Notify, url);
+			req.headers.insert("Content-Type".to_string(), UPNP_XML_CONTENT_TYPE.to_string());
+			req.headers.insert("NT".to_string(), "upnp:event".to_string());
+			req.headers.insert("NTS".to_string(), "upnp:propchange".to_string());
+			req.headers.insert("SID".to_string(), sid.to_string());
+			req.headers.insert("SEQ".to_string(), seq.to_string());
+			req.body.extend_from_slice(body.as_bytes());
+
+			match self.client.send(url, req) {
+				Ok(ref resp) if resp.response_code >= 200 && resp.response_code < 300 => { return true; }
+				_ => ()
+			}
+		}
+		false
+	}
+}
+
+/// SEQ runs from 0 up, and wraps to 1.
+fn next_seq(sub: &mut GenaSubscription) -> u32 {
+	let seq = sub.seq;
+	sub.seq = if seq == u32::MAX { 1 } else { seq + 1 };
+	seq
+}
+
+/// `TIMEOUT: Second-1800` or `Second-infinite`, capped at `max`.
+fn parse_timeout(header: Option<&String>, max: u32) -> u32 {
+	let requested = header.and_then(|h| {
+		let h = h.trim();
+		match h.get(..7) {
+			Some(prefix) if prefix.eq_ignore_ascii_case("Second-") => h[7..].parse::<u32>().ok(),
+			_ => None
+		}
+	});
+
+	match requested {
+		Some(t) if t < max => t,
+		_ => max
+	}
+}
+
+/// `CALLBACK: <http://host/path><http://other/path>`. Only http URLs are
+/// accepted.
+fn parse_callbacks(header: &str) -> Option<Vec<Url>> {
+	let mut callbacks = Vec::new();
+	let mut rest = header.trim();
+	while rest.len() > 0 {
+		if !rest.starts_with('<') {
+			return None;
+		}
+		let url = match rest.find('>').and_then(|end| Url::parse(&rest[1..end]).ok()) {
+			Some(ref url) if url.scheme == "http" => url.clone(),
+			_ => { return None; }
+		};
+		let end = rest.find('>').unwrap();
+		callbacks.push(url);
+		rest = rest[end + 1..].trim();
+	}
+
+	if callbacks.len() > 0 { Some(callbacks) } else { None }
+}
+
+/// The body of an event.
+pub fn property_set_xml(variables: &[(String, String)]) -> String {
+	let mut w = XmlWriter::new();
+	w.start_with_attributes("e:propertyset", &[("xmlns:e", "urn:schemas-upnp-org:event-1-0")]);
+	for (name, value) in variables {
+		w.start("e:property");
+		w.element(name, value);
+		w.end("e:property");
+	}
+	w.end("e:propertyset");
+	w.into_string()
+}
+
+/// The variables in the body of an event, for control points.
+pub fn parse_property_set(body: &[u8]) -> Result<Vec<(String, String)>, XmlError> {
+	let root = try!(parse_xml(body));
+	if root.local_name() != "propertyset" {
+		return Err(XmlError::Malformed);
+	}
+
+	let mut variables = Vec::new();
+	for property in root.children.iter().filter(|c| c.local_name() == "property") {
+		for var in &property.children {
+			variables.push((var.name.clone(), var.text.clone()));
+		}
+	}
+	Ok(variables)
+}
+
+/// Takes SUBSCRIBE and UNSUBSCRIBE requests to the event URLs of the
+/// publisher's services. New subscribers get their initial event once
+/// they've been answered, so the route has to be served through
+/// `HttpConnection::poll_routed`, as the servers do.
+pub struct GenaRoute {
+	publisher: Arc<GenaPublisher>,
+}
+
+impl GenaRoute {
+	pub fn new(publisher: Arc<GenaPublisher>) -> GenaRoute {
+		GenaRoute {
+			publisher: publisher
+		}
+	}
+}
+
+impl HttpRoute for GenaRoute {
+	fn try(&self, msg: &HttpRequestMessage) -> Result<bool, HttpRouteError> {
+		if msg.method != HttpMethod::Subscribe && msg.method != HttpMethod::Unsubscribe {
+			return Ok(false);
+		}
+		let state = self.publisher.shared.state.lock().unwrap();
+		Ok(state.services.iter().any(|s| s.event_sub_url == msg.url))
+	}
+
+	fn execute(&self, msg: &HttpRequestMessage) -> Result<HttpResponseMessage, HttpRouteError> {
+		let mut resp = HttpResponseMessage::empty();
+		resp.response_code = 200;
+		resp.response_status = "OK".to_string();
+
+		if msg.method == HttpMethod::Unsubscribe {
+			return Ok(match self.publisher.unsubscribe(msg) {
+				Ok(()) => resp,
+				Err(e) => e.to_response()
+			});
+		}
+
+		match self.publisher.subscribe(msg) {
+			Ok((sid, timeout)) => {
+				resp.headers.insert("SID".to_string(), sid);
+				resp.headers.insert("TIMEOUT".to_string(), format!("Second-{}", timeout));
+				Ok(resp)
+			}
+			Err(e) => Ok(e.to_response())
+		}
+	}
+
+	/// A new subscriber knows its SID now, so events can follow.
+	fn responded(&self, msg: &HttpRequestMessage, resp: &HttpResponseMessage) {
+		if msg.method != HttpMethod::Subscribe || msg.get_raw_header("SID").is_some() || resp.response_code != 200 {
+			return;
+		}
+		if let Some(sid) = resp.get_raw_header("SID") {
+			self.publisher.queue_initial_event(sid);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use server::*;
+	use test_support::*;
+
+	use std::sync::{Arc, Mutex};
+	use std::sync::mpsc::{channel, Receiver};
+	use std::thread;
+	use std::time::{Duration, Instant};
+	use alloc::boxed::Box;
+
+	struct Event {
+		sid: String,
+		seq: u32,
+		variables: Vec<(String, String)>,
+	}
+
+	/// A control point's callback server, handing over the events it gets.
+	fn subscriber() -> (u16, HttpServerHandle, thread::JoinHandle<()>, Receiver<Event>) {
+		let (tx, rx) = channel();
+		let tx = Mutex::new(tx);
+		let route = HttpRouteStaticUrl {
+			urls: vec!["/events".to_string()],
+			methods: vec![HttpMethod::Notify],
+			action: Box::new(move |req| {
+				assert_eq!(req.get_raw_header("NT").unwrap(), "upnp:event");
+				assert_eq!(req.get_raw_header("NTS").unwrap(), "upnp:propchange");
+				tx.lock().unwrap().send(Event {
+					sid: req.get_raw_header("SID").unwrap().clone(),
+					seq: req.get_raw_header("SEQ").unwrap().parse().unwrap(),
+					variables: parse_property_set(&req.body).unwrap()
+				}).unwrap();
+				HttpResponseMessage::new_status(200, "OK")
+			})
+		};
+
+		let server = HttpServerBuilder::new().bind("127.0.0.1:0").route(route).build().unwrap();
+		let port = server.local_addr().port();
+		let handle = server.handle();
+		let t = thread::spawn(move || server.run().unwrap());
+		(port, handle, t, rx)
+	}
+
+	fn switch_power() -> UpnpService {
+		let scpd = UpnpScpd::new()
+			.state_variable(UpnpStateVariable::new("Target", "boolean").default_value("0"))
+			.state_variable(UpnpStateVariable::new("Status", "boolean").default_value("0").evented());
+		UpnpService::new("urn:schemas-upnp-org:service:SwitchPower:1", "urn:upnp-org:serviceId:SwitchPower").scpd(scpd)
+	}
+
+	#[test]
+	pub fn test_gena_subscriptions() {
+		let (port, handle, t, events) = subscriber();
+		let service = switch_power();
+		let publisher = Arc::new(GenaPublisher::new());
+		publisher.add_service(&service);
+		let mut router = HttpRouter::new();
+		router.add_route(GenaRoute::new(publisher.clone()));
+
+		let callback = format!("<http://127.0.0.1:1/unreachable><http://127.0.0.1:{}/events>", port);
+		let resp = TestClient::request(HttpMethod::Subscribe, "/upnp/SwitchPower/event")
+			.header("CALLBACK", &callback)
+			.header("NT", "upnp:event")
+			.header("TIMEOUT", "Second-infinite")
+			.send(&router);
+		assert_eq!(resp.response_code, 200);
+		assert_eq!(resp.get_raw_header("TIMEOUT").unwrap(), "Second-1800");
+		let sid = resp.get_raw_header("SID").unwrap().clone();
+		assert!(sid.starts_with("uuid:") && sid.len() == 41);
+
+		// the initial event has every evented variable
+		let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+		assert_eq!((event.sid.as_str(), event.seq), (sid.as_str(), 0));
+		assert_eq!(event.variables, [("Status".to_string(), "0".to_string())]);
+
+		assert_eq!(publisher.set_variable(&service, "Status", "1"), 1);
+		let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+		assert_eq!(event.seq, 1);
+		assert_eq!(event.variables, [("Status".to_string(), "1".to_string())]);
+		assert_eq!(publisher.set_variable(&service, "Status", "1"), 0);
+
+		// renewal
+		let resp = TestClient::request(HttpMethod::Subscribe, "/upnp/SwitchPower/event")
+			.header("SID", &sid)
+			.header("TIMEOUT", "Second-60")
+			.send(&router);
+		assert_eq!(resp.response_code, 200);
+		assert_eq!(resp.get_raw_header("SID").unwrap(), &sid);
+		assert_eq!(resp.get_raw_header("TIMEOUT").unwrap(), "Second-60");
+
+		let rejected = vec![
+			TestClient::request(HttpMethod::Subscribe, "/upnp/SwitchPower/event").header("SID", &sid).header("NT", "upnp:event"),
+			TestClient::request(HttpMethod::Subscribe, "/upnp/SwitchPower/event").header("SID", "uuid:unknown"),
+			TestClient::request(HttpMethod::Subscribe, "/upnp/SwitchPower/event").header("NT", "upnp:event"),
+			TestClient::request(HttpMethod::Subscribe, "/upnp/SwitchPower/event").header("NT", "upnp:event").header("CALLBACK", "http://no-brackets/"),
+			TestClient::request(HttpMethod::Subscribe, "/upnp/SwitchPower/event").header("NT", "upnp:other").header("CALLBACK", &callback),
+			TestClient::request(HttpMethod::Unsubscribe, "/upnp/SwitchPower/event").header("SID", &sid).header("CALLBACK", &callback)
+		];
+		let codes: Vec<u16> = rejected.into_iter().map(|r| r.send(&router).response_code).collect();
+		assert_eq!(codes, [400, 412, 412, 412, 412, 400]);
+		assert_eq!(TestClient::request(HttpMethod::Subscribe, "/elsewhere").send(&router).response_code, 404);
+
+		// the renewal counts from now, SEQ goes on
+		publisher.expire(Instant::now() + Duration::from_secs(30));
+		assert_eq!(publisher.subscriber_count(&service), 1);
+		assert_eq!(publisher.set_variables(&service, &[("Status", "0"), ("Target", "1")]), 1);
+		let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+		assert_eq!(event.seq, 2);
+		assert_eq!(event.variables, [("Status".to_string(), "0".to_string())]);
+
+		let unsubscribe = || TestClient::request(HttpMethod::Unsubscribe, "/upnp/SwitchPower/event").header("SID", &sid).send(&router);
+		assert_eq!(unsubscribe().response_code, 200);
+		assert_eq!(unsubscribe().response_code, 412);
+		assert_eq!(publisher.set_variable(&service, "Status", "1"), 0);
+
+		// expiry; an initial event has the current values
+		let resp = TestClient::request(HttpMethod::Subscribe, "/upnp/SwitchPower/event")
+			.header("CALLBACK", &callback)
+			.header("NT", "upnp:event")
+			.send(&router);
+		assert_eq!(resp.get_raw_header("TIMEOUT").unwrap(), "Second-1800");
+		let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+		assert_eq!((event.seq, event.variables), (0, vec![("Status".to_string(), "1".to_string())]));
+		publisher.expire(Instant::now() + Duration::from_secs(1801));
+		assert_eq!(publisher.subscriber_count(&service), 0);
+
+		handle.shutdown();
+		t.join().unwrap();
+		assert!(events.try_recv().is_err());
+	}
+	#[test]
+	pub fn test_gena_limits() {
+		let (port, handle, t, events) = subscriber();
+		let service = switch_power();
+		let config = GenaConfig { max_subscriptions: 2, max_callbacks: 1, .. GenaConfig::default() };
+		let publisher = Arc::new(GenaPublisher::with_config(config));
+		publisher.add_service(&service);
+		let mut router = HttpRouter::new();
+		router.add_route(GenaRoute::new(publisher.clone()));
+		let subscribe = |callback: &str| TestClient::request(HttpMethod::Subscribe, "/upnp/SwitchPower/event")
+			.header("CALLBACK", callback)
+			.header("NT", "upnp:event")
+			.send(&router);
+
+		// only the first callback is kept, and once it fails the
+		// subscription is gone
+		let resp = subscribe(&format!("<http://127.0.0.1:1/unreachable><http://127.0.0.1:{}/events>", port));
+		assert_eq!(resp.response_code, 200);
+		let start = Instant::now();
+		while publisher.subscriber_count(&service) > 0 {
+			assert!(start.elapsed() < Duration::from_secs(5));
+			thread::sleep(Duration::from_millis(10));
+		}
+
+		let callback = format!("<http://127.0.0.1:{}/events>", port);
+		let sids: Vec<String> = (0..2).map(|_| subscribe(&callback).get_raw_header("SID").unwrap().clone()).collect();
+		assert_eq!(subscribe(&callback).response_code, 503);
+		for _ in 0..2 {
+			events.recv_timeout(Duration::from_secs(5)).unwrap();
+		}
+
+		// each subscriber gets its events in order
+		for value in &["1", "0", "1"] {
+			assert_eq!(publisher.set_variable(&service, "Status", value), 2);
+		}
+		let mut seqs = vec![Vec::new(), Vec::new()];
+		for _ in 0..6 {
+			let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+			seqs[sids.iter().position(|s| *s == event.sid).unwrap()].push(event.seq);
+		}
+		assert_eq!(seqs, [[1, 2, 3], [1, 2, 3]]);
+
+		handle.shutdown();
+		t.join().unwrap();
+	}
+}
@@ -0,0 +1,13 @@
+//! UPnP device architecture on top of the HTTP and SSDP parts.
+
+mod xml;
+mod description;
+mod soap;
+#[cfg(any(test, feature = "std"))]
+mod gena;
+
+pub use self::xml::{XmlElement, XmlError, parse_xml};
+pub use self::description::*;
+pub use self::soap::*;
+#[cfg(any(test, feature = "std"))]
+pub use self::gena::*;
@@ -0,0 +1,466 @@
+#![allow(deprecated, bare_trait_objects, clippy::redundant_field_names, clippy::new_without_default, clippy::len_zero)]
+
+use core::fmt::Display;
+use core::str::FromStr;
+use http::*;
+use router::*;
+use super::description::*;
+use super::xml::*;
+use alloc::vec::Vec;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::boxed::Box;
+
+const SOAP_ENVELOPE_NS: &str = "http://schemas.xmlsoap.org/soap/envelope/";
+const SOAP_ENCODING: &str = "http://schemas.xmlsoap.org/soap/encoding/";
+
+/// Arguments of an action, in or out, in order.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UpnpArguments {
+	args: Vec<(String, String)>,
+}
+
+impl UpnpArguments {
+	pub fn new() -> UpnpArguments {
+		UpnpArguments {
+			args: Vec::new()
+		}
+	}
+
+	pub fn with<V: Display>(mut self, name: &str, value: V) -> UpnpArguments {
+		self.set(name, value);
+		self
+	}
+
+	/// UPnP booleans are written as `1` and `0`.
+	pub fn with_bool(self, name: &str, value: bool) -> UpnpArguments {
+		self.with(name, if value { "1" } else { "0" })
+	}
+
+	pub fn set<V: Display>(&mut self, name: &str, value: V) {
+		let value = value.to_string();
+		if let Some(arg) = self.args.iter_mut().find(|a| a.0 == name) {
+			arg.1 = value;
+			return;
+		}
+		self.args.push((name.to_string(), value));
+	}
+
+	pub fn get(&self, name: &str) -> Option<&str> {
+		self.args.iter().find(|a| a.0 == name).map(|a| a.1.as_str())
+	}
+
+	/// The argument, or the Invalid Args fault if it's missing.
+	pub fn get_str(&self, name: &str) -> Result<&str, UpnpFault> {
+		self.get(name).ok_or(UpnpFault::invalid_args())
+	}
+
+	/// The argument as a number or another `FromStr` type. Faults with
+	/// Argument Value Invalid if it doesn't parse.
+	pub fn parse<T: FromStr>(&self, name: &str) -> Result<T, UpnpFault> {
+		let value = try!(self.get_str(name));
+		value.trim().parse().map_err(|_| UpnpFault::argument_value_invalid())
+	}
+
+	/// A UPnP boolean: `1`, `true` or `yes`, `0`, `false` or `no`.
+	pub fn get_bool(&self, name: &str) -> Result<bool, UpnpFault> {
+		let value = try!(self.get_str(name));
+		parse_upnp_bool(value).ok_or(UpnpFault::argument_value_invalid())
+	}
+
+	pub fn iter<'a>(&'a self) -> ::core::slice::Iter<'a, (String, String)> {
+		self.args.iter()
+	}
+
+	pub fn len(&self) -> usize {
+		self.args.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.args.is_empty()
+	}
+}
+
+fn parse_upnp_bool(value: &str) -> Option<bool> {
+	match value.trim() {
+		"1" | "true" | "yes" => Some(true),
+		"0" | "false" | "no" => Some(false),
+		_ => None
+	}
+}
+
+/// Error answer to an action, sent as a SOAP fault with a UPnPError
+/// detail.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UpnpFault {
+	pub code: u16,
+	pub description: String,
+}
+
+impl UpnpFault {
+	pub fn new(code: u16, description: &str) -> UpnpFault {
+		UpnpFault {
+			code: code,
+			description: description.to_string()
+		}
+	}
+
+	/// No such action in the service.
+	pub fn invalid_action() -> UpnpFault {
+		UpnpFault::new(401, "Invalid Action")
+	}
+
+	/// Missing, extra or misordered arguments.
+	pub fn invalid_args() -> UpnpFault {
+		UpnpFault::new(402, "Invalid Args")
+	}
+
+	pub fn action_failed() -> UpnpFault {
+		UpnpFault::new(501, "Action Failed")
+	}
+
+	/// Wrong type, or not one of the allowed values.
+	pub fn argument_value_invalid() -> UpnpFault {
+		UpnpFault::new(600, "Argument Value Invalid")
+	}
+
+	pub fn argument_value_out_of_range() -> UpnpFault {
+		UpnpFault::new(601, "Argument Value Out of Range")
+	}
+
+	pub fn to_response(&self) -> HttpResponseMessage {
+		let mut w = XmlWriter::new();
+		start_envelope(&mut w);
+		w.start("s:Fault");
+		w.element("faultcode", "s:Client");
+		w.element("faultstring", "UPnPError");
+		w.start("detail");
+		w.start_root("UPnPError", "urn:schemas-upnp-org:control-1-0");
+		w.element("errorCode", &self.code.to_string());
+		w.element("errorDescription", &self.description);
+		w.end("UPnPError");
+		w.end("detail");
+		w.end("s:Fault");
+		end_envelope(&mut w);
+
+		let mut resp = soap_response(w.into_string());
+		resp.response_code = 500;
+		resp.response_status = "Internal Server Error".to_string();
+		resp
+	}
+}
+
+fn start_envelope(w: &mut XmlWriter) {
+	w.start_with_attributes("s:Envelope", &[("xmlns:s", SOAP_ENVELOPE_NS), ("s:encodingStyle", SOAP_ENCODING)]);
+	w.start("s:Body");
+}
+
+fn end_envelope(w: &mut XmlWriter) {
+	w.end("s:Body");
+	w.end("s:Envelope");
+}
+
+fn soap_response(body: String) -> HttpResponseMessage {
+	let mut resp = HttpResponseMessage::text_utf8(&body);
+	resp.headers.insert("Content-Type".to_string(), UPNP_XML_CONTENT_TYPE.to_string());
+	resp.headers.insert("EXT".to_string(), String::new());
+	resp
+}
+
+/// The response to a successful action.
+pub fn soap_action_response(service_type: &str, action: &str, out: &UpnpArguments) -> HttpResponseMessage {
+	let mut w = XmlWriter::new();
+	start_envelope(&mut w);
+	w.start_with_attributes(&format!("u:{}Response", action), &[("xmlns:u", service_type)]);
+	for (name, value) in out.iter() {
+		w.element(name, value);
+	}
+	w.end(&format!("u:{}Response", action));
+	end_envelope(&mut w);
+	soap_response(w.into_string())
+}
+
+/// Service type and action name of a `SOAPACTION` header,
+/// `"urn:schemas-upnp-org:service:SwitchPower:1#SetTarget"`.
+pub fn parse_soap_action(header: &str) -> Option<(&str, &str)> {
+	let header = header.trim();
+	let header = if header.len() >= 2 && header.starts_with('"') && header.ends_with('"') {
+		&header[1..header.len() - 1]
+	} else {
+		header
+	};
+
+	match header.rfind('#') {
+		Some(i) if i > 0 && i + 1 < header.len() => Some((&header[..i], &header[i + 1..])),
+		_ => None
+	}
+}
+
+type UpnpActionHandler = Box<Fn(&UpnpArguments) -> Result<UpnpArguments, UpnpFault> + Send + Sync>;
+
+struct ControlledService {
+	control_url: String,
+	service_type: String,
+	scpd: UpnpScpd,
+	handlers: Vec<(String, UpnpActionHandler)>,
+}
+
+/// Invokes actions: takes the SOAP requests POSTed to the control URLs of
+/// services, and calls the handler registered for the service type and
+/// action named by `SOAPACTION`. If the service's SCPD describes the
+/// action, the in arguments are checked against it before the handler
+/// runs and the out arguments are answered in its order.
+pub struct UpnpControlRoute {
+	services: Vec<ControlledService>,
+}
+
+impl UpnpControlRoute {
+	pub fn new() -> UpnpControlRoute {
+		UpnpControlRoute {
+			services: Vec::new()
+		}
+	}
+
+	pub fn action<F>(mut self, service: &UpnpService, action: &str, handler: F) -> UpnpControlRoute
+		where F: Fn(&UpnpArguments) -> Result<UpnpArguments, UpnpFault> + Send + Sync + 'static
+	{
+		let i = match self.services.iter().position(|s| s.control_url == service.control_url && s.service_type == service.service_type) {
+			Some(i) => i,
+			None => {
+				self.services.push(ControlledService {
+					control_url: service.control_url.clone(),
+					service_type: service.service_type.clone(),
+					scpd: service.scpd.clone(),
+					handlers: Vec::new()
+				});
+				self.services.len() - 1
+			}
+		};
+
+		self.services[i].handlers.push((action.to_string(), Box::new(handler)));
+		self
+	}
+
+	fn invoke(&self, msg: &HttpRequestMessage) -> Result<HttpResponseMessage, UpnpFault> {
+		let (service_type, action) = try!(msg.get_raw_header("SOAPACTION")
+			.and_then(|h| parse_soap_action(h))
+			.ok_or(UpnpFault::invalid_action()));
+
+		let service = try!(self.services.iter()
+			.find(|s| s.control_url == msg.url && s.service_type == service_type)
+			.ok_or(UpnpFault::invalid_action()));
+		let handler = try!(service.handlers.iter()
+			.find(|h| h.0 == action)
+			.ok_or(UpnpFault::invalid_action()));
+
+		let envelope = try!(parse_xml(&msg.body).map_err(|_| UpnpFault::invalid_args()));
+		let call = try!(envelope.child("Body")
+			.and_then(|b| b.children.first())
+			.ok_or(UpnpFault::invalid_args()));
+		if envelope.local_name() != "Envelope" || call.local_name() != action {
+			return Err(UpnpFault::invalid_action());
+		}
+
+		let mut args = UpnpArguments::new();
+		for arg in &call.children {
+			args.set(arg.local_name(), &arg.text);
+		}
+
+		let description = service.scpd.find_action(action);
+		if let Some(description) = description {
+			try!(check_in_arguments(&service.scpd, description, &args));
+		}
+
+		let out = try!((handler.1)(&args));
+		let out = match description {
+			Some(description) => try!(order_out_arguments(description, out)),
+			None => out
+		};
+
+		Ok(soap_action_response(service_type, action, &out))
+	}
+}
+
+/// The in arguments must be exactly the described ones, of the right type.
+fn check_in_arguments(scpd: &UpnpScpd, action: &UpnpAction, args: &UpnpArguments) -> Result<(), UpnpFault> {
+	let described: Vec<&UpnpArgument> = action.arguments.iter().filter(|a| a.direction == UpnpDirection::In).collect();
+	if described.len() != args.len() {
+		return Err(UpnpFault::invalid_args());
+	}
+
+	for arg in described {
+		let value = try!(args.get_str(&arg.name));
+		if let Some(var) = scpd.find_state_variable(&arg.related_state_variable) {
+			if !is_valid_value(var, value) {
+				return Err(UpnpFault::argument_value_invalid());
+			}
+		}
+	}
+	Ok(())
+}
+
+fn is_valid_value(var: &UpnpStateVariable, value: &str) -> bool {
+	let value = value.trim();
+	let type_ok = match var.data_type.as_str() {
+		"boolean" => parse_upnp_bool(value).is_some(),
+		"ui1" => value.parse::<u8>().is_ok(),
+		"ui2" => value.parse::<u16>().is_ok(),
+		"ui4" => value.parse::<u32>().is_ok(),
+		"ui8" => value.parse::<u64>().is_ok(),
+		"i1" => value.parse::<i8>().is_ok(),
+		"i2" => value.parse::<i16>().is_ok(),
+		"i4" | "int" => value.parse::<i32>().is_ok(),
+		"i8" => value.parse::<i64>().is_ok(),
+		_ => true
+	};
+
+	type_ok && (var.allowed_values.len() == 0 || var.allowed_values.iter().any(|v| v == value))
+}
+
+/// Out arguments in the described order. A handler leaving one out is a
+/// bug on our side.
+fn order_out_arguments(action: &UpnpAction, out: UpnpArguments) -> Result<UpnpArguments, UpnpFault> {
+	let mut ordered = UpnpArguments::new();
+	for arg in action.arguments.iter().filter(|a| a.direction == UpnpDirection::Out) {
+		match out.get(&arg.name) {
+			Some(value) => ordered.set(&arg.name, value),
+			None => { return Err(UpnpFault::action_failed()); }
+		}
+	}
+	Ok(ordered)
+}
+
+impl HttpRoute for UpnpControlRoute {
+	fn try(&self, msg: &HttpRequestMessage) -> Result<bool, HttpRouteError> {
+		Ok(msg.method == HttpMethod::Post && self.services.iter().any(|s| s.control_url == msg.url))
+	}
+
+	fn execute(&self, msg: &HttpRequestMessage) -> Result<HttpResponseMessage, HttpRouteError> {
+		match self.invoke(msg) {
+			Ok(resp) => Ok(resp),
+			Err(fault) => Ok(fault.to_response())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use test_support::*;
+
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicBool, Ordering};
+
+	const SWITCH_POWER: &str = "urn:schemas-upnp-org:service:SwitchPower:1";
+
+	fn switch_power() -> UpnpService {
+		let scpd = UpnpScpd::new()
+			.action(UpnpAction::new("SetTarget").argument_in("newTargetValue", "Target"))
+			.action(UpnpAction::new("GetStatus").argument_out("ResultStatus", "Status").argument_out("Since", "Since"))
+			.state_variable(UpnpStateVariable::new("Target", "boolean"))
+			.state_variable(UpnpStateVariable::new("Status", "boolean").evented())
+			.state_variable(UpnpStateVariable::new("Since", "ui4"));
+		UpnpService::new(SWITCH_POWER, "urn:upnp-org:serviceId:SwitchPower").scpd(scpd)
+	}
+
+	fn router() -> HttpRouter {
+		let service = switch_power();
+		let on = Arc::new(AtomicBool::new(false));
+		let on_get = on.clone();
+
+		let mut router = HttpRouter::new();
+		router.add_route(UpnpControlRoute::new()
+			.action(&service, "SetTarget", move |args| {
+				on.store(try!(args.get_bool("newTargetValue")), Ordering::SeqCst);
+				Ok(UpnpArguments::new())
+			})
+			.action(&service, "GetStatus", move |_| {
+				Ok(UpnpArguments::new().with("Since", 10).with_bool("ResultStatus", on_get.load(Ordering::SeqCst)))
+			})
+			.action(&service, "Undescribed", |args| {
+				let n: u32 = try!(args.parse("n"));
+				if n > 100 { return Err(UpnpFault::argument_value_out_of_range()); }
+				Ok(UpnpArguments::new().with("double", n * 2))
+			}));
+		router
+	}
+
+	fn call(router: &HttpRouter, action: &str, args: &str) -> (u16, XmlElement) {
+		let body = format!("<?xml version=\"1.0\"?>\
+			<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+			<s:Body><u:{0} xmlns:u=\"{1}\">{2}</u:{0}></s:Body></s:Envelope>", action, SWITCH_POWER, args);
+		let resp = TestClient::post("/upnp/SwitchPower/control")
+			.header("SOAPACTION", &format!("\"{}#{}\"", SWITCH_POWER, action))
+			.header("Content-Type", "text/xml; charset=\"utf-8\"")
+			.body(body.as_bytes())
+			.send(router);
+		assert_eq!(resp.get_raw_header("Content-Type").unwrap(), UPNP_XML_CONTENT_TYPE);
+		(resp.response_code, parse_xml(&resp.body).unwrap())
+	}
+
+	fn fault_code(envelope: &XmlElement) -> String {
+		let fault = envelope.child("Body").unwrap().child("Fault").unwrap();
+		assert_eq!(fault.child("faultstring").unwrap().text, "UPnPError");
+		fault.child("detail").unwrap().child("UPnPError").unwrap().child("errorCode").unwrap().text.clone()
+	}
+
+	#[test]
+	pub fn test_soap_actions() {
+		let router = router();
+
+		let (code, envelope) = call(&router, "SetTarget", "<newTargetValue>1</newTargetValue>");
+		assert_eq!(code, 200);
+		let resp = envelope.child("Body").unwrap().child("SetTargetResponse").unwrap();
+		assert_eq!(resp.attribute("xmlns:u"), Some(SWITCH_POWER));
+		assert_eq!(resp.children.len(), 0);
+
+		// out arguments in the SCPD order
+		let (code, envelope) = call(&router, "GetStatus", "");
+		assert_eq!(code, 200);
+		let resp = envelope.child("Body").unwrap().child("GetStatusResponse").unwrap();
+		let out: Vec<(&str, &str)> = resp.children.iter().map(|c| (c.name.as_str(), c.text.as_str())).collect();
+		assert_eq!(out, [("ResultStatus", "1"), ("Since", "10")]);
+
+		let (_, envelope) = call(&router, "Undescribed", "<n>21</n>");
+		assert_eq!(envelope.child("Body").unwrap().child("UndescribedResponse").unwrap().child("double").unwrap().text, "42");
+	}
+
+	#[test]
+	pub fn test_soap_faults() {
+		let router = router();
+
+		let faults = [
+			("SetTarget", "", "402"),
+			("SetTarget", "<newTargetValue>1</newTargetValue><extra>1</extra>", "402"),
+			("SetTarget", "<newTargetValue>maybe</newTargetValue>", "600"),
+			("Undescribed", "<n>x</n>", "600"),
+			("Undescribed", "<n>101</n>", "601"),
+			("Missing", "", "401")
+		];
+		for &(action, args, code) in faults.iter() {
+			let (status, envelope) = call(&router, action, args);
+			assert_eq!(status, 500);
+			assert_eq!(fault_code(&envelope), code, "{} {}", action, args);
+		}
+
+		// SOAPACTION and body must agree
+		let resp = TestClient::post("/upnp/SwitchPower/control")
+			.header("SOAPACTION", &format!("\"{}#GetStatus\"", SWITCH_POWER))
+			.body(b"<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body><u:SetTarget/></s:Body></s:Envelope>")
+			.send(&router);
+		assert_eq!(fault_code(&parse_xml(&resp.body).unwrap()), "401");
+
+		let resp = TestClient::post("/upnp/SwitchPower/control").body(b"<a/>").send(&router);
+		assert_eq!(fault_code(&parse_xml(&resp.body).unwrap()), "401");
+
+		let resp = TestClient::post("/upnp/SwitchPower/control")
+			.header("SOAPACTION", &format!("{}#GetStatus", SWITCH_POWER))
+			.body(b"<s:Envelope")
+			.send(&router);
+		assert_eq!(fault_code(&parse_xml(&resp.body).unwrap()), "402");
+
+		assert_eq!(TestClient::get("/upnp/SwitchPower/control").send(&router).response_code, 404);
+		assert_eq!(parse_soap_action("\"urn:x#A\""), Some(("urn:x", "A")));
+		assert_eq!(parse_soap_action("urn:x#"), None);
+	}
+}
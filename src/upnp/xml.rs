@@ -0,0 +1,358 @@
+#![allow(deprecated, clippy::len_zero)]
+
+use core::str::from_utf8;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::string::ToString;
+
+/// Escape text for element content and attribute values.
+pub fn xml_escape(s: &str) -> String {
+	let mut out = String::with_capacity(s.len());
+	for c in s.chars() {
+		match c {
+			'&' => out.push_str("&amp;"),
+			'<' => out.push_str("&lt;"),
+			'>' => out.push_str("&gt;"),
+			'"' => out.push_str("&quot;"),
+			'\'' => out.push_str("&apos;"),
+			c => out.push(c)
+		}
+	}
+	out
+}
+
+/// Writes the small, attribute-less documents UPnP uses, indented with
+/// two spaces.
+pub struct XmlWriter {
+	out: String,
+	depth: usize,
+}
+
+impl XmlWriter {
+	pub fn new() -> XmlWriter {
+		XmlWriter {
+			out: String::from("<?xml version=\"1.0\"?>\n"),
+			depth: 0
+		}
+	}
+
+	/// Open the root element, declaring its default namespace.
+	pub fn start_root(&mut self, name: &str, xmlns: &str) {
+		self.start_with_attributes(name, &[("xmlns", xmlns)]);
+	}
+
+	pub fn start(&mut self, name: &str) {
+		self.indent();
+		self.out.push_str(&format!("<{}>\n", name));
+		self.depth += 1;
+	}
+
+	pub fn start_with_attributes(&mut self, name: &str, attributes: &[(&str, &str)]) {
+		self.indent();
+		self.out.push('<');
+		self.out.push_str(name);
+		for &(attribute, value) in attributes {
+			self.out.push_str(&format!(" {}=\"{}\"", attribute, xml_escape(value)));
+		}
+		self.out.push_str(">\n");
+		self.depth += 1;
+	}
+
+	pub fn end(&mut self, name: &str) {
+		self.depth -= 1;
+		self.indent();
+		self.out.push_str(&format!("</{}>\n", name));
+	}
+
+	/// An element with only text in it.
+	pub fn element(&mut self, name: &str, text: &str) {
+		self.indent();
+		self.out.push_str(&format!("<{}>{}</{}>\n", name, xml_escape(text), name));
+	}
+
+	pub fn optional_element(&mut self, name: &str, text: &Option<String>) {
+		if let Some(ref text) = *text {
+			self.element(name, text);
+		}
+	}
+
+	pub fn into_string(self) -> String {
+		self.out
+	}
+
+	fn indent(&mut self) {
+		for _ in 0..self.depth {
+			self.out.push_str("  ");
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum XmlError {
+	UnexpectedEnd,
+	Malformed,
+	/// A closing tag that doesn't match the open element.
+	MismatchedTag,
+	InvalidEntity,
+	/// DOCTYPEs aren't supported, nor needed by UPnP.
+	Doctype,
+	/// Nested deeper than `MAX_XML_DEPTH`.
+	TooDeep,
+}
+
+/// Deepest element nesting `parse_xml` accepts.
+pub const MAX_XML_DEPTH: usize = 32;
+
+/// An element and everything in it. Names keep their namespace prefix.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct XmlElement {
+	pub name: String,
+	pub attributes: Vec<(String, String)>,
+	pub children: Vec<XmlElement>,
+	/// The text directly in this element, with entities and CDATA sections
+	/// resolved.
+	pub text: String,
+}
+
+impl XmlElement {
+	/// The name without its namespace prefix.
+	pub fn local_name(&self) -> &str {
+		match self.name.find(':') {
+			Some(i) => &self.name[i + 1..],
+			None => &self.name
+		}
+	}
+
+	pub fn attribute(&self, name: &str) -> Option<&str> {
+		self.attributes.iter().find(|a| a.0 == name).map(|a| a.1.as_str())
+	}
+
+	/// The first child with this local name.
+	pub fn child(&self, local_name: &str) -> Option<&XmlElement> {
+		self.children.iter().find(|c| c.local_name() == local_name)
+	}
+}
+
+/// Parse a document into its root element. Comments and processing
+/// instructions are skipped.
+pub fn parse_xml(input: &[u8]) -> Result<XmlElement, XmlError> {
+	let input = try!(from_utf8(input).map_err(|_| XmlError::Malformed));
+	let mut reader = XmlReader { s: input, pos: 0 };
+
+	try!(reader.skip_misc());
+	if !reader.rest().starts_with("<") {
+		return Err(if reader.rest().len() == 0 { XmlError::UnexpectedEnd } else { XmlError::Malformed });
+	}
+	let root = try!(reader.element(0));
+	try!(reader.skip_misc());
+	if reader.rest().len() > 0 {
+		return Err(XmlError::Malformed);
+	}
+	Ok(root)
+}
+
+struct XmlReader<'a> {
+	s: &'a str,
+	pos: usize,
+}
+
+impl<'a> XmlReader<'a> {
+	fn rest(&self) -> &'a str {
+		&self.s[self.pos..]
+	}
+
+	fn skip_whitespace(&mut self) {
+		let rest = self.rest();
+		self.pos += rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len());
+	}
+
+	/// Move past the next `end`, returning what was before it.
+	fn until(&mut self, end: &str) -> Result<&'a str, XmlError> {
+		let rest = self.rest();
+		match rest.find(end) {
+			Some(i) => {
+				self.pos += i + end.len();
+				Ok(&rest[..i])
+			}
+			None => Err(XmlError::UnexpectedEnd)
+		}
+	}
+
+	/// Whitespace, comments and processing instructions around the root.
+	fn skip_misc(&mut self) -> Result<(), XmlError> {
+		loop {
+			self.skip_whitespace();
+			let rest = self.rest();
+			if rest.starts_with("<?") {
+				try!(self.until("?>"));
+			} else if rest.starts_with("<!--") {
+				try!(self.until("-->"));
+			} else if rest.starts_with("<!") {
+				return Err(XmlError::Doctype);
+			} else {
+				return Ok(());
+			}
+		}
+	}
+
+	fn name(&mut self) -> Result<&'a str, XmlError> {
+		let rest = self.rest();
+		let len = rest.find(|c: char| c.is_whitespace() || c == '/' || c == '>' || c == '=').unwrap_or(rest.len());
+		if len == 0 {
+			return Err(if rest.len() == 0 { XmlError::UnexpectedEnd } else { XmlError::Malformed });
+		}
+		self.pos += len;
+		Ok(&rest[..len])
+	}
+
+	fn expect(&mut self, s: &str) -> Result<(), XmlError> {
+		let rest = self.rest();
+		if rest.starts_with(s) {
+			self.pos += s.len();
+			Ok(())
+		} else if rest.len() < s.len() && s.starts_with(rest) {
+			Err(XmlError::UnexpectedEnd)
+		} else {
+			Err(XmlError::Malformed)
+		}
+	}
+
+	/// The element starting at `<`.
+	fn element(&mut self, depth: usize) -> Result<XmlElement, XmlError> {
+		if depth >= MAX_XML_DEPTH {
+			return Err(XmlError::TooDeep);
+		}
+
+		try!(self.expect("<"));
+		let mut element = XmlElement {
+			name: try!(self.name()).to_string(),
+			attributes: Vec::new(),
+			children: Vec::new(),
+			text: String::new()
+		};
+
+		loop {
+			self.skip_whitespace();
+			let rest = self.rest();
+			if rest.starts_with("/>") {
+				self.pos += 2;
+				return Ok(element);
+			}
+			if rest.starts_with(">") {
+				self.pos += 1;
+				break;
+			}
+
+			let name = try!(self.name());
+			self.skip_whitespace();
+			try!(self.expect("="));
+			self.skip_whitespace();
+			let quote = if self.rest().starts_with("'") { "'" } else { "\"" };
+			try!(self.expect(quote));
+			let value = try!(unescape(try!(self.until(quote))));
+			element.attributes.push((name.to_string(), value));
+		}
+
+		loop {
+			let rest = self.rest();
+			if rest.starts_with("</") {
+				self.pos += 2;
+				if try!(self.name()) != element.name {
+					return Err(XmlError::MismatchedTag);
+				}
+				self.skip_whitespace();
+				try!(self.expect(">"));
+				return Ok(element);
+			} else if rest.starts_with("<!--") {
+				try!(self.until("-->"));
+			} else if rest.starts_with("<![CDATA[") {
+				self.pos += 9;
+				element.text.push_str(try!(self.until("]]>")));
+			} else if rest.starts_with("<?") {
+				try!(self.until("?>"));
+			} else if rest.starts_with("<") {
+				element.children.push(try!(self.element(depth + 1)));
+			} else if rest.len() == 0 {
+				return Err(XmlError::UnexpectedEnd);
+			} else {
+				let len = rest.find('<').unwrap_or(rest.len());
+				self.pos += len;
+				element.text.push_str(&try!(unescape(&rest[..len])));
+			}
+		}
+	}
+}
+
+/// Resolve the predefined entities and character references.
+fn unescape(s: &str) -> Result<String, XmlError> {
+	let mut out = String::with_capacity(s.len());
+	let mut rest = s;
+	while let Some(i) = rest.find('&') {
+		out.push_str(&rest[..i]);
+		rest = &rest[i + 1..];
+		let end = try!(rest.find(';').ok_or(XmlError::InvalidEntity));
+		let entity = &rest[..end];
+		rest = &rest[end + 1..];
+
+		let c = match entity {
+			"amp" => '&',
+			"lt" => '<',
+			"gt" => '>',
+			"quot" => '"',
+			"apos" => '\'',
+			_ if entity.starts_with("#x") => try!(char_ref(&entity[2..], 16)),
+			_ if entity.starts_with("#") => try!(char_ref(&entity[1..], 10)),
+			_ => { return Err(XmlError::InvalidEntity); }
+		};
+		out.push(c);
+	}
+	out.push_str(rest);
+	Ok(out)
+}
+
+fn char_ref(digits: &str, radix: u32) -> Result<char, XmlError> {
+	u32::from_str_radix(digits, radix).ok()
+		.and_then(::core::char::from_u32)
+		.ok_or(XmlError::InvalidEntity)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	pub fn test_xml_parser() {
+		let doc = b"<?xml version=\"1.0\"?>\n<!-- a comment -->\n\
+			<s:Envelope xmlns:s='http://schemas.xmlsoap.org/soap/envelope/' a = \"1 &amp; 2\">\
+			<s:Body>\
+			<u:Set xmlns:u=\"urn:x\"><v>a &lt;b&gt; &#65;&#x42; <![CDATA[<c>]]></v><e/><e /></u:Set>\
+			</s:Body>\
+			</s:Envelope>\n";
+
+		let root = parse_xml(doc).unwrap();
+		assert_eq!(root.name, "s:Envelope");
+		assert_eq!(root.local_name(), "Envelope");
+		assert_eq!(root.attribute("a"), Some("1 & 2"));
+		assert_eq!(root.attribute("xmlns:s"), Some("http://schemas.xmlsoap.org/soap/envelope/"));
+
+		let set = root.child("Body").unwrap().child("Set").unwrap();
+		assert_eq!(set.attribute("xmlns:u"), Some("urn:x"));
+		assert_eq!(set.child("v").unwrap().text, "a <b> AB <c>");
+		assert_eq!(set.children.len(), 3);
+		assert_eq!(set.children[1].text, "");
+
+		assert_eq!(parse_xml(b""), Err(XmlError::UnexpectedEnd));
+		assert_eq!(parse_xml(b"<a><b></a>"), Err(XmlError::MismatchedTag));
+		assert_eq!(parse_xml(b"<a><b>"), Err(XmlError::UnexpectedEnd));
+		assert_eq!(parse_xml(b"<a>&bogus;</a>"), Err(XmlError::InvalidEntity));
+		assert_eq!(parse_xml(b"<a></a><b/>"), Err(XmlError::Malformed));
+		assert_eq!(parse_xml(b"<!DOCTYPE a [<!ENTITY x 'y'>]><a/>"), Err(XmlError::Doctype));
+		assert_eq!(parse_xml(b"<a x></a>"), Err(XmlError::Malformed));
+
+		let mut deep = String::new();
+		for _ in 0..MAX_XML_DEPTH + 1 { deep.push_str("<a>"); }
+		assert_eq!(parse_xml(deep.as_bytes()), Err(XmlError::TooDeep));
+
+		assert_eq!(xml_escape("<a href=\"x\">&'"), "&lt;a href=&quot;x&quot;&gt;&amp;&apos;");
+	}
+}
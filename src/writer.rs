@@ -0,0 +1,249 @@
+#![allow(deprecated, clippy::redundant_field_names)]
+
+use http::*;
+use alloc::vec::*;
+use alloc::string::String;
+use alloc::collections::BTreeMap;
+
+/// A message that can be serialized piece by piece, without first building
+/// the whole output in memory.
+pub trait HttpMessageSegments {
+	/// Calls `f` with each consecutive piece of the serialized message. Stops
+	/// early once `f` returns `false`.
+	fn for_each_segment<F: FnMut(&[u8]) -> bool>(&self, f: F);
+
+	/// Total length of the serialized message, in bytes.
+	fn serialized_len(&self) -> usize {
+		let mut len = 0;
+		self.for_each_segment(|s| { len += s.len(); true });
+		len
+	}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HttpWriteError {
+	BufferTooSmall,
+}
+
+/// Destination for serialized bytes.
+pub trait HttpSink {
+	fn write_bytes(&mut self, data: &[u8]) -> Result<(), HttpWriteError>;
+}
+
+impl HttpSink for Vec<u8> {
+	fn write_bytes(&mut self, data: &[u8]) -> Result<(), HttpWriteError> {
+		self.extend_from_slice(data);
+		Ok(())
+	}
+}
+
+/// Sink over a fixed, caller-provided buffer.
+pub struct SliceSink<'a> {
+	buf: &'a mut [u8],
+	pos: usize
+}
+
+impl<'a> SliceSink<'a> {
+	pub fn new(buf: &'a mut [u8]) -> SliceSink<'a> {
+		SliceSink {
+			buf: buf,
+			pos: 0
+		}
+	}
+
+	pub fn written(&self) -> usize {
+		self.pos
+	}
+}
+
+impl<'a> HttpSink for SliceSink<'a> {
+	fn write_bytes(&mut self, data: &[u8]) -> Result<(), HttpWriteError> {
+		if self.buf.len() - self.pos < data.len() {
+			return Err(HttpWriteError::BufferTooSmall);
+		}
+
+		self.buf[self.pos..(self.pos + data.len())].copy_from_slice(data);
+		self.pos += data.len();
+		Ok(())
+	}
+}
+
+/// Serialize the whole message into `sink`. Returns the number of bytes written.
+pub fn serialize_to<M: HttpMessageSegments, S: HttpSink>(msg: &M, sink: &mut S) -> Result<usize, HttpWriteError> {
+	let mut written = 0;
+	let mut result = Ok(());
+	msg.for_each_segment(|s| {
+		result = sink.write_bytes(s);
+		if result.is_ok() {
+			written += s.len();
+		}
+		result.is_ok()
+	});
+
+	try!(result);
+	Ok(written)
+}
+
+/// Serialize the whole message into `buf`. Fails if the buffer can't hold it.
+pub fn serialize_to_slice<M: HttpMessageSegments>(msg: &M, buf: &mut [u8]) -> Result<usize, HttpWriteError> {
+	let mut sink = SliceSink::new(buf);
+	serialize_to(msg, &mut sink)
+}
+
+/// Resumable serializer. Each call to `write_to` continues where the previous
+/// one stopped, so a small TX buffer can be filled and drained to the socket
+/// repeatedly.
+pub struct HttpMessageWriter<'a, M: 'a> {
+	msg: &'a M,
+	pos: usize,
+	len: usize
+}
+
+impl<'a, M: HttpMessageSegments> HttpMessageWriter<'a, M> {
+	pub fn new(msg: &'a M) -> HttpMessageWriter<'a, M> {
+		HttpMessageWriter::resume(msg, 0)
+	}
+
+	/// Continue a message of which the first `written` bytes were already
+	/// sent, for callers that can't keep the writer around.
+	pub fn resume(msg: &'a M, written: usize) -> HttpMessageWriter<'a, M> {
+		HttpMessageWriter {
+			msg: msg,
+			pos: written,
+			len: msg.serialized_len()
+		}
+	}
+
+	/// Fill `buf` with the next part of the message. Returns the number of
+	/// bytes written, zero once the message is complete.
+	pub fn write_to(&mut self, buf: &mut [u8]) -> usize {
+		let start = self.pos;
+		let end = start + buf.len();
+		let mut offset = 0;
+		let mut n = 0;
+
+		self.msg.for_each_segment(|s| {
+			let seg_start = offset;
+			let seg_end = offset + s.len();
+			offset = seg_end;
+
+			if seg_end <= start { return true; }
+			if seg_start >= end { return false; }
+
+			let from = start.saturating_sub(seg_start);
+			let to = if end < seg_end { end - seg_start } else { s.len() };
+			buf[n..(n + to - from)].copy_from_slice(&s[from..to]);
+			n += to - from;
+
+			true
+		});
+
+		self.pos += n;
+		n
+	}
+
+	/// Number of bytes already written.
+	pub fn written(&self) -> usize {
+		self.pos
+	}
+
+	/// Number of bytes still to be written.
+	pub fn remaining(&self) -> usize {
+		self.len - self.pos
+	}
+
+	pub fn is_complete(&self) -> bool {
+		self.pos >= self.len
+	}
+}
+
+fn u16_to_ascii(n: u16, buf: &mut [u8; 5]) -> &[u8] {
+	let mut n = n;
+	let mut i = buf.len();
+	loop {
+		i -= 1;
+		buf[i] = b'0' + (n % 10) as u8;
+		n /= 10;
+		if n == 0 { break; }
+	}
+	&buf[i..]
+}
+
+fn headers_and_body<F: FnMut(&[u8]) -> bool>(headers: &BTreeMap<String, String>, repeated: &[(String, String)], body: &[u8], f: &mut F) -> bool {
+	for (key, val) in headers.iter().chain(repeated.iter().map(|h| (&h.0, &h.1))) {
+		if !(f(key.as_bytes()) && f(b": ") && f(val.as_bytes()) && f(b"\r\n")) {
+			return false;
+		}
+	}
+
+	f(b"\r\n") && f(body)
+}
+
+impl HttpMessageSegments for HttpRequestMessage {
+	fn for_each_segment<F: FnMut(&[u8]) -> bool>(&self, f: F) {
+		let mut f = f;
+		let _ = f(self.method.as_str().as_bytes()) && f(b" ") && f(self.url.as_bytes()) &&
+		        f(b" ") && f(self.http_version.as_str().as_bytes()) && f(b"\r\n") &&
+		        headers_and_body(&self.headers, &self.repeated_headers, &self.body, &mut f);
+	}
+}
+
+impl HttpMessageSegments for HttpResponseMessage {
+	fn for_each_segment<F: FnMut(&[u8]) -> bool>(&self, f: F) {
+		let mut f = f;
+		let mut code = [0; 5];
+		let _ = f(self.http_version.as_str().as_bytes()) && f(b" ") &&
+		        f(u16_to_ascii(self.response_code, &mut code)) && f(b" ") &&
+		        f(self.response_status.as_bytes()) && f(b"\r\n") &&
+		        headers_and_body(&self.headers, &self.repeated_headers, &self.body, &mut f);
+	}
+}
+
+impl HttpMessageSegments for HttpMessage {
+	fn for_each_segment<F: FnMut(&[u8]) -> bool>(&self, f: F) {
+		match *self {
+			HttpMessage::Request(ref r) => r.for_each_segment(f),
+			HttpMessage::Response(ref r) => r.for_each_segment(f)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use alloc::string::ToString;
+
+	#[test]
+	pub fn test_resumable_writer() {
+		let mut resp = HttpResponseMessage::text_utf8("Hello World, this is a longer body.");
+		resp.response_code = 404;
+		resp.response_status = "Not Found".to_string();
+		let expected = resp.to_bytes();
+
+		let mut writer = HttpMessageWriter::new(&resp);
+		let mut out = Vec::new();
+		let mut tx = [0; 7];
+		loop {
+			let n = writer.write_to(&mut tx);
+			if n == 0 { break; }
+			out.extend_from_slice(&tx[..n]);
+		}
+
+		assert!(writer.is_complete());
+		assert_eq!(writer.written(), expected.len());
+		assert_eq!(out, expected);
+		assert!(out.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+	}
+
+	#[test]
+	pub fn test_serialize_to_slice() {
+		let req = HttpRequestMessage::new_get("/generate_204", "clients3.google.com");
+
+		let mut buf = [0; 128];
+		let n = serialize_to_slice(&req, &mut buf).unwrap();
+		assert_eq!(&buf[..n], &b"GET /generate_204 HTTP/1.1\r\nHost: clients3.google.com\r\n\r\n"[..]);
+
+		let mut small = [0; 16];
+		assert_eq!(serialize_to_slice(&req, &mut small), Err(HttpWriteError::BufferTooSmall));
+	}
+}